@@ -0,0 +1,464 @@
+use serde::Deserialize;
+use tera::{Tera, Context};
+use tokio::sync::oneshot;
+use warp::{Filter, Reply, Rejection};
+use warp::http::{StatusCode, Uri};
+use std::sync::Arc;
+use log::{info, warn};
+
+use crate::auth::{self, Role};
+use crate::cache;
+use crate::db;
+use crate::error::Error;
+use crate::handlers;
+use crate::health;
+use crate::stats;
+
+#[derive(Deserialize, Debug)]
+pub struct AddLeague {
+    pub league_id: String,
+}
+
+// Filters for the change log, blank fields are ignored
+#[derive(Deserialize, Debug, Default)]
+pub struct ChangeQuery {
+    pub user: Option<String>,
+    pub league: Option<String>,
+    pub season: Option<String>,
+    pub week: Option<String>,
+    pub table: Option<String>,
+}
+
+// How many changes to show at once
+const CHANGES_LIMIT: i64 = 500;
+
+// Runs job ("all" for every job) now, for league or every managed
+// league when it's empty
+#[derive(Deserialize, Debug)]
+pub struct Ingest {
+    pub job: String,
+    #[serde(default)]
+    pub league: String,
+}
+
+pub async fn admin_handler(session: auth::Session, db_pool: Arc<db::DBPool>, jobs: health::Jobs, tera: Arc<Tera>) -> std::result::Result<impl Reply, Rejection> {
+
+    info!("GET /admin");
+
+    let db = db::get_db_con(&db_pool)
+            .await?;
+
+    let leagues = db::get_managed_leagues(&db)
+        .await
+        .map_err(Error::Database)?;
+
+    let accounts = db::get_accounts(&db)
+        .await
+        .map_err(Error::Database)?;
+
+    let mut ctx = Context::new();
+    ctx.insert("leagues", &leagues);
+    ctx.insert("accounts", &accounts);
+    ctx.insert("jobs", &jobs.statuses());
+    ctx.insert("job_names", &stats::JOBS);
+    ctx.insert("session", &session);
+    ctx.insert("can_edit", &(session.role >= Role::Commissioner));
+    handlers::render("admin.html", ctx, tera)
+}
+
+pub async fn add_league_handler(session: auth::Session, form: AddLeague, db_pool: Arc<db::DBPool>, triggers: stats::TriggerSender) -> std::result::Result<impl Reply, Rejection> {
+
+    let league_id = form.league_id.trim().to_string();
+
+    info!("POST /admin/leagues {} by {}", league_id, session.username);
+
+    if league_id.is_empty() || !league_id.chars().all(|c| c.is_ascii_digit()) {
+        return Err(Error::BadRequest(format!("{:?} isn't a Sleeper league id", league_id)).into());
+    }
+
+    stats::lookup_league(&league_id)
+        .await?
+        .ok_or_else(|| Error::BadRequest(format!("Sleeper doesn't have a league {}", league_id)))?;
+
+    let db = db::get_db_con(&db_pool)
+            .await?;
+
+    if db::add_managed_league(&db, &league_id).await.map_err(Error::Database)? {
+        // The stats loop may be gone if ingestion isn't running, the
+        // league is still picked up whenever it starts
+        let _ = triggers.send(stats::Trigger {
+            jobs: stats::NEW_LEAGUE_JOBS.iter().map(|j| j.to_string()).collect(),
+            leagues: Some(vec![league_id]),
+            reply: None,
+        });
+    }
+
+    Ok(warp::redirect::see_other(Uri::from_static("/admin")))
+}
+
+pub async fn remove_league_handler(league_id: String, session: auth::Session, _form: auth::Empty, db_pool: Arc<db::DBPool>, cache: cache::Cache, jobs: health::Jobs) -> std::result::Result<impl Reply, Rejection> {
+
+    info!("POST /admin/leagues/{}/remove by {}", league_id, session.username);
+
+    let mut db = db::get_db_con(&db_pool)
+            .await?;
+
+    if !db::remove_managed_league(&mut db, &league_id).await.map_err(Error::Database)? {
+        return Err(Error::NotFound(String::from("league")).into());
+    }
+    jobs.remove_league(&league_id);
+    cache.invalidate();
+
+    Ok(warp::redirect::see_other(Uri::from_static("/admin")))
+}
+
+pub async fn overrides_handler(session: auth::Session, db_pool: Arc<db::DBPool>, tera: Arc<Tera>) -> std::result::Result<impl Reply, Rejection> {
+
+    info!("GET /admin/overrides");
+
+    let db = db::get_db_con(&db_pool)
+            .await?;
+
+    let overrides = db::get_overrides(&db)
+        .await
+        .map_err(Error::Database)?;
+
+    let standings = db::get_standings(&db)
+        .await
+        .map_err(Error::Database)?;
+
+    // Nothing to default the season to until state has been fetched
+    let season = db::get_time_period(&db).await.ok().map(|(season, _)| season);
+
+    let mut ctx = Context::new();
+    ctx.insert("overrides", &overrides);
+    ctx.insert("standings", &standings);
+    ctx.insert("season", &season);
+    ctx.insert("results", &db::RESULTS);
+    ctx.insert("session", &session);
+    ctx.insert("can_edit", &(session.role >= Role::Commissioner));
+    handlers::render("overrides.html", ctx, tera)
+}
+
+// Makes sure an override has what its kind needs, dropping anything
+// that doesn't apply to it
+pub fn check_override(new: db::NewOverride, teams: usize) -> Result<db::NewOverride, Error> {
+    let reason = new.reason.trim().to_string();
+    if reason.is_empty() {
+        return Err(Error::BadRequest(String::from("overrides need a reason")));
+    }
+
+    match new.kind.as_str() {
+        "points" | "result" => {
+            let (season, week) = match (new.season, new.week) {
+                (Some(season), Some(week)) if week >= 1 => (season, week),
+                _ => return Err(Error::BadRequest(format!("{} overrides need a season and week", new.kind))),
+            };
+            let (points, result) = match (new.kind.as_str(), new.points, new.result.as_deref()) {
+                ("points", Some(points), _) if points.is_finite() && points >= 0.0 => (Some(points), None),
+                ("result", _, Some(result)) if db::RESULTS.contains(&result) => (None, Some(result.to_string())),
+                ("points", _, _) => return Err(Error::BadRequest(String::from("points overrides need points"))),
+                _ => return Err(Error::BadRequest(format!("result overrides need one of {}", db::RESULTS.join(", ")))),
+            };
+            Ok(db::NewOverride { season: Some(season), week: Some(week), points, result, seed: None, reason, ..new })
+        },
+        "seed" => match new.seed {
+            Some(seed) if seed >= 1 && seed as usize <= teams => {
+                Ok(db::NewOverride { season: None, week: None, points: None, result: None, seed: Some(seed), reason, ..new })
+            },
+            _ => Err(Error::BadRequest(format!("seeds go from 1 to {}", teams))),
+        },
+        kind => Err(Error::BadRequest(format!("unknown override {}", kind))),
+    }
+}
+
+pub async fn add_override_handler(session: auth::Session, form: db::NewOverride, db_pool: Arc<db::DBPool>, cache: cache::Cache) -> std::result::Result<impl Reply, Rejection> {
+
+    info!("POST /admin/overrides {} {} by {}", form.kind, form.user_id, session.username);
+
+    let mut db = db::get_db_con(&db_pool)
+            .await?;
+
+    let standings = db::get_standings(&db)
+        .await
+        .map_err(Error::Database)?;
+    if !standings.iter().any(|s| s.user.id == form.user_id) {
+        return Err(Error::BadRequest(format!("{} isn't in any league", form.user_id)).into());
+    }
+
+    let new = check_override(form, standings.len())?;
+    let id = db::add_override(&mut db, &new, &session.username)
+        .await
+        .map_err(Error::Database)?;
+    info!("added {} override {} for {}: {}", new.kind, id, new.user_id, new.reason);
+    cache.invalidate();
+
+    Ok(warp::redirect::see_other(Uri::from_static("/admin/overrides")))
+}
+
+pub async fn remove_override_handler(id: i32, session: auth::Session, _form: auth::Empty, db_pool: Arc<db::DBPool>, cache: cache::Cache) -> std::result::Result<impl Reply, Rejection> {
+
+    info!("POST /admin/overrides/{}/remove by {}", id, session.username);
+
+    let db = db::get_db_con(&db_pool)
+            .await?;
+
+    if !db::remove_override(&db, id, &session.username).await.map_err(Error::Database)? {
+        return Err(Error::NotFound(String::from("override")).into());
+    }
+    cache.invalidate();
+
+    Ok(warp::redirect::see_other(Uri::from_static("/admin/overrides")))
+}
+
+pub fn change_filter(query: ChangeQuery) -> Result<db::ChangeFilter, Error> {
+    let set = |field: Option<String>| field.map(|f| f.trim().to_string()).filter(|f| !f.is_empty());
+    let number = |name: &str, field: Option<String>| match set(field) {
+        Some(f) => f.parse::<i32>().map(Some).map_err(|_| Error::BadRequest(format!("{} has to be a number", name))),
+        None => Ok(None),
+    };
+    Ok(db::ChangeFilter {
+        user_id: set(query.user),
+        league_id: set(query.league),
+        season: number("season", query.season)?,
+        week: number("week", query.week)?,
+        table: set(query.table),
+    })
+}
+
+pub async fn changes_handler(session: auth::Session, query: ChangeQuery, db_pool: Arc<db::DBPool>, tera: Arc<Tera>) -> std::result::Result<impl Reply, Rejection> {
+
+    info!("GET /admin/changes");
+
+    let filter = change_filter(query)?;
+
+    let db = db::get_db_con(&db_pool)
+            .await?;
+
+    let changes = db::get_changes(&db, &filter, CHANGES_LIMIT).await?;
+
+    let standings = db::get_standings(&db)
+        .await
+        .map_err(Error::Database)?;
+
+    let leagues = db::get_managed_leagues(&db)
+        .await
+        .map_err(Error::Database)?;
+
+    let mut ctx = Context::new();
+    ctx.insert("changes", &changes);
+    ctx.insert("filter", &filter);
+    ctx.insert("limit", &CHANGES_LIMIT);
+    ctx.insert("standings", &standings);
+    ctx.insert("leagues", &leagues);
+    ctx.insert("tables", &db::LOGGED_TABLES);
+    ctx.insert("session", &session);
+    handlers::render("changes.html", ctx, tera)
+}
+
+pub async fn ingest_handler(
+    session: auth::Session,
+    form: Ingest,
+    accept: Option<String>,
+    db_pool: Arc<db::DBPool>,
+    triggers: stats::TriggerSender,
+    tera: Arc<Tera>,
+) -> std::result::Result<warp::reply::Response, Rejection> {
+
+    let league_id = form.league.trim().to_string();
+
+    info!("POST /admin/ingest {} {} by {}", form.job, league_id, session.username);
+
+    let to_run: Vec<String> = match form.job.as_str() {
+        "all" => stats::JOBS.iter().map(|j| j.to_string()).collect(),
+        job if stats::JOBS.contains(&job) => vec![job.to_string()],
+        job => return Err(Error::BadRequest(format!("unknown job {}", job)).into()),
+    };
+    // Only leagues in the big league can be fetched, anything else
+    // would show up in the standings
+    let leagues = match league_id.as_str() {
+        "" => None,
+        l => {
+            let db = db::get_db_con(&db_pool)
+                    .await?;
+            let managed = db::get_managed_leagues(&db)
+                .await
+                .map_err(Error::Database)?;
+            if !managed.iter().any(|m| m.id == l) {
+                return Err(Error::BadRequest(format!("{:?} isn't one of the big league's leagues", l)).into());
+            }
+            Some(vec![league_id.clone()])
+        },
+    };
+
+    // Runs go through the stats loop so they never overlap with
+    // the scheduled ones
+    let (reply, results) = oneshot::channel();
+    triggers
+        .send(stats::Trigger { jobs: to_run, leagues, reply: Some(reply) })
+        .map_err(|_| Error::Unavailable(String::from("ingestion")))?;
+    let results = results
+        .await
+        .map_err(|_| Error::Unavailable(String::from("ingestion")))?;
+
+    let failed = results.iter().filter(|r| r.error.is_some()).count();
+    if failed > 0 {
+        warn!("{} of {} jobs requested by {} failed", failed, results.len(), session.username);
+    } else {
+        info!("{} jobs requested by {} succeeded", results.len(), session.username);
+    }
+    let status = match failed {
+        0 => StatusCode::OK,
+        _ => StatusCode::BAD_GATEWAY,
+    };
+
+    if accept.is_some_and(|a| a.contains("application/json")) {
+        return Ok(warp::reply::with_status(warp::reply::json(&results), status).into_response());
+    }
+
+    let mut ctx = Context::new();
+    ctx.insert("results", &results);
+    ctx.insert("failed", &failed);
+    let page = handlers::render("ingest.html", ctx, tera)?;
+    Ok(warp::reply::with_status(page, status).into_response())
+}
+
+pub fn routes(
+    db_pool: Arc<db::DBPool>,
+    tera: Arc<Tera>,
+    triggers: stats::TriggerSender,
+    cache: cache::Cache,
+    jobs: health::Jobs,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+
+    let admin_tera = tera.clone();
+    let admin_route = warp::get()
+        .and(warp::path!("admin"))
+        .and(auth::require(Role::Viewer, db_pool.clone()))
+        .and(db::with_db(db_pool.clone()))
+        .and(health::with_jobs(jobs.clone()))
+        .and(warp::any().map(move || admin_tera.clone()))
+        .and_then(admin_handler);
+
+    let add_league_route = warp::post()
+        .and(warp::path!("admin" / "leagues"))
+        .and(auth::form::<AddLeague>(Role::Commissioner, db_pool.clone()))
+        .and(db::with_db(db_pool.clone()))
+        .and(stats::with_triggers(triggers.clone()))
+        .and_then(add_league_handler);
+
+    let remove_league_route = warp::post()
+        .and(warp::path!("admin" / "leagues" / String / "remove"))
+        .and(auth::form::<auth::Empty>(Role::Commissioner, db_pool.clone()))
+        .and(db::with_db(db_pool.clone()))
+        .and(cache::with_cache(cache.clone()))
+        .and(health::with_jobs(jobs))
+        .and_then(remove_league_handler);
+
+    let overrides_tera = tera.clone();
+    let overrides_route = warp::get()
+        .and(warp::path!("admin" / "overrides"))
+        .and(auth::require(Role::Viewer, db_pool.clone()))
+        .and(db::with_db(db_pool.clone()))
+        .and(warp::any().map(move || overrides_tera.clone()))
+        .and_then(overrides_handler);
+
+    let add_override_route = warp::post()
+        .and(warp::path!("admin" / "overrides"))
+        .and(auth::form::<db::NewOverride>(Role::Commissioner, db_pool.clone()))
+        .and(db::with_db(db_pool.clone()))
+        .and(cache::with_cache(cache.clone()))
+        .and_then(add_override_handler);
+
+    let remove_override_route = warp::post()
+        .and(warp::path!("admin" / "overrides" / i32 / "remove"))
+        .and(auth::form::<auth::Empty>(Role::Commissioner, db_pool.clone()))
+        .and(db::with_db(db_pool.clone()))
+        .and(cache::with_cache(cache))
+        .and_then(remove_override_handler);
+
+    let changes_tera = tera.clone();
+    let changes_route = warp::get()
+        .and(warp::path!("admin" / "changes"))
+        .and(auth::require(Role::Viewer, db_pool.clone()))
+        .and(warp::query::<ChangeQuery>())
+        .and(db::with_db(db_pool.clone()))
+        .and(warp::any().map(move || changes_tera.clone()))
+        .and_then(changes_handler);
+
+    let ingest_route = warp::post()
+        .and(warp::path!("admin" / "ingest"))
+        .and(auth::form::<Ingest>(Role::Commissioner, db_pool.clone()))
+        .and(warp::header::optional::<String>("accept"))
+        .and(db::with_db(db_pool))
+        .and(stats::with_triggers(triggers))
+        .and(warp::any().map(move || tera.clone()))
+        .and_then(ingest_handler);
+
+    admin_route
+        .or(add_league_route)
+        .or(remove_league_route)
+        .or(overrides_route)
+        .or(add_override_route)
+        .or(remove_override_route)
+        .or(changes_route)
+        .or(ingest_route)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::admin;
+    use crate::db::{self, NewOverride};
+
+    #[test]
+    fn test_change_filter() {
+        let filter = admin::change_filter(admin::ChangeQuery {
+            user: Some(String::from("1234")),
+            league: Some(String::from("")),
+            season: Some(String::from(" 2023 ")),
+            week: None,
+            table: Some(String::from("matchups")),
+        }).unwrap();
+        assert_eq!(filter, db::ChangeFilter {
+            user_id: Some(String::from("1234")),
+            league_id: None,
+            season: Some(2023),
+            week: None,
+            table: Some(String::from("matchups")),
+        });
+
+        assert!(admin::change_filter(admin::ChangeQuery { week: Some(String::from("last")), ..Default::default() }).is_err());
+    }
+
+    #[test]
+    fn test_check_override() {
+        let points = NewOverride {
+            kind: String::from("points"),
+            user_id: String::from("1"),
+            season: Some(2023),
+            week: Some(3),
+            points: Some(101.5),
+            result: Some(String::from("win")),
+            seed: Some(2),
+            reason: String::from(" stat correction "),
+        };
+        assert_eq!(
+            admin::check_override(points.clone(), 12).unwrap(),
+            NewOverride { result: None, seed: None, reason: String::from("stat correction"), ..points.clone() }
+        );
+        assert!(admin::check_override(NewOverride { week: None, ..points.clone() }, 12).is_err());
+        assert!(admin::check_override(NewOverride { reason: String::from(" "), ..points.clone() }, 12).is_err());
+
+        let result = NewOverride { kind: String::from("result"), result: Some(String::from("forfeit")), ..points.clone() };
+        assert!(admin::check_override(result.clone(), 12).is_err());
+        assert_eq!(
+            admin::check_override(NewOverride { result: Some(String::from("loss")), ..result }, 12).unwrap().points,
+            None
+        );
+
+        let seed = NewOverride { kind: String::from("seed"), season: None, week: None, ..points };
+        assert_eq!(admin::check_override(seed.clone(), 12).unwrap().seed, Some(2));
+        assert!(admin::check_override(NewOverride { seed: Some(13), ..seed.clone() }, 12).is_err());
+        assert!(admin::check_override(NewOverride { kind: String::from("bonus"), ..seed }, 12).is_err());
+    }
+}
@@ -0,0 +1,937 @@
+use serde::{Serialize, Deserialize};
+use warp::{Filter, Reply, Rejection};
+use warp::http::StatusCode;
+use std::convert::Infallible;
+use std::sync::Arc;
+use utoipa::{IntoParams, OpenApi, ToSchema};
+use log::info;
+
+use crate::db;
+use crate::config;
+use crate::grades;
+use crate::lineups;
+use crate::settings;
+use crate::handlers;
+use crate::trades;
+use crate::reload;
+use crate::error::{self, Error};
+
+const DEFAULT_PER_PAGE: usize = 50;
+const MAX_PER_PAGE: usize = 500;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct Pagination {
+    pub page: Option<usize>,
+    pub per_page: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct MatchupsQuery {
+    /// Defaults to the current week
+    pub week: Option<i32>,
+    pub page: Option<usize>,
+    pub per_page: Option<usize>,
+}
+
+impl MatchupsQuery {
+    fn pagination(&self) -> Pagination {
+        Pagination {
+            page: self.page,
+            per_page: self.per_page,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct WireQuery {
+    /// trade, waiver, free_agent or commissioner
+    pub kind: Option<String>,
+    /// Only transactions that moved a player at this position
+    pub position: Option<String>,
+    /// Defaults to the whole season
+    pub week: Option<i32>,
+    pub page: Option<usize>,
+    pub per_page: Option<usize>,
+}
+
+impl WireQuery {
+    fn pagination(&self) -> Pagination {
+        Pagination {
+            page: self.page,
+            per_page: self.per_page,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct WirePlayersQuery {
+    /// Defaults to the current week
+    pub week: Option<i32>,
+    pub position: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DraftsQuery {
+    /// Defaults to the current season
+    pub season: Option<i32>,
+    pub page: Option<usize>,
+    pub per_page: Option<usize>,
+}
+
+impl DraftsQuery {
+    fn pagination(&self) -> Pagination {
+        Pagination {
+            page: self.page,
+            per_page: self.per_page,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct TradesQuery {
+    /// Defaults to the current season
+    pub season: Option<i32>,
+    /// Only trades in this league
+    pub league: Option<String>,
+    /// Only trades this user was part of
+    pub user: Option<String>,
+    pub page: Option<usize>,
+    pub per_page: Option<usize>,
+}
+
+impl TradesQuery {
+    fn pagination(&self) -> Pagination {
+        Pagination {
+            page: self.page,
+            per_page: self.per_page,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct WirePlayers {
+    /// Missing until the current season has been fetched
+    pub season: Option<i32>,
+    pub week: Option<i32>,
+    pub most_added: Vec<db::PlayerMoves>,
+    pub most_dropped: Vec<db::PlayerMoves>,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+#[aliases(StandingsPage = Page<db::Standing>, MatchupsPage = Page<db::Week>, TransactionsPage = Page<db::Transaction>, DraftsPage = Page<db::Draft>, AdpPage = Page<db::Adp>, GradesPage = Page<grades::DraftGrade>, TradesPage = Page<trades::Trade>)]
+pub struct Page<T> {
+    pub data: Vec<T>,
+    pub page: usize,
+    pub per_page: usize,
+    pub total: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct LeagueDetail {
+    pub league: db::League,
+    pub standings: Vec<db::Standing>,
+    /// Each user's lineups this season against their optimal lineups
+    pub lineups: Vec<lineups::LineupSummary>,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct UserDetail {
+    pub user: db::User,
+    pub roster: db::Roster,
+    pub players: Vec<db::Player>,
+    pub matchups: Vec<db::Week>,
+    /// None until the user's draft has picks
+    pub draft_grade: Option<grades::DraftGrade>,
+    /// Each finished week's lineup against the optimal lineup
+    pub lineups: Vec<lineups::WeekLineup>,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct ErrorBody {
+    pub status: u16,
+    pub message: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct ApiError {
+    pub error: ErrorBody,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, message: &str) -> ApiError {
+        ApiError {
+            error: ErrorBody {
+                status: status.as_u16(),
+                message: message.to_string(),
+            },
+        }
+    }
+}
+
+impl Pagination {
+    pub fn paginate<T>(&self, items: Vec<T>) -> Result<Page<T>, Error> {
+        let page = self.page.unwrap_or(1);
+        let per_page = self.per_page.unwrap_or(DEFAULT_PER_PAGE);
+
+        if page == 0 {
+            return Err(Error::BadRequest(String::from("page starts at 1")));
+        }
+        if per_page == 0 || per_page > MAX_PER_PAGE {
+            return Err(Error::BadRequest(
+                format!("per_page must be between 1 and {}", MAX_PER_PAGE)
+            ));
+        }
+
+        let total = items.len();
+        let data = items
+            .into_iter()
+            .skip((page - 1) * per_page)
+            .take(per_page)
+            .collect();
+
+        Ok(Page {
+            data,
+            page,
+            per_page,
+            total,
+        })
+    }
+}
+
+pub async fn handle_rejection(err: Rejection) -> std::result::Result<impl Reply, Infallible> {
+    let (status, message) = error::describe_rejection(&err);
+    Ok(warp::reply::with_status(warp::reply::json(&ApiError::new(status, &message)), status))
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "bigleague", description = "Standings, matchups and playoffs for a big league built from Sleeper leagues"),
+    paths(
+        standings_handler,
+        league_handler,
+        user_handler,
+        bracket_handler,
+        matchups_handler,
+        league_transactions_handler,
+        user_transactions_handler,
+        wire_handler,
+        wire_players_handler,
+        drafts_handler,
+        adp_handler,
+        grades_handler,
+        trades_handler,
+        settings_handler,
+    ),
+    components(schemas(
+        db::Standing,
+        db::League,
+        db::Roster,
+        db::User,
+        db::Player,
+        db::Week,
+        db::Bracket,
+        db::PlayoffTeam,
+        db::Transaction,
+        db::TransactionPlayer,
+        db::TransactionPick,
+        db::PlayerMoves,
+        db::Draft,
+        db::DraftPick,
+        db::Adp,
+        db::ScoredPick,
+        grades::PickValue,
+        grades::DraftGrade,
+        lineups::WeekLineup,
+        lineups::LineupSummary,
+        trades::TradedPlayer,
+        trades::TradeSide,
+        trades::Trade,
+        db::LeagueSettings,
+        settings::SettingRow,
+        settings::Comparison,
+        StandingsPage,
+        MatchupsPage,
+        TransactionsPage,
+        DraftsPage,
+        AdpPage,
+        GradesPage,
+        TradesPage,
+        LeagueDetail,
+        UserDetail,
+        WirePlayers,
+        ApiError,
+        ErrorBody,
+    )),
+)]
+pub struct ApiDoc;
+
+// The path filters are kept separate from the handlers so the
+// OpenAPI spec can be checked against them without a database.
+// They are all relative to the /api prefix added in `routes`.
+pub fn standings_path() -> impl Filter<Extract = (), Error = Rejection> + Copy {
+    warp::path!("v1" / "standings")
+}
+
+pub fn league_path() -> impl Filter<Extract = (String,), Error = Rejection> + Copy {
+    warp::path!("v1" / "leagues" / String)
+}
+
+pub fn user_path() -> impl Filter<Extract = (String,), Error = Rejection> + Copy {
+    warp::path!("v1" / "users" / String)
+}
+
+pub fn bracket_path() -> impl Filter<Extract = (), Error = Rejection> + Copy {
+    warp::path!("v1" / "bracket")
+}
+
+pub fn matchups_path() -> impl Filter<Extract = (), Error = Rejection> + Copy {
+    warp::path!("v1" / "matchups")
+}
+
+pub fn league_transactions_path() -> impl Filter<Extract = (String,), Error = Rejection> + Copy {
+    warp::path!("v1" / "leagues" / String / "transactions")
+}
+
+pub fn user_transactions_path() -> impl Filter<Extract = (String,), Error = Rejection> + Copy {
+    warp::path!("v1" / "users" / String / "transactions")
+}
+
+pub fn wire_path() -> impl Filter<Extract = (), Error = Rejection> + Copy {
+    warp::path!("v1" / "wire")
+}
+
+pub fn wire_players_path() -> impl Filter<Extract = (), Error = Rejection> + Copy {
+    warp::path!("v1" / "wire" / "players")
+}
+
+pub fn drafts_path() -> impl Filter<Extract = (), Error = Rejection> + Copy {
+    warp::path!("v1" / "drafts")
+}
+
+pub fn adp_path() -> impl Filter<Extract = (), Error = Rejection> + Copy {
+    warp::path!("v1" / "drafts" / "adp")
+}
+
+pub fn grades_path() -> impl Filter<Extract = (), Error = Rejection> + Copy {
+    warp::path!("v1" / "drafts" / "grades")
+}
+
+pub fn trades_path() -> impl Filter<Extract = (), Error = Rejection> + Copy {
+    warp::path!("v1" / "trades")
+}
+
+pub fn settings_path() -> impl Filter<Extract = (), Error = Rejection> + Copy {
+    warp::path!("v1" / "settings")
+}
+
+pub fn routes(db_pool: Arc<db::DBPool>, config: reload::ConfigReceiver) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+
+    let openapi_route = warp::path!("openapi.json")
+        .and_then(openapi_handler);
+
+    let standings_route = standings_path()
+        .and(warp::query::<Pagination>())
+        .and(db::with_db(db_pool.clone()))
+        .and_then(standings_handler);
+
+    let league_route = league_path()
+        .and(db::with_db(db_pool.clone()))
+        .and_then(league_handler);
+
+    let user_route = user_path()
+        .and(db::with_db(db_pool.clone()))
+        .and_then(user_handler);
+
+    let bracket_route = bracket_path()
+        .and(db::with_db(db_pool.clone()))
+        .and(reload::with_config(config))
+        .and_then(bracket_handler);
+
+    let matchups_route = matchups_path()
+        .and(warp::query::<MatchupsQuery>())
+        .and(db::with_db(db_pool.clone()))
+        .and_then(matchups_handler);
+
+    let league_transactions_route = league_transactions_path()
+        .and(warp::query::<Pagination>())
+        .and(db::with_db(db_pool.clone()))
+        .and_then(league_transactions_handler);
+
+    let user_transactions_route = user_transactions_path()
+        .and(warp::query::<Pagination>())
+        .and(db::with_db(db_pool.clone()))
+        .and_then(user_transactions_handler);
+
+    let wire_route = wire_path()
+        .and(warp::query::<WireQuery>())
+        .and(db::with_db(db_pool.clone()))
+        .and_then(wire_handler);
+
+    let wire_players_route = wire_players_path()
+        .and(warp::query::<WirePlayersQuery>())
+        .and(db::with_db(db_pool.clone()))
+        .and_then(wire_players_handler);
+
+    let drafts_route = drafts_path()
+        .and(warp::query::<DraftsQuery>())
+        .and(db::with_db(db_pool.clone()))
+        .and_then(drafts_handler);
+
+    let adp_route = adp_path()
+        .and(warp::query::<DraftsQuery>())
+        .and(db::with_db(db_pool.clone()))
+        .and_then(adp_handler);
+
+    let grades_route = grades_path()
+        .and(warp::query::<DraftsQuery>())
+        .and(db::with_db(db_pool.clone()))
+        .and_then(grades_handler);
+
+    let trades_route = trades_path()
+        .and(warp::query::<TradesQuery>())
+        .and(db::with_db(db_pool.clone()))
+        .and_then(trades_handler);
+
+    let settings_route = settings_path()
+        .and(db::with_db(db_pool))
+        .and_then(settings_handler);
+
+    // Everything under /api is answered here, including errors, so
+    // API clients always get JSON back.
+    warp::path("api").and(
+        openapi_route
+            .or(standings_route)
+            .or(league_route)
+            .or(user_route)
+            .or(bracket_route)
+            .or(matchups_route)
+            .or(league_transactions_route)
+            .or(user_transactions_route)
+            .or(wire_route)
+            .or(wire_players_route)
+            .or(drafts_route)
+            .or(adp_route)
+            .or(grades_route)
+            .or(trades_route)
+            .or(settings_route)
+            .recover(handle_rejection)
+    )
+}
+
+pub async fn openapi_handler() -> std::result::Result<impl Reply, Rejection> {
+
+    info!("GET /api/openapi.json");
+
+    Ok(warp::reply::json(&ApiDoc::openapi()))
+}
+
+/// Combined standings across every league
+#[utoipa::path(
+    get,
+    path = "/api/v1/standings",
+    params(Pagination),
+    responses(
+        (status = 200, description = "Standings ordered by rank", body = StandingsPage),
+        (status = 400, description = "Invalid pagination", body = ApiError),
+        (status = 500, description = "Internal error", body = ApiError),
+    ),
+)]
+pub async fn standings_handler(pagination: Pagination, db_pool: Arc<db::DBPool>) -> std::result::Result<impl Reply, Rejection> {
+
+    info!("GET /api/v1/standings");
+
+    let db = db::get_db_con(&db_pool)
+            .await?;
+
+    let standings = db::get_standings(&db)
+        .await
+        .map_err(Error::Database)?;
+
+    Ok(warp::reply::json(&pagination.paginate(standings)?))
+}
+
+/// A single league and its standings
+#[utoipa::path(
+    get,
+    path = "/api/v1/leagues/{id}",
+    params(("id" = String, Path, description = "Sleeper league id")),
+    responses(
+        (status = 200, description = "League with standings", body = LeagueDetail),
+        (status = 404, description = "Unknown league", body = ApiError),
+        (status = 500, description = "Internal error", body = ApiError),
+    ),
+)]
+pub async fn league_handler(id: String, db_pool: Arc<db::DBPool>) -> std::result::Result<impl Reply, Rejection> {
+
+    info!("GET /api/v1/leagues/{}", id);
+
+    let db = db::get_db_con(&db_pool)
+            .await?;
+
+    let league = db::get_league(&db, &id)
+        .await
+        .map_err(Error::Database)?
+        .ok_or_else(|| Error::NotFound(String::from("league")))?;
+
+    let standings = db::get_league_standings(&db, &id)
+        .await
+        .map_err(Error::Database)?;
+
+    let weeks = lineups::get_current_lineups(&db, &id)
+        .await
+        .map_err(Error::Database)?;
+    let lineups = lineups::summarize(&weeks);
+
+    Ok(warp::reply::json(&LeagueDetail { league, standings, lineups }))
+}
+
+/// A user's roster, current lineup and matchups
+#[utoipa::path(
+    get,
+    path = "/api/v1/users/{id}",
+    params(("id" = String, Path, description = "Sleeper user id")),
+    responses(
+        (status = 200, description = "User details", body = UserDetail),
+        (status = 404, description = "Unknown user", body = ApiError),
+        (status = 500, description = "Internal error", body = ApiError),
+    ),
+)]
+pub async fn user_handler(id: String, db_pool: Arc<db::DBPool>) -> std::result::Result<impl Reply, Rejection> {
+
+    info!("GET /api/v1/users/{}", id);
+
+    let db = db::get_db_con(&db_pool)
+            .await?;
+
+    let (user, roster) = db::get_user(&db, &id)
+        .await
+        .map_err(Error::Database)?
+        .ok_or_else(|| Error::NotFound(String::from("user")))?;
+
+    let (season, week) = db::get_time_period(&db)
+        .await?;
+
+    let players = db::get_players(&db, &id, &roster.league_id, season, week)
+        .await
+        .map_err(Error::Database)?;
+
+    let matchups = db::get_user_matchups(&db, &id, season)
+        .await
+        .map_err(Error::Database)?;
+
+    let draft_grade = grades::get_grades(&db, season)
+        .await
+        .map_err(Error::Database)?
+        .into_iter()
+        .find(|g| g.user_id == id);
+
+    let lineups = lineups::get_league_lineups(&db, &roster.league_id, season)
+        .await
+        .map_err(Error::Database)?
+        .into_iter()
+        .filter(|l| l.user_id == id)
+        .collect();
+
+    Ok(warp::reply::json(&UserDetail { user, roster, players, matchups, draft_grade, lineups }))
+}
+
+/// The playoff bracket
+#[utoipa::path(
+    get,
+    path = "/api/v1/bracket",
+    responses(
+        (status = 200, description = "Playoff bracket", body = db::Bracket),
+        (status = 500, description = "Internal error", body = ApiError),
+    ),
+)]
+pub async fn bracket_handler(db_pool: Arc<db::DBPool>, config: config::Config) -> std::result::Result<impl Reply, Rejection> {
+
+    info!("GET /api/v1/bracket");
+
+    let db = db::get_db_con(&db_pool)
+            .await?;
+
+    let bracket = db::get_bracket(db, config)
+        .await?;
+
+    Ok(warp::reply::json(&bracket))
+}
+
+/// Every matchup for a week
+#[utoipa::path(
+    get,
+    path = "/api/v1/matchups",
+    params(MatchupsQuery),
+    responses(
+        (status = 200, description = "Matchups for the week", body = MatchupsPage),
+        (status = 400, description = "Invalid pagination", body = ApiError),
+        (status = 500, description = "Internal error", body = ApiError),
+    ),
+)]
+pub async fn matchups_handler(query: MatchupsQuery, db_pool: Arc<db::DBPool>) -> std::result::Result<impl Reply, Rejection> {
+
+    info!("GET /api/v1/matchups?week={:?}", query.week);
+
+    let db = db::get_db_con(&db_pool)
+            .await?;
+
+    let (season, current_week) = db::get_time_period(&db)
+        .await?;
+
+    let matchups = db::get_week_matchups(&db, season, query.week.unwrap_or(current_week))
+        .await
+        .map_err(Error::Database)?;
+
+    Ok(warp::reply::json(&query.pagination().paginate(matchups)?))
+}
+
+/// A league's trades, waivers and free agent moves
+#[utoipa::path(
+    get,
+    path = "/api/v1/leagues/{id}/transactions",
+    params(("id" = String, Path, description = "Sleeper league id"), Pagination),
+    responses(
+        (status = 200, description = "Completed transactions, newest first", body = TransactionsPage),
+        (status = 400, description = "Invalid pagination", body = ApiError),
+        (status = 500, description = "Internal error", body = ApiError),
+    ),
+)]
+pub async fn league_transactions_handler(id: String, pagination: Pagination, db_pool: Arc<db::DBPool>) -> std::result::Result<impl Reply, Rejection> {
+
+    info!("GET /api/v1/leagues/{}/transactions", id);
+
+    let db = db::get_db_con(&db_pool)
+            .await?;
+
+    let filter = db::TransactionFilter { league_id: Some(id), ..Default::default() };
+    let transactions = db::get_transactions(&db, &filter, None)
+        .await
+        .map_err(Error::Database)?;
+
+    Ok(warp::reply::json(&pagination.paginate(transactions)?))
+}
+
+/// Every transaction a user was part of
+#[utoipa::path(
+    get,
+    path = "/api/v1/users/{id}/transactions",
+    params(("id" = String, Path, description = "Sleeper user id"), Pagination),
+    responses(
+        (status = 200, description = "Completed transactions, newest first", body = TransactionsPage),
+        (status = 400, description = "Invalid pagination", body = ApiError),
+        (status = 500, description = "Internal error", body = ApiError),
+    ),
+)]
+pub async fn user_transactions_handler(id: String, pagination: Pagination, db_pool: Arc<db::DBPool>) -> std::result::Result<impl Reply, Rejection> {
+
+    info!("GET /api/v1/users/{}/transactions", id);
+
+    let db = db::get_db_con(&db_pool)
+            .await?;
+
+    let filter = db::TransactionFilter { user_id: Some(id), ..Default::default() };
+    let transactions = db::get_transactions(&db, &filter, None)
+        .await
+        .map_err(Error::Database)?;
+
+    Ok(warp::reply::json(&pagination.paginate(transactions)?))
+}
+
+/// Every league's transactions this season in one feed
+#[utoipa::path(
+    get,
+    path = "/api/v1/wire",
+    params(WireQuery),
+    responses(
+        (status = 200, description = "Completed transactions, newest first", body = TransactionsPage),
+        (status = 400, description = "Invalid filter or pagination", body = ApiError),
+        (status = 500, description = "Internal error", body = ApiError),
+    ),
+)]
+pub async fn wire_handler(query: WireQuery, db_pool: Arc<db::DBPool>) -> std::result::Result<impl Reply, Rejection> {
+
+    info!("GET /api/v1/wire?kind={:?}&position={:?}&week={:?}", query.kind, query.position, query.week);
+
+    let db = db::get_db_con(&db_pool)
+            .await?;
+
+    let period = db::get_time_period_if_fetched(&db)
+        .await
+        .map_err(Error::Database)?;
+
+    let filter = handlers::wire_filter(period.map(|(season, _)| season), query.kind.clone(), query.position.clone(), query.week)?;
+    let transactions = match period {
+        Some(_) => db::get_transactions(&db, &filter, None)
+            .await
+            .map_err(Error::Database)?,
+        // Nothing has been ingested until the state is fetched
+        None => vec![],
+    };
+
+    Ok(warp::reply::json(&query.pagination().paginate(transactions)?))
+}
+
+/// The players added and dropped the most across every league in a week
+#[utoipa::path(
+    get,
+    path = "/api/v1/wire/players",
+    params(WirePlayersQuery),
+    responses(
+        (status = 200, description = "Most added and dropped players", body = WirePlayers),
+        (status = 400, description = "Invalid week", body = ApiError),
+        (status = 500, description = "Internal error", body = ApiError),
+    ),
+)]
+pub async fn wire_players_handler(query: WirePlayersQuery, db_pool: Arc<db::DBPool>) -> std::result::Result<impl Reply, Rejection> {
+
+    info!("GET /api/v1/wire/players?week={:?}&position={:?}", query.week, query.position);
+
+    let db = db::get_db_con(&db_pool)
+            .await?;
+
+    let period = db::get_time_period_if_fetched(&db)
+        .await
+        .map_err(Error::Database)?;
+
+    let week = query.week.or(period.map(|(_, current_week)| current_week));
+    let filter = handlers::wire_filter(period.map(|(season, _)| season), None, query.position, week)?;
+
+    let (most_added, most_dropped) = match (filter.season, filter.week) {
+        (Some(season), Some(week)) => {
+            let most_added = db::get_most_moved(&db, season, week, "add", filter.position.as_deref(), handlers::MOST_MOVED)
+                .await
+                .map_err(Error::Database)?;
+            let most_dropped = db::get_most_moved(&db, season, week, "drop", filter.position.as_deref(), handlers::MOST_MOVED)
+                .await
+                .map_err(Error::Database)?;
+            (most_added, most_dropped)
+        },
+        // Nothing has been ingested until the state is fetched
+        _ => (vec![], vec![]),
+    };
+
+    Ok(warp::reply::json(&WirePlayers { season: filter.season, week: filter.week, most_added, most_dropped }))
+}
+
+// None when no season was asked for and the state hasn't been
+// fetched yet, which leaves nothing to show
+async fn season_or_current(db: &db::DBCon, season: Option<i32>) -> Result<Option<i32>, Error> {
+    match season {
+        Some(s) => Ok(Some(s)),
+        None => Ok(db::get_time_period_if_fetched(db).await.map_err(Error::Database)?.map(|(season, _)| season)),
+    }
+}
+
+/// Every league's draft board for a season
+#[utoipa::path(
+    get,
+    path = "/api/v1/drafts",
+    params(DraftsQuery),
+    responses(
+        (status = 200, description = "Drafts with their picks in order", body = DraftsPage),
+        (status = 400, description = "Invalid pagination", body = ApiError),
+        (status = 500, description = "Internal error", body = ApiError),
+    ),
+)]
+pub async fn drafts_handler(query: DraftsQuery, db_pool: Arc<db::DBPool>) -> std::result::Result<impl Reply, Rejection> {
+
+    info!("GET /api/v1/drafts?season={:?}", query.season);
+
+    let db = db::get_db_con(&db_pool)
+            .await?;
+
+    let drafts = match season_or_current(&db, query.season).await? {
+        Some(season) => db::get_drafts(&db, season, None)
+            .await
+            .map_err(Error::Database)?,
+        None => vec![],
+    };
+
+    Ok(warp::reply::json(&query.pagination().paginate(drafts)?))
+}
+
+/// Average draft position across every league for a season
+#[utoipa::path(
+    get,
+    path = "/api/v1/drafts/adp",
+    params(DraftsQuery),
+    responses(
+        (status = 200, description = "Players by average draft position", body = AdpPage),
+        (status = 400, description = "Invalid pagination", body = ApiError),
+        (status = 500, description = "Internal error", body = ApiError),
+    ),
+)]
+pub async fn adp_handler(query: DraftsQuery, db_pool: Arc<db::DBPool>) -> std::result::Result<impl Reply, Rejection> {
+
+    info!("GET /api/v1/drafts/adp?season={:?}", query.season);
+
+    let db = db::get_db_con(&db_pool)
+            .await?;
+
+    let adp = match season_or_current(&db, query.season).await? {
+        Some(season) => db::get_adp(&db, season)
+            .await
+            .map_err(Error::Database)?,
+        None => vec![],
+    };
+
+    Ok(warp::reply::json(&query.pagination().paginate(adp)?))
+}
+
+/// Every user's draft graded against the big league, best first
+#[utoipa::path(
+    get,
+    path = "/api/v1/drafts/grades",
+    params(DraftsQuery),
+    responses(
+        (status = 200, description = "Draft grades by rank", body = GradesPage),
+        (status = 400, description = "Invalid pagination", body = ApiError),
+        (status = 500, description = "Internal error", body = ApiError),
+    ),
+)]
+pub async fn grades_handler(query: DraftsQuery, db_pool: Arc<db::DBPool>) -> std::result::Result<impl Reply, Rejection> {
+
+    info!("GET /api/v1/drafts/grades?season={:?}", query.season);
+
+    let db = db::get_db_con(&db_pool)
+            .await?;
+
+    let grades = match season_or_current(&db, query.season).await? {
+        Some(season) => grades::get_grades(&db, season)
+            .await
+            .map_err(Error::Database)?,
+        None => vec![],
+    };
+
+    Ok(warp::reply::json(&query.pagination().paginate(grades)?))
+}
+
+/// A season's trades with the points each side has gotten out of them
+#[utoipa::path(
+    get,
+    path = "/api/v1/trades",
+    params(TradesQuery),
+    responses(
+        (status = 200, description = "Trades, newest first", body = TradesPage),
+        (status = 400, description = "Invalid pagination", body = ApiError),
+        (status = 500, description = "Internal error", body = ApiError),
+    ),
+)]
+pub async fn trades_handler(query: TradesQuery, db_pool: Arc<db::DBPool>) -> std::result::Result<impl Reply, Rejection> {
+
+    info!("GET /api/v1/trades?season={:?}&league={:?}&user={:?}", query.season, query.league, query.user);
+
+    let db = db::get_db_con(&db_pool)
+            .await?;
+
+    let trades = match season_or_current(&db, query.season).await? {
+        Some(season) => trades::get_trades(&db, season, query.league.clone(), query.user.clone())
+            .await
+            .map_err(Error::Database)?,
+        None => vec![],
+    };
+
+    Ok(warp::reply::json(&query.pagination().paginate(trades)?))
+}
+
+/// Every league's settings side by side, and which ones differ
+#[utoipa::path(
+    get,
+    path = "/api/v1/settings",
+    responses(
+        (status = 200, description = "League settings comparison", body = settings::Comparison),
+        (status = 500, description = "Internal error", body = ApiError),
+    ),
+)]
+pub async fn settings_handler(db_pool: Arc<db::DBPool>) -> std::result::Result<impl Reply, Rejection> {
+
+    info!("GET /api/v1/settings");
+
+    let db = db::get_db_con(&db_pool)
+            .await?;
+
+    let leagues = db::get_league_settings(&db)
+        .await
+        .map_err(Error::Database)?;
+
+    Ok(warp::reply::json(&settings::compare(leagues)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{api, config, db};
+    use crate::error::Error;
+    use std::sync::Arc;
+    use tokio::sync::watch;
+    use utoipa::OpenApi;
+    use warp::Reply;
+    use warp::http::StatusCode;
+    use utoipa::openapi::PathItemType;
+
+    #[tokio::test]
+    async fn test_openapi_matches_routes() {
+        let spec = api::ApiDoc::openapi();
+
+        // Nothing listens on port 1, so every handler fails fast with a
+        // 500 and only paths that aren't routed come back as 404
+        let mut config: config::Config = toml::from_str(&std::fs::read_to_string("Bigleague.toml").unwrap()).unwrap();
+        config.database.url = Some(String::from("postgres://bigleague@127.0.0.1:1/bigleague"));
+        config.database.timeout = 1;
+        let pool = Arc::new(db::create_pool(config.clone()).unwrap());
+        let (_sender, receiver) = watch::channel(config);
+        let routes = api::routes(pool, receiver);
+
+        for (path, item) in spec.paths.paths.iter() {
+            assert!(item.operations.contains_key(&PathItemType::Get), "{} isn't a GET", path);
+
+            // Fill in path parameters so the path can be requested
+            let concrete = path.replace("{id}", "1234");
+            let reply = warp::test::request().path(&concrete).reply(&routes).await;
+            assert_ne!(reply.status(), StatusCode::NOT_FOUND, "{} doesn't match any route", path);
+        }
+
+        let reply = warp::test::request().path("/api/v1/nothing").reply(&routes).await;
+        assert_eq!(reply.status(), StatusCode::NOT_FOUND);
+
+        // Every route defined in api::routes, other than the spec
+        // itself, has to be in the spec
+        let source = include_str!("api.rs");
+        let body = &source[source.find("pub fn routes(").unwrap()..];
+        let body = &body[..body.find("\n}\n").unwrap()];
+        let defined = body.matches(".and_then(").count() - 1;
+        assert_eq!(spec.paths.paths.len(), defined);
+    }
+
+    #[tokio::test]
+    async fn test_rejections_are_json() {
+        let reply = api::handle_rejection(warp::reject::custom(Error::NotFound(String::from("user"))))
+            .await
+            .unwrap()
+            .into_response();
+
+        assert_eq!(reply.status(), StatusCode::NOT_FOUND);
+        let body = warp::hyper::body::to_bytes(reply.into_body()).await.unwrap();
+        let error: api::ApiError = serde_json::from_slice(&body).unwrap();
+        assert_eq!(error.error.message, "user not found");
+    }
+
+    #[test]
+    fn test_paginate() {
+        let pagination = api::Pagination {
+            page: Some(2),
+            per_page: Some(3),
+        };
+
+        let page = pagination.paginate((1..=7).collect::<Vec<i32>>()).unwrap();
+
+        assert_eq!(page.data, vec![4, 5, 6]);
+        assert_eq!(page.total, 7);
+        assert!(api::Pagination { page: Some(0), per_page: None }.paginate(vec![1]).is_err());
+    }
+}
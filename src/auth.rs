@@ -0,0 +1,279 @@
+use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
+use argon2::password_hash::SaltString;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use chrono::{Duration, Utc};
+use rand_core::{OsRng, RngCore};
+use serde::{Serialize, Deserialize};
+use serde::de::DeserializeOwned;
+use subtle::ConstantTimeEq;
+use tera::{Tera, Context};
+use warp::{Filter, Reply, Rejection};
+use warp::http::{HeaderValue, Uri};
+use warp::http::header::SET_COOKIE;
+use warp::hyper::body::Bytes;
+use std::str::FromStr;
+use std::sync::{Arc, LazyLock};
+use log::{info, warn};
+
+use crate::db;
+use crate::error::Error;
+use crate::handlers;
+
+const SESSION_COOKIE: &str = "bigleague_session";
+
+const SESSION_DAYS: i64 = 7;
+
+// Logins for accounts that don't exist are checked against this, so
+// they take as long to fail as a wrong password
+static MISSING_ACCOUNT_HASH: LazyLock<String> = LazyLock::new(|| {
+    hash_password("no such account").unwrap_or_default()
+});
+
+// Viewers can look at the admin pages, commissioners can change things
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Viewer,
+    Commissioner,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Commissioner => "commissioner",
+        }
+    }
+}
+
+impl FromStr for Role {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Role, Error> {
+        match s {
+            "viewer" => Ok(Role::Viewer),
+            "commissioner" => Ok(Role::Commissioner),
+            _ => Err(Error::BadRequest(format!("unknown role {}", s))),
+        }
+    }
+}
+
+// Whoever is logged in on this request
+#[derive(Serialize, Debug, Clone)]
+pub struct Session {
+    #[serde(skip)]
+    pub token: String,
+    pub username: String,
+    pub role: Role,
+    pub csrf_token: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Login {
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct Csrf {
+    csrf_token: String,
+}
+
+// For forms that only need the CSRF token
+#[derive(Deserialize, Debug)]
+pub struct Empty {}
+
+pub fn hash_password(password: &str) -> Result<String, Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| Error::BadRequest(format!("couldn't hash password: {}", e)))
+}
+
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    match PasswordHash::new(hash) {
+        Ok(parsed) => Argon2::default().verify_password(password.as_bytes(), &parsed).is_ok(),
+        Err(e) => {
+            warn!("stored password hash is invalid: {}", e);
+            false
+        }
+    }
+}
+
+fn new_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+// The logged in session, if there is one
+pub fn session(db_pool: Arc<db::DBPool>) -> impl Filter<Extract = (Option<Session>,), Error = Rejection> + Clone {
+    warp::cookie::optional::<String>(SESSION_COOKIE)
+        .and(db::with_db(db_pool))
+        .and_then(|token: Option<String>, db_pool: Arc<db::DBPool>| async move {
+            let token = match token {
+                Some(t) => t,
+                None => return Ok::<_, Rejection>(None),
+            };
+            let db = db::get_db_con(&db_pool).await?;
+            let session = db::get_session(&db, &token)
+                .await
+                .map_err(Error::Database)?
+                .and_then(|(account, csrf_token)| {
+                    Some(Session {
+                        token,
+                        username: account.username,
+                        role: account.role.parse().ok()?,
+                        csrf_token,
+                    })
+                });
+            Ok(session)
+        })
+}
+
+// Guards a route, only letting through accounts with at least `role`
+pub fn require(role: Role, db_pool: Arc<db::DBPool>) -> impl Filter<Extract = (Session,), Error = Rejection> + Clone {
+    session(db_pool).and_then(move |session: Option<Session>| async move {
+        match session {
+            Some(s) if s.role >= role => Ok(s),
+            Some(_) => Err(warp::reject::custom(Error::Forbidden)),
+            None => Err(warp::reject::custom(Error::Unauthorized)),
+        }
+    })
+}
+
+// Guards a form post, checking its CSRF token against the session
+// before handing over the rest of the form
+pub fn form<T: DeserializeOwned + Send + 'static>(role: Role, db_pool: Arc<db::DBPool>) -> impl Filter<Extract = (Session, T), Error = Rejection> + Clone {
+    require(role, db_pool)
+        .and(warp::body::content_length_limit(16 * 1024))
+        .and(warp::body::bytes())
+        .and_then(|session: Session, body: Bytes| async move {
+            let csrf: Csrf = serde_urlencoded::from_bytes(&body)
+                .map_err(|_| warp::reject::custom(Error::Forbidden))?;
+            if !bool::from(csrf.csrf_token.as_bytes().ct_eq(session.csrf_token.as_bytes())) {
+                return Err(warp::reject::custom(Error::Forbidden));
+            }
+            let form: T = serde_urlencoded::from_bytes(&body)
+                .map_err(|e| warp::reject::custom(Error::BadRequest(e.to_string())))?;
+            Ok((session, form))
+        })
+        .untuple_one()
+}
+
+fn session_cookie(value: &str, max_age: i64, secure: bool) -> HeaderValue {
+    HeaderValue::from_str(&format!(
+        "{}={}; Path=/; Max-Age={}; HttpOnly; SameSite=Lax{}",
+        SESSION_COOKIE, value, max_age, if secure { "; Secure" } else { "" },
+    ))
+    .unwrap_or_else(|_| HeaderValue::from_static(""))
+}
+
+pub async fn login_page_handler(tera: Arc<Tera>) -> std::result::Result<impl Reply, Rejection> {
+
+    info!("GET /login");
+
+    let mut ctx = Context::new();
+    ctx.insert("failed", &false);
+    handlers::render("login.html", ctx, tera)
+}
+
+pub async fn login_handler(login: Login, db_pool: Arc<db::DBPool>, tera: Arc<Tera>, secure: bool) -> std::result::Result<warp::reply::Response, Rejection> {
+
+    info!("POST /login {}", login.username);
+
+    let db = db::get_db_con(&db_pool)
+            .await?;
+
+    let (account, hash) = match db::get_account_login(&db, &login.username).await.map_err(Error::Database)? {
+        Some((account, hash)) => (Some(account), hash),
+        None => (None, MISSING_ACCOUNT_HASH.clone()),
+    };
+    let verified = verify_password(&login.password, &hash);
+    let account = account.filter(|_| verified);
+
+    let account = match account {
+        Some(a) => a,
+        None => {
+            warn!("failed login for {}", login.username);
+            let mut ctx = Context::new();
+            ctx.insert("failed", &true);
+            let page = handlers::render("login.html", ctx, tera)?;
+            return Ok(warp::reply::with_status(page, warp::http::StatusCode::UNAUTHORIZED).into_response());
+        }
+    };
+
+    let token = new_token();
+    let expires_at = Utc::now() + Duration::days(SESSION_DAYS);
+    db::create_session(&db, &token, account.id, &new_token(), expires_at)
+        .await
+        .map_err(Error::Database)?;
+
+    let mut response = warp::redirect::see_other(Uri::from_static("/admin")).into_response();
+    response.headers_mut().insert(SET_COOKIE, session_cookie(&token, SESSION_DAYS * 24 * 60 * 60, secure));
+    Ok(response)
+}
+
+pub async fn logout_handler(session: Session, _form: Empty, db_pool: Arc<db::DBPool>, secure: bool) -> std::result::Result<impl Reply, Rejection> {
+
+    info!("POST /logout {}", session.username);
+
+    let db = db::get_db_con(&db_pool)
+            .await?;
+
+    db::delete_session(&db, &session.token)
+        .await
+        .map_err(Error::Database)?;
+
+    let mut response = warp::redirect::see_other(Uri::from_static("/")).into_response();
+    response.headers_mut().insert(SET_COOKIE, session_cookie("", 0, secure));
+    Ok(response)
+}
+
+pub fn routes(db_pool: Arc<db::DBPool>, tera: Arc<Tera>, secure_cookies: bool) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+
+    let login_tera = tera.clone();
+    let login_page_route = warp::get()
+        .and(warp::path!("login"))
+        .and(warp::any().map(move || login_tera.clone()))
+        .and_then(login_page_handler);
+
+    let login_route = warp::post()
+        .and(warp::path!("login"))
+        .and(warp::body::content_length_limit(16 * 1024))
+        .and(warp::body::form::<Login>())
+        .and(db::with_db(db_pool.clone()))
+        .and(warp::any().map(move || tera.clone()))
+        .and(warp::any().map(move || secure_cookies))
+        .and_then(login_handler);
+
+    let logout_route = warp::post()
+        .and(warp::path!("logout"))
+        .and(form::<Empty>(Role::Viewer, db_pool.clone()))
+        .and(db::with_db(db_pool))
+        .and(warp::any().map(move || secure_cookies))
+        .and_then(logout_handler);
+
+    login_page_route
+        .or(login_route)
+        .or(logout_route)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::auth::{self, Role};
+
+    #[test]
+    fn test_passwords_and_roles() {
+        let hash = auth::hash_password("hunter2").unwrap();
+        assert!(auth::verify_password("hunter2", &hash));
+        assert!(!auth::verify_password("hunter3", &hash));
+        assert!(!auth::verify_password("hunter2", "not a hash"));
+
+        assert!(Role::Commissioner > Role::Viewer);
+        assert_eq!("commissioner".parse::<Role>().unwrap(), Role::Commissioner);
+        assert!("admin".parse::<Role>().is_err());
+    }
+}
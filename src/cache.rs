@@ -0,0 +1,270 @@
+use chrono::{DateTime, Utc};
+use warp::{Filter, Reply, Rejection};
+use warp::http::{HeaderMap, HeaderValue, StatusCode};
+use warp::http::header::{CACHE_CONTROL, CONTENT_TYPE, ETAG, LAST_MODIFIED};
+use warp::hyper::body::{self, Bytes};
+use warp::path::FullPath;
+use warp::reply::Response;
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::convert::Infallible;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicU64, Ordering};
+use log::{debug, warn};
+
+// Keys include the query string, so cap how many pages are kept
+// to stop arbitrary queries from growing the cache forever.
+const MAX_ENTRIES: usize = 1024;
+
+const HTTP_DATE: &str = "%a, %d %b %Y %H:%M:%S GMT";
+
+// Rendered pages and API responses, keyed by path and query.
+// Everything is thrown away whenever ingestion writes new data.
+#[derive(Clone, Default)]
+pub struct Cache {
+    entries: Arc<RwLock<HashMap<String, Arc<Entry>>>>,
+    // Bumped on every invalidation so renders that started before
+    // it can't put stale pages back
+    generation: Arc<AtomicU64>,
+}
+
+pub struct Entry {
+    body: Bytes,
+    content_type: Option<HeaderValue>,
+    etag: String,
+    last_modified: DateTime<Utc>,
+}
+
+// Conditional request headers sent by the client
+#[derive(Debug, Clone, Default)]
+pub struct Conditional {
+    pub if_none_match: Option<String>,
+    pub if_modified_since: Option<String>,
+}
+
+impl Cache {
+    pub fn new() -> Cache {
+        Cache::default()
+    }
+
+    pub fn get(&self, key: &str) -> Option<Arc<Entry>> {
+        self.entries.read().ok()?.get(key).cloned()
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::SeqCst)
+    }
+
+    pub fn invalidate(&self) {
+        match self.entries.write() {
+            Ok(mut entries) => {
+                self.generation.fetch_add(1, Ordering::SeqCst);
+                debug!("invalidating {} cached responses", entries.len());
+                entries.clear();
+            },
+            Err(e) => warn!("couldn't invalidate cache: {}", e),
+        }
+    }
+
+    // Skipped when the cache was invalidated after the
+    // entry's generation was read
+    fn insert(&self, key: String, entry: Arc<Entry>, generation: u64) {
+        if let Ok(mut entries) = self.entries.write() {
+            if generation != self.generation() {
+                debug!("not caching {} rendered before an invalidation", key);
+                return;
+            }
+            if entries.len() < MAX_ENTRIES || entries.contains_key(&key) {
+                entries.insert(key, entry);
+            }
+        }
+    }
+
+    // Only successful responses are cached, anything else is
+    // passed straight through
+    async fn store(&self, key: String, generation: u64, reply: impl Reply, conditional: &Conditional) -> Response {
+        let response = reply.into_response();
+        if response.status() != StatusCode::OK {
+            return response;
+        }
+
+        let (parts, body) = response.into_parts();
+        let body = match body::to_bytes(body).await {
+            Ok(b) => b,
+            Err(e) => {
+                warn!("couldn't read response for {}: {}", key, e);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        };
+
+        let entry = Arc::new(Entry::new(body, parts.headers.get(CONTENT_TYPE).cloned(), Utc::now()));
+        self.insert(key, entry.clone(), generation);
+        entry.respond(conditional)
+    }
+}
+
+impl Entry {
+    pub fn new(body: Bytes, content_type: Option<HeaderValue>, last_modified: DateTime<Utc>) -> Entry {
+        let mut hasher = DefaultHasher::new();
+        body.hash(&mut hasher);
+        Entry {
+            body,
+            content_type,
+            etag: format!("\"{:016x}\"", hasher.finish()),
+            last_modified,
+        }
+    }
+
+    fn not_modified(&self, conditional: &Conditional) -> bool {
+        // If-None-Match wins when both are sent
+        if let Some(tags) = &conditional.if_none_match {
+            return tags.split(',').any(|t| t.trim() == self.etag || t.trim() == "*");
+        }
+        if let Some(since) = &conditional.if_modified_since {
+            if let Ok(since) = DateTime::parse_from_rfc2822(since) {
+                return since.timestamp() >= self.last_modified.timestamp();
+            }
+        }
+        false
+    }
+
+    pub fn respond(&self, conditional: &Conditional) -> Response {
+        let mut response = if self.not_modified(conditional) {
+            StatusCode::NOT_MODIFIED.into_response()
+        } else {
+            let mut response = Response::new(self.body.clone().into());
+            if let Some(content_type) = &self.content_type {
+                response.headers_mut().insert(CONTENT_TYPE, content_type.clone());
+            }
+            response
+        };
+
+        let headers: &mut HeaderMap = response.headers_mut();
+        if let Ok(etag) = HeaderValue::from_str(&self.etag) {
+            headers.insert(ETAG, etag);
+        }
+        if let Ok(modified) = HeaderValue::from_str(&self.last_modified.format(HTTP_DATE).to_string()) {
+            headers.insert(LAST_MODIFIED, modified);
+        }
+        // Always check back since the data can change at any time
+        headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+        response
+    }
+}
+
+pub fn with_cache(cache: Cache) -> impl Filter<Extract = (Cache,), Error = Infallible> + Clone {
+    warp::any().map(move || cache.clone())
+}
+
+fn request_key() -> impl Filter<Extract = (String,), Error = Infallible> + Clone {
+    warp::path::full()
+        .and(warp::query::raw().or(warp::any().map(String::new)).unify())
+        .map(|path: FullPath, query: String| {
+            if query.is_empty() {
+                path.as_str().to_string()
+            } else {
+                format!("{}?{}", path.as_str(), query)
+            }
+        })
+}
+
+fn conditional() -> impl Filter<Extract = (Conditional,), Error = Infallible> + Clone {
+    warp::header::optional::<String>("if-none-match")
+        .and(warp::header::optional::<String>("if-modified-since"))
+        .map(|if_none_match, if_modified_since| Conditional { if_none_match, if_modified_since })
+        .recover(|_| async { Ok::<_, Infallible>(Conditional::default()) })
+        .unify()
+}
+
+// Serves a route out of the cache when possible, otherwise runs
+// the route and keeps its response for next time
+pub fn cached<F, R>(cache: Cache, route: F) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone
+where
+    F: Filter<Extract = (R,), Error = Rejection> + Clone + Send + Sync + 'static,
+    R: Reply + 'static,
+{
+    let hit = request_key()
+        .and(conditional())
+        .and(with_cache(cache.clone()))
+        .and_then(|key: String, conditional: Conditional, cache: Cache| async move {
+            match cache.get(&key) {
+                Some(entry) => Ok(entry.respond(&conditional)),
+                None => Err(warp::reject()),
+            }
+        });
+
+    // The generation is read before the route renders anything
+    let miss = request_key()
+        .and(conditional())
+        .and(with_cache(cache))
+        .map(|key: String, conditional: Conditional, cache: Cache| {
+            let generation = cache.generation();
+            (key, conditional, cache, generation)
+        })
+        .untuple_one()
+        .and(route)
+        .and_then(|key: String, conditional: Conditional, cache: Cache, generation: u64, reply: R| async move {
+            Ok::<_, Rejection>(cache.store(key, generation, reply, &conditional).await)
+        });
+
+    hit.or(miss).unify()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cache;
+    use chrono::Utc;
+    use warp::Filter;
+    use warp::hyper::body::Bytes;
+    use warp::http::StatusCode;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn test_cached_route() {
+        let renders = Arc::new(AtomicUsize::new(0));
+        let counter = renders.clone();
+        let route = warp::path!("page").map(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            "hello"
+        });
+
+        let cache = cache::Cache::new();
+        let filter = cache::cached(cache.clone(), route);
+
+        let first = warp::test::request().path("/page").reply(&filter).await;
+        assert_eq!(first.status(), StatusCode::OK);
+        let etag = first.headers()["etag"].to_str().unwrap().to_string();
+
+        let second = warp::test::request().path("/page").reply(&filter).await;
+        assert_eq!(second.body(), "hello");
+        assert_eq!(renders.load(Ordering::SeqCst), 1);
+
+        let revalidated = warp::test::request()
+            .path("/page")
+            .header("if-none-match", &etag)
+            .reply(&filter)
+            .await;
+        assert_eq!(revalidated.status(), StatusCode::NOT_MODIFIED);
+
+        cache.invalidate();
+        warp::test::request().path("/page").reply(&filter).await;
+        assert_eq!(renders.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_stale_insert_skipped() {
+        let cache = cache::Cache::new();
+        let entry = Arc::new(cache::Entry::new(Bytes::from("old"), None, Utc::now()));
+
+        // Rendered before an invalidation, so it must not be kept
+        let generation = cache.generation();
+        cache.invalidate();
+        cache.insert(String::from("/page"), entry.clone(), generation);
+        assert!(cache.get("/page").is_none());
+
+        cache.insert(String::from("/page"), entry, cache.generation());
+        assert!(cache.get("/page").is_some());
+    }
+}
@@ -0,0 +1,254 @@
+use clap::{Parser, Subcommand};
+use serde_json::{Map, Value};
+use std::io::IsTerminal;
+use std::sync::Arc;
+use log::{info, warn};
+
+use crate::auth;
+use crate::config;
+use crate::db;
+use crate::error::Error;
+use crate::events;
+use crate::health;
+use crate::reload;
+use crate::stats;
+
+#[derive(Parser, Debug)]
+#[command(version, about = "Standings and playoffs across Sleeper leagues")]
+pub struct Cli {
+    /// Path to the config file
+    #[arg(long, global = true, default_value = "Bigleague.toml")]
+    pub config: String,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug, Clone, PartialEq)]
+pub enum Command {
+    /// Run ingestion and the web server, the default when no command is given
+    Serve,
+    /// Run ingestion without the web server
+    Ingest {
+        /// Run the jobs once and exit instead of looping
+        #[arg(long)]
+        once: bool,
+        /// Only run this job
+        #[arg(long, value_parser = stats::JOBS, requires = "once")]
+        job: Option<String>,
+        /// Only ingest this league instead of every configured one
+        #[arg(long, requires = "once")]
+        league: Option<String>,
+    },
+    /// Load every week of matchups for a season
+    Backfill {
+        #[arg(long)]
+        season: i32,
+        /// Only backfill this league instead of every configured one
+        #[arg(long)]
+        league: Option<String>,
+    },
+    /// Create or update the database tables
+    Migrate,
+    /// Check the config file and exit
+    CheckConfig,
+    /// Add or update a login for the admin pages, the password is read from a file or piped in
+    AddAccount {
+        username: String,
+        #[arg(long, value_enum, default_value = "viewer")]
+        role: auth::Role,
+        /// File holding the password instead of stdin
+        #[arg(long)]
+        password_file: Option<String>,
+    },
+    /// Remove a login for the admin pages
+    RemoveAccount {
+        username: String,
+    },
+    /// Write the database out as JSON
+    Export {
+        /// Only export this table
+        #[arg(long, value_parser = db::TABLES)]
+        table: Option<String>,
+        /// File to write to instead of stdout
+        #[arg(long)]
+        output: Option<String>,
+    },
+}
+
+// Connects to the database and makes sure the tables are there
+pub async fn setup_db(config: &config::Config) -> Result<Arc<db::DBPool>, Error> {
+    let pool = Arc::new(db::create_pool(config.clone())?);
+    db::create_tables(pool.clone()).await?;
+    let mut con = db::get_db_con(&pool).await?;
+    db::seed_managed_leagues(&con, &config.bigleague.leagues).await?;
+    db::set_normalized_scoring(&mut con, &config.bigleague.scoring).await?;
+    Ok(pool)
+}
+
+// Checks the playoff bids against the teams already in the database,
+// skipped when the database can't be reached
+pub async fn check_bids(config: &config::Config, db_pool: &db::DBPool) -> Vec<config::Problem> {
+    let sizes = match db::get_db_con(db_pool).await {
+        Ok(con) => db::get_league_sizes(&con).await.unwrap_or_default(),
+        Err(e) => {
+            warn!("couldn't check playoff bids against the database: {}", e);
+            return vec![];
+        }
+    };
+    let leagues = stats::current_leagues(db_pool, config).await;
+    config::validate_bids(config, &leagues, &sizes)
+}
+
+// Runs every command other than serve, which lives in main
+pub async fn run(command: Command, config_path: String, config: config::Config) -> Result<(), Error> {
+    match command {
+        Command::CheckConfig => {
+            // Bids can only be checked once the leagues have been ingested
+            let pool = Arc::new(db::create_pool(config.clone())?);
+            let problems = check_bids(&config, &pool).await;
+            if !problems.is_empty() {
+                return Err(Error::Config(config::ConfigError::Invalid(problems)));
+            }
+            println!("config is valid");
+            Ok(())
+        },
+        Command::Migrate => {
+            setup_db(&config).await?;
+            println!("tables are up to date");
+            Ok(())
+        },
+        Command::Ingest { once, job, league } => {
+            let pool = setup_db(&config).await?;
+            let events = events::channel();
+            let jobs = health::Jobs::new();
+
+            if !once {
+                let config = reload::watch_config(config_path, config);
+                // Nothing sends triggers without the web server
+                let (_triggers, trigger_receiver) = stats::triggers();
+                return stats::stats_loop(config, pool, events, crate::cache::Cache::new(), jobs, trigger_receiver)
+                    .await
+                    .map_err(|e| Error::Ingest(e.to_string()));
+            }
+
+            let managed = stats::current_leagues(&pool, &config).await;
+            let leagues = match league {
+                Some(l) => {
+                    if !managed.contains(&l) {
+                        warn!("league {} isn't a managed league", l);
+                    }
+                    vec![l]
+                },
+                None => managed,
+            };
+            let to_run: Vec<&str> = match &job {
+                Some(j) => vec![j.as_str()],
+                None => stats::JOBS.to_vec(),
+            };
+
+            stats::ingest_once(&config, &pool, &events, &jobs, &to_run, &leagues).await
+        },
+        Command::Backfill { season, league } => {
+            let pool = setup_db(&config).await?;
+            let events = events::channel();
+
+            let leagues = match league {
+                Some(l) => vec![l],
+                None => stats::current_leagues(&pool, &config).await,
+            };
+            for league_id in leagues {
+                stats::backfill_season(&pool, league_id, season, &events).await?;
+            }
+            Ok(())
+        },
+        Command::AddAccount { username, role, password_file } => {
+            // Typing it into a terminal would echo it back, so the
+            // password has to come from a file or a pipe
+            let password = match password_file {
+                Some(path) => std::fs::read_to_string(path)?,
+                None if std::io::stdin().is_terminal() => {
+                    return Err(Error::BadRequest(String::from("pipe the password in or pass --password-file")));
+                },
+                None => {
+                    let mut password = String::new();
+                    std::io::stdin().read_line(&mut password)?;
+                    password
+                },
+            };
+            let password = password.trim_end_matches(['\n', '\r']);
+            if password.is_empty() {
+                return Err(Error::BadRequest(String::from("password can't be empty")));
+            }
+
+            let pool = setup_db(&config).await?;
+            let con = db::get_db_con(&pool).await?;
+
+            let hash = auth::hash_password(password)?;
+            db::upsert_account(&con, &username, &hash, role.as_str()).await?;
+            println!("{} can log in as a {}", username, role.as_str());
+            Ok(())
+        },
+        Command::RemoveAccount { username } => {
+            let pool = setup_db(&config).await?;
+            let con = db::get_db_con(&pool).await?;
+            if !db::remove_account(&con, &username).await? {
+                return Err(Error::NotFound(format!("account {}", username)));
+            }
+            println!("removed {}", username);
+            Ok(())
+        },
+        Command::Export { table, output } => {
+            let pool = Arc::new(db::create_pool(config)?);
+            let con = db::get_db_con(&pool).await?;
+
+            let tables: Vec<&str> = match &table {
+                Some(t) => vec![t.as_str()],
+                None => db::TABLES.to_vec(),
+            };
+            let mut export = Map::new();
+            for t in tables {
+                export.insert(t.to_string(), db::export_table(&con, t).await?);
+            }
+            let export = serde_json::to_string_pretty(&Value::Object(export))?;
+
+            match output {
+                Some(path) => {
+                    std::fs::write(&path, export)?;
+                    info!("exported to {}", path);
+                },
+                None => println!("{}", export),
+            }
+            Ok(())
+        },
+        Command::Serve => Err(Error::BadRequest(String::from("serve is handled by main"))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::auth;
+    use crate::cli::{Cli, Command};
+    use clap::Parser;
+
+    #[test]
+    fn test_parse_commands() {
+        let cli = Cli::parse_from(["bigleague"]);
+        assert_eq!(cli.config, "Bigleague.toml");
+        assert_eq!(cli.command, None);
+
+        let cli = Cli::parse_from(["bigleague", "ingest", "--once", "--job", "rosters", "--config", "other.toml"]);
+        assert_eq!(cli.config, "other.toml");
+        assert_eq!(cli.command, Some(Command::Ingest { once: true, job: Some(String::from("rosters")), league: None }));
+
+        assert!(Cli::try_parse_from(["bigleague", "ingest", "--once", "--job", "nope"]).is_err());
+        assert!(Cli::try_parse_from(["bigleague", "ingest", "--league", "1234"]).is_err());
+
+        let cli = Cli::parse_from(["bigleague", "add-account", "alice", "--password-file", "/run/secrets/alice"]);
+        assert_eq!(cli.command, Some(Command::AddAccount {
+            username: String::from("alice"),
+            role: auth::Role::Viewer,
+            password_file: Some(String::from("/run/secrets/alice")),
+        }));
+    }
+}
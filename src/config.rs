@@ -1,12 +1,45 @@
 use serde::{Serialize, Deserialize};
+use toml::Value;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fmt;
+use std::net::IpAddr;
+use log::info;
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+use crate::live;
+
+// Environment variables starting with this override config fields,
+// with double underscores between sections,
+// e.g. BIGLEAGUE_DATABASE__PASSWORD sets database.password
+const ENV_PREFIX: &str = "BIGLEAGUE_";
+
+// Fields ending in this are read from the file they name instead,
+// e.g. database.password_file sets database.password
+const FILE_SUFFIX: &str = "_file";
+
+// Fields that are always strings, so overrides like
+// BIGLEAGUE_DATABASE__PASSWORD=1234 stay strings even when the field
+// isn't in the config file
+const STRING_FIELDS: &[&str] = &[
+    "web.ip",
+    "stats.players_path",
+    "database.url",
+    "database.host",
+    "database.user",
+    "database.password",
+    "database.dbname",
+];
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Web {
     pub ip: String,
     pub port: u16,
+    // Only send the session cookie over HTTPS, on unless turned off
+    // for local development over plain HTTP
+    pub secure_cookies: Option<bool>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Stats {
     pub rosters_interval: u64,
     pub players_interval: u64,
@@ -14,42 +47,59 @@ pub struct Stats {
     pub leagues_interval: u64,
     pub state_interval: u64,
     pub matchups_interval: u64,
+    pub transactions_interval: Option<u64>,
+    pub drafts_interval: Option<u64>,
+    pub player_stats_interval: Option<u64>,
     pub dev_mode: Option<bool>,
     pub players_path: Option<String>,
+    pub live: Option<Live>,
+    pub max_failed_intervals: Option<u32>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Live {
+    pub interval: u64,
+    pub windows: Option<Vec<String>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Database {
-    pub host: String,
+    // Takes the place of the fields below when set
+    pub url: Option<String>,
+    pub host: Option<String>,
     pub port: Option<u16>,
-    pub user: String,
-    pub password: String,
-    pub dbname: String,
+    pub user: Option<String>,
+    pub password: Option<String>,
+    pub dbname: Option<String>,
     pub max_open: u64,
     pub max_idle: u64,
     pub timeout: u64,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct AtLarge {
     pub bids: i64,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct PerLeague {
     pub bids_per_league: i64,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Bigleague {
     pub leagues: Vec<String>,
     pub playoffs_start_week: i32,
     pub playoffs_championship_week: i32,
     pub playoffs_at_large: Option<AtLarge>,
     pub playoffs_per_league: Option<PerLeague>,
+    // Points per stat, using Sleeper's stat names. When set, the
+    // combined standings and bracket rescore every league's starters
+    // under it instead of using each league's own scoring.
+    pub scoring: Option<HashMap<String, f64>>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Config {
     pub web: Web,
     pub stats: Stats,
@@ -57,7 +107,404 @@ pub struct Config {
     pub bigleague: Bigleague,
 }
 
-pub fn read_config(path: &str) -> Result<Config, toml::de::Error> {
-    let raw_config = std::fs::read_to_string(path).expect("couldn't read config file");
-    toml::from_str(&raw_config)
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(String, std::io::Error),
+    Parse(toml::de::Error),
+    Secret(String, String, std::io::Error),
+    Invalid(Vec<Problem>),
+}
+
+// Something wrong with a single field, field is the dotted path
+// to it in the config file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Problem {
+    pub field: String,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Read(path, e) => write!(f, "couldn't read config file {}: {}", path, e),
+            ConfigError::Parse(e) => write!(f, "couldn't parse config: {}", e),
+            ConfigError::Secret(field, path, e) => write!(f, "couldn't read {} from {}: {}", field, path, e),
+            ConfigError::Invalid(problems) => {
+                write!(f, "found {} problem(s) in config:", problems.len())?;
+                for problem in problems {
+                    write!(f, "\n  {}", problem)?;
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<toml::de::Error> for ConfigError {
+    fn from(e: toml::de::Error) -> ConfigError {
+        ConfigError::Parse(e)
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+impl Problem {
+    fn new(field: impl Into<String>, message: impl Into<String>) -> Problem {
+        Problem { field: field.into(), message: message.into() }
+    }
+}
+
+// Reads the config file, then applies DATABASE_URL and any BIGLEAGUE_*
+// environment variables on top of it, then loads secrets from files.
+// The result is validated before it's returned.
+pub fn read_config(path: &str) -> Result<Config, ConfigError> {
+    let raw_config = std::fs::read_to_string(path)
+        .map_err(|e| ConfigError::Read(path.to_string(), e))?;
+    let mut config: Value = toml::from_str(&raw_config)?;
+
+    if let Ok(url) = env::var("DATABASE_URL") {
+        set_field(&mut config, &["database", "url"], &url);
+    }
+    apply_env(&mut config, env::vars());
+    resolve_files(&mut config)?;
+
+    let config: Config = config.try_into()?;
+
+    let problems = validate(&config);
+    if !problems.is_empty() {
+        return Err(ConfigError::Invalid(problems));
+    }
+    Ok(config)
+}
+
+// Checks everything that can be checked without the database,
+// returning every problem found rather than just the first
+pub fn validate(config: &Config) -> Vec<Problem> {
+    let mut problems = vec![];
+
+    if config.web.ip.parse::<IpAddr>().is_err() {
+        problems.push(Problem::new("web.ip", format!("{:?} isn't an ip address", config.web.ip)));
+    }
+
+    let intervals = [
+        ("stats.rosters_interval", config.stats.rosters_interval),
+        ("stats.players_interval", config.stats.players_interval),
+        ("stats.users_interval", config.stats.users_interval),
+        ("stats.leagues_interval", config.stats.leagues_interval),
+        ("stats.state_interval", config.stats.state_interval),
+        ("stats.matchups_interval", config.stats.matchups_interval),
+    ];
+    for (field, interval) in intervals {
+        if interval == 0 {
+            problems.push(Problem::new(field, "must be greater than 0"));
+        }
+    }
+
+    if let Some(live) = &config.stats.live {
+        if live.interval == 0 {
+            problems.push(Problem::new("stats.live.interval", "must be greater than 0"));
+        }
+        for (i, window) in live.windows.iter().flatten().enumerate() {
+            if live::GameWindow::parse(window).is_none() {
+                problems.push(Problem::new(
+                    format!("stats.live.windows[{}]", i),
+                    format!("{:?} should look like \"Sun 17:00-04:30\"", window),
+                ));
+            }
+        }
+    }
+
+    if config.stats.transactions_interval == Some(0) {
+        problems.push(Problem::new("stats.transactions_interval", "must be greater than 0"));
+    }
+    if config.stats.drafts_interval == Some(0) {
+        problems.push(Problem::new("stats.drafts_interval", "must be greater than 0"));
+    }
+    if config.stats.player_stats_interval == Some(0) {
+        problems.push(Problem::new("stats.player_stats_interval", "must be greater than 0"));
+    }
+    if config.stats.max_failed_intervals == Some(0) {
+        problems.push(Problem::new("stats.max_failed_intervals", "must be greater than 0"));
+    }
+
+    let database = &config.database;
+    if database.url.is_none() {
+        for (field, value) in [("host", &database.host), ("user", &database.user), ("dbname", &database.dbname)] {
+            if value.is_none() {
+                problems.push(Problem::new(format!("database.{}", field), "is required unless database.url is set"));
+            }
+        }
+    }
+    if database.max_open == 0 {
+        problems.push(Problem::new("database.max_open", "must be greater than 0"));
+    }
+    if database.max_idle > database.max_open {
+        problems.push(Problem::new("database.max_idle", "can't be more than database.max_open"));
+    }
+
+    let bigleague = &config.bigleague;
+    let mut seen = HashSet::new();
+    for (i, league) in bigleague.leagues.iter().enumerate() {
+        let field = format!("bigleague.leagues[{}]", i);
+        if league.is_empty() || !league.chars().all(|c| c.is_ascii_digit()) {
+            problems.push(Problem::new(field, format!("{:?} isn't a Sleeper league id", league)));
+        } else if !seen.insert(league) {
+            problems.push(Problem::new(field, format!("league {} is listed more than once", league)));
+        }
+    }
+
+    if bigleague.playoffs_start_week < 1 {
+        problems.push(Problem::new("bigleague.playoffs_start_week", "must be at least 1"));
+    }
+    if bigleague.playoffs_championship_week < bigleague.playoffs_start_week {
+        problems.push(Problem::new(
+            "bigleague.playoffs_championship_week",
+            format!(
+                "week {} is before bigleague.playoffs_start_week (week {})",
+                bigleague.playoffs_championship_week,
+                bigleague.playoffs_start_week,
+            ),
+        ));
+    }
+
+    match (&bigleague.playoffs_at_large, &bigleague.playoffs_per_league) {
+        (Some(_), Some(_)) => problems.push(Problem::new(
+            "bigleague.playoffs_per_league",
+            "only one of playoffs_at_large and playoffs_per_league can be set",
+        )),
+        (None, None) => problems.push(Problem::new(
+            "bigleague.playoffs_at_large",
+            "one of playoffs_at_large and playoffs_per_league must be set",
+        )),
+        (None, Some(_)) => problems.push(Problem::new(
+            "bigleague.playoffs_per_league",
+            "isn't supported yet, use playoffs_at_large",
+        )),
+        (Some(_), None) => (),
+    }
+    if let Some(at_large) = &bigleague.playoffs_at_large {
+        if at_large.bids < 1 {
+            problems.push(Problem::new("bigleague.playoffs_at_large.bids", "must be at least 1"));
+        }
+    }
+    if let Some(per_league) = &bigleague.playoffs_per_league {
+        if per_league.bids_per_league < 1 {
+            problems.push(Problem::new("bigleague.playoffs_per_league.bids_per_league", "must be at least 1"));
+        }
+    }
+
+    if let Some(scoring) = &bigleague.scoring {
+        if scoring.is_empty() {
+            problems.push(Problem::new("bigleague.scoring", "needs at least one stat, or leave it out to use each league's scoring"));
+        }
+        let mut stats: Vec<(&String, &f64)> = scoring.iter().filter(|(_, points)| !points.is_finite()).collect();
+        stats.sort_by_key(|(stat, _)| *stat);
+        for (stat, points) in stats {
+            problems.push(Problem::new(format!("bigleague.scoring.{}", stat), format!("{} isn't a number of points", points)));
+        }
+    }
+
+    problems
+}
+
+// Checks the playoff bids against the number of teams in each league.
+// Leagues that haven't been ingested yet are left out.
+pub fn validate_bids(config: &Config, leagues: &[String], league_sizes: &HashMap<String, i64>) -> Vec<Problem> {
+    let mut problems = vec![];
+
+    let leagues: HashSet<&String> = leagues.iter().collect();
+    let sizes: Vec<i64> = leagues
+        .into_iter()
+        .filter_map(|l| league_sizes.get(l).copied())
+        .collect();
+    if sizes.is_empty() {
+        return problems;
+    }
+
+    if let Some(at_large) = &config.bigleague.playoffs_at_large {
+        let teams: i64 = sizes.iter().sum();
+        if at_large.bids > teams {
+            problems.push(Problem::new(
+                "bigleague.playoffs_at_large.bids",
+                format!("{} bids but there are only {} teams", at_large.bids, teams),
+            ));
+        }
+    }
+    if let Some(per_league) = &config.bigleague.playoffs_per_league {
+        let smallest = sizes.iter().min().copied().unwrap_or_default();
+        if per_league.bids_per_league > smallest {
+            problems.push(Problem::new(
+                "bigleague.playoffs_per_league.bids_per_league",
+                format!("{} bids but the smallest league only has {} teams", per_league.bids_per_league, smallest),
+            ));
+        }
+    }
+
+    problems
+}
+
+pub fn apply_env(config: &mut Value, vars: impl Iterator<Item = (String, String)>) {
+    for (key, raw) in vars {
+        let path = match key.strip_prefix(ENV_PREFIX) {
+            Some(p) => p.to_lowercase(),
+            None => continue,
+        };
+        let path: Vec<&str> = path.split("__").collect();
+        if path.iter().any(|p| p.is_empty()) {
+            continue;
+        }
+        info!("overriding {} from the environment", path.join("."));
+        set_field(config, &path, &raw);
+    }
+}
+
+// Environment variables are always strings, so the value is parsed
+// as TOML unless the field it replaces is already a string, or is
+// one that's always a string
+fn set_field(config: &mut Value, path: &[&str], raw: &str) {
+    let dotted = path.join(".");
+    let is_string = STRING_FIELDS.contains(&dotted.as_str()) || dotted.ends_with(FILE_SUFFIX);
+
+    let (field, sections) = match path.split_last() {
+        Some(p) => p,
+        None => return,
+    };
+
+    let mut table = config;
+    for section in sections {
+        let current = match table {
+            Value::Table(t) => t,
+            _ => return,
+        };
+        table = current
+            .entry(section.to_string())
+            .or_insert_with(|| Value::Table(toml::map::Map::new()));
+    }
+
+    if let Value::Table(t) = table {
+        let value = match t.get(*field) {
+            Some(Value::String(_)) => Value::String(raw.to_string()),
+            _ if is_string => Value::String(raw.to_string()),
+            _ => parse_value(raw),
+        };
+        t.insert(field.to_string(), value);
+    }
+}
+
+fn parse_value(raw: &str) -> Value {
+    toml::from_str::<toml::map::Map<String, Value>>(&format!("value = {}", raw))
+        .ok()
+        .and_then(|mut t| t.remove("value"))
+        .unwrap_or_else(|| Value::String(raw.to_string()))
+}
+
+pub fn resolve_files(config: &mut Value) -> Result<(), ConfigError> {
+    let table = match config {
+        Value::Table(t) => t,
+        _ => return Ok(()),
+    };
+
+    let file_keys: Vec<String> = table
+        .keys()
+        .filter(|k| k.ends_with(FILE_SUFFIX))
+        .cloned()
+        .collect();
+
+    for key in file_keys {
+        let field = key.trim_end_matches(FILE_SUFFIX).to_string();
+        let path = match table.remove(&key) {
+            Some(Value::String(p)) => p,
+            _ => return Err(ConfigError::Invalid(vec![Problem::new(key, "should be a path")])),
+        };
+        let secret = std::fs::read_to_string(&path)
+            .map_err(|e| ConfigError::Secret(field.clone(), path.clone(), e))?;
+        table.insert(field, Value::String(secret.trim_end_matches(['\n', '\r']).to_string()));
+    }
+
+    for (_, value) in table.iter_mut() {
+        resolve_files(value)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config;
+    use toml::Value;
+
+    #[test]
+    fn test_env_overrides() {
+        let mut value: Value = toml::from_str("
+            [web]
+            port = 6543
+            [database]
+            password = \"password\"
+        ").unwrap();
+
+        let vars = vec![
+            (String::from("BIGLEAGUE_WEB__PORT"), String::from("8080")),
+            (String::from("BIGLEAGUE_DATABASE__PASSWORD"), String::from("1234")),
+            (String::from("BIGLEAGUE_STATS__LIVE__INTERVAL"), String::from("30")),
+            // Not in the file, but still strings
+            (String::from("BIGLEAGUE_DATABASE__USER"), String::from("true")),
+            (String::from("BIGLEAGUE_DATABASE__DBNAME"), String::from("2024")),
+            (String::from("HOME"), String::from("/root")),
+        ];
+        config::apply_env(&mut value, vars.into_iter());
+
+        assert_eq!(value["web"]["port"], Value::Integer(8080));
+        assert_eq!(value["database"]["password"], Value::String(String::from("1234")));
+        assert_eq!(value["stats"]["live"]["interval"], Value::Integer(30));
+        assert_eq!(value["database"]["user"], Value::String(String::from("true")));
+        assert_eq!(value["database"]["dbname"], Value::String(String::from("2024")));
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut config: config::Config = toml::from_str(&std::fs::read_to_string("Bigleague.toml").unwrap()).unwrap();
+        assert_eq!(config::validate(&config), vec![]);
+
+        config.stats.rosters_interval = 0;
+        config.bigleague.leagues = vec![String::from("1234"), String::from("1234")];
+        config.bigleague.playoffs_championship_week = 9;
+        config.bigleague.playoffs_per_league = Some(config::PerLeague { bids_per_league: 2 });
+        config.bigleague.scoring = Some(std::collections::HashMap::from([
+            (String::from("rec"), 0.5),
+            (String::from("pass_td"), f64::NAN),
+        ]));
+
+        let fields: Vec<String> = config::validate(&config).into_iter().map(|p| p.field).collect();
+        assert_eq!(fields, vec![
+            "stats.rosters_interval",
+            "bigleague.leagues[1]",
+            "bigleague.playoffs_championship_week",
+            "bigleague.playoffs_per_league",
+            "bigleague.scoring.pass_td",
+        ]);
+
+        config.bigleague.playoffs_per_league = None;
+        let sizes = std::collections::HashMap::from([(String::from("1234"), 3)]);
+        assert_eq!(config::validate_bids(&config, &config.bigleague.leagues, &sizes).len(), 1);
+    }
+
+    #[test]
+    fn test_secret_files() {
+        let path = std::env::temp_dir().join("bigleague_test_password");
+        std::fs::write(&path, "hunter2\n").unwrap();
+
+        let mut value: Value = toml::from_str(&format!("
+            [database]
+            password_file = {:?}
+        ", path.to_str().unwrap())).unwrap();
+        config::resolve_files(&mut value).unwrap();
+
+        assert_eq!(value["database"]["password"], Value::String(String::from("hunter2")));
+        assert!(value["database"].get("password_file").is_none());
+    }
 }
@@ -2,36 +2,296 @@ use warp::Filter;
 use mobc::{Connection, Pool};
 use mobc_postgres::{tokio_postgres, PgConnectionManager};
 use tokio_postgres::{Config, Error, NoTls};
+use tokio_postgres::row::Row;
 use std::str::FromStr;
 use std::time::Duration;
 use std::convert::Infallible;
+use std::ops::{Deref, DerefMut};
 use serde::{Serialize, Deserialize};
+use utoipa::ToSchema;
 use std::sync::Arc;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use chrono::{DateTime, Utc};
 use log::{info, error, trace};
 
 use crate::config;
+use crate::error;
 
 pub type DBCon = Connection<PgConnectionManager<NoTls>>;
 pub type DBPool = Pool<PgConnectionManager<NoTls>>;
 
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
 pub struct Standing {
     pub user: User,
     pub roster: Roster,
     pub league: League,
     pub rank: i64,
+    /// Points for under the big league's scoring, when it's set
+    pub normalized_fpts: Option<f64>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Account {
+    pub id: i32,
+    pub username: String,
+    pub role: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ManagedLeague {
+    pub id: String,
+    // Empty until the league has been fetched
+    pub name: Option<String>,
+    pub added_at: DateTime<Utc>,
+}
+
+// A commissioner's correction to ingested data, kind is points,
+// result or seed and decides which of the optional fields are set
+#[derive(Serialize, Debug)]
+pub struct Override {
+    pub id: i32,
+    pub kind: String,
+    pub user_id: String,
+    pub user_name: Option<String>,
+    pub season: Option<i32>,
+    pub week: Option<i32>,
+    pub points: Option<f32>,
+    pub result: Option<String>,
+    pub seed: Option<i32>,
+    pub reason: String,
+    pub created_by: String,
+    pub created_at: DateTime<Utc>,
+    pub removed_by: Option<String>,
+    pub removed_at: Option<DateTime<Utc>>,
+}
+
+// A row ingestion added, changed or deleted. Updates only have the
+// columns that changed.
+#[derive(Serialize, Debug)]
+pub struct Change {
+    pub id: i64,
+    pub table_name: String,
+    pub operation: String,
+    pub job: Option<String>,
+    pub league_id: Option<String>,
+    pub league_name: Option<String>,
+    pub user_id: Option<String>,
+    pub user_name: Option<String>,
+    pub season: Option<i32>,
+    pub week: Option<i32>,
+    pub old_values: Option<serde_json::Value>,
+    pub new_values: Option<serde_json::Value>,
+    pub changed_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct ChangeFilter {
+    pub user_id: Option<String>,
+    pub league_id: Option<String>,
+    pub season: Option<i32>,
+    pub week: Option<i32>,
+    pub table: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct NewOverride {
+    pub kind: String,
+    pub user_id: String,
+    pub season: Option<i32>,
+    pub week: Option<i32>,
+    pub points: Option<f32>,
+    pub result: Option<String>,
+    pub seed: Option<i32>,
+    pub reason: String,
+}
+
+pub const RESULTS: [&str; 3] = ["win", "loss", "tie"];
+
+// Tables whose changes end up in the change log
+pub const LOGGED_TABLES: [&str; 10] = ["leagues", "league_settings", "scoring_settings", "users", "rosters", "ownership", "state", "matchups", "transactions", "drafts"];
+
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct Transaction {
+    pub id: String,
+    pub league_id: String,
+    pub league_name: Option<String>,
+    pub season: i32,
+    pub week: i32,
+    /// trade, waiver, free_agent or commissioner
+    pub kind: String,
+    pub creator_id: Option<String>,
+    pub waiver_bid: Option<i32>,
+    #[schema(value_type = String, format = DateTime)]
+    pub created_at: DateTime<Utc>,
+    pub players: Vec<TransactionPlayer>,
+    pub picks: Vec<TransactionPick>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct TransactionPlayer {
+    pub player_id: String,
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
+    pub position: Option<String>,
+    pub team: Option<String>,
+    /// add or drop
+    pub action: String,
+    pub user_id: String,
+    pub user_name: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct TransactionPick {
+    pub season: i32,
+    pub round: i32,
+    pub original_user_id: String,
+    pub original_user_name: Option<String>,
+    pub previous_user_id: String,
+    pub previous_user_name: Option<String>,
+    pub user_id: String,
+    pub user_name: Option<String>,
+}
+
+// Narrows down the transactions feed, unset fields match everything.
+// Positions match any transaction that moved a player at it.
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct TransactionFilter {
+    pub league_id: Option<String>,
+    pub user_id: Option<String>,
+    pub season: Option<i32>,
+    pub week: Option<i32>,
+    pub kind: Option<String>,
+    pub position: Option<String>,
+}
+
+pub const TRANSACTION_KINDS: [&str; 4] = ["trade", "waiver", "free_agent", "commissioner"];
+
+// How often a player was added or dropped across every league
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct PlayerMoves {
+    pub player_id: String,
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
+    pub position: Option<String>,
+    pub team: Option<String>,
+    pub count: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct Draft {
+    pub id: String,
+    pub league_id: String,
+    pub league_name: Option<String>,
+    pub season: i32,
+    /// snake, linear or auction
+    pub kind: String,
+    pub status: String,
+    pub rounds: i32,
+    pub teams: i32,
+    #[schema(value_type = Option<String>, format = DateTime)]
+    pub start_time: Option<DateTime<Utc>>,
+    pub picks: Vec<DraftPick>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct DraftPick {
+    pub pick_no: i32,
+    pub round: i32,
+    pub draft_slot: i32,
+    pub player_id: String,
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
+    pub position: Option<String>,
+    pub team: Option<String>,
+    /// None for picks made for a roster without an owner
+    pub user_id: Option<String>,
+    pub user_name: Option<String>,
+    /// What was paid for the player in auction drafts
+    pub amount: Option<i32>,
+    pub is_keeper: bool,
+}
+
+// A draft pick along with everything its player has scored for the
+// league that season
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct ScoredPick {
+    pub draft_id: String,
+    pub league_id: String,
+    pub league_name: Option<String>,
+    pub pick_no: i32,
+    pub round: i32,
+    pub player_id: String,
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
+    pub position: Option<String>,
+    pub user_id: Option<String>,
+    pub user_name: Option<String>,
+    pub is_keeper: bool,
+    pub points: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct LeagueSettings {
+    pub league_id: String,
+    pub league_name: String,
+    pub total_rosters: Option<i32>,
+    pub playoff_teams: Option<i32>,
+    /// The last week trades can be made
+    pub trade_deadline: Option<i32>,
+    /// 0 (rolling), 1 (reverse standings) or 2 (FAAB)
+    pub waiver_type: Option<i32>,
+    /// Lineup slots in order, including the bench
+    pub roster_positions: Vec<String>,
+    /// Points per stat
+    pub scoring: BTreeMap<String, f64>,
+}
+
+// One player on a roster for a week, with what they scored
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LineupPlayer {
+    pub week: i32,
+    pub user_id: String,
+    pub player_id: String,
+    pub position: Option<String>,
+    pub starter: bool,
+    pub points: f64,
+}
+
+// A user's score for a week against their opponent's
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MatchupPoints {
+    pub week: i32,
+    pub user_id: String,
+    pub user_name: Option<String>,
+    pub points: f64,
+    pub opponent_id: String,
+    pub opponent_points: f64,
+}
+
+// Where a player went across every league's draft
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct Adp {
+    pub player_id: String,
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
+    pub position: Option<String>,
+    pub team: Option<String>,
+    /// How many drafts the player was picked in
+    pub drafts: i64,
+    pub average_pick: f64,
+    pub earliest_pick: i32,
+    pub latest_pick: i32,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
 pub struct League {
     pub id: String,
     pub name: String,
     pub avatar: String,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
 pub struct Roster {
     pub user_id: String,
     pub league_id: String,
@@ -45,14 +305,14 @@ pub struct Roster {
     pub roster_id: i32,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
 pub struct User {
     pub id: String,
     pub name: String,
     pub avatar: String,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
 pub struct Player {
     pub id: String,
     pub first_name: String,
@@ -64,249 +324,1918 @@ pub struct Player {
     pub points: f32,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct Ownership {
-    pub user_id: String,
-    pub league_id: String,
-    pub player_id: String,
-    pub starter: i32,
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct Week {
+    pub league_id: String,
+    pub season: i32,
+    pub week: i32,
+    pub user_id: String,
+    pub user_name: String,
+    pub user_avatar: String,
+    pub user_points: f32,
+    pub opponent_id: String,
+    pub opponent_name: String,
+    pub opponent_avatar: String,
+    pub opponent_points: f32,
+    // Only set when a commissioner decided the week
+    pub result: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct Bracket {
+    pub num_teams: usize,
+    pub start_week: i32,
+    pub champ_week: i32,
+    pub stages: Vec<Vec<PlayoffTeam>>,
+}
+
+impl Bracket {
+    pub fn empty() -> Bracket {
+        Bracket {
+            num_teams: 0,
+            start_week: 0,
+            champ_week: 0,
+            stages: vec![],
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct PlayoffTeam {
+    pub week: i32,
+    pub rank: i64,
+    pub user: User,
+    pub points: f32,
+}
+
+pub async fn get_db_con(db_pool: &DBPool) -> Result<DBCon, error::Error> {
+    db_pool.get().await.map_err(error::Error::Pool)
+}
+
+pub fn with_db(db_pool: Arc<DBPool>) -> impl Filter<Extract = (Arc<DBPool>,), Error = Infallible> + Clone {
+    warp::any().map(move || db_pool.clone())
+}
+
+pub fn create_pool(bl_config: config::Config) -> std::result::Result<DBPool, mobc::Error<Error>> {
+    
+    info!("creating database pool");
+
+    let database = &bl_config.database;
+    let pg_config = match &database.url {
+        Some(url) => Config::from_str(url)?,
+        None => {
+            let mut pg_config = Config::new();
+            pg_config.port(database.port.unwrap_or(5432));
+            if let Some(host) = &database.host {
+                pg_config.host(host);
+            }
+            if let Some(user) = &database.user {
+                pg_config.user(user);
+            }
+            if let Some(password) = &database.password {
+                pg_config.password(password);
+            }
+            if let Some(dbname) = &database.dbname {
+                pg_config.dbname(dbname);
+            }
+            pg_config
+        },
+    };
+
+    let manager = PgConnectionManager::new(pg_config, NoTls);
+    Ok(Pool::builder()
+            .max_open(bl_config.database.max_open)
+            .max_idle(bl_config.database.max_idle)
+            .get_timeout(Some(Duration::from_secs(bl_config.database.timeout)))
+            .build(manager))
+}
+
+pub async fn create_tables(db_pool: Arc<DBPool>) -> Result<(), error::Error>{
+
+    info!("creating tables");
+
+    let con = get_db_con(&db_pool).await?;
+
+    // Create table for the leagues that the users are in
+    con.batch_execute(
+        "
+        CREATE TABLE IF NOT EXISTS leagues (
+            id varchar(64) PRIMARY KEY,
+            name varchar(64) NOT NULL,
+            avatar varchar(64)
+        ) 
+        "
+    ).await.unwrap();
+
+
+    con.batch_execute(
+        "
+        CREATE TABLE IF NOT EXISTS users (
+            id varchar(64) PRIMARY KEY,
+            name varchar(64) NOT NULL,
+            avatar varchar(64)
+        )
+        "
+    ).await.unwrap();
+    
+    // Create table for users in the big league
+    con.batch_execute(
+        "
+        CREATE TABLE IF NOT EXISTS rosters (
+            user_id varchar(64) PRIMARY KEY,
+            league_id varchar(64),
+            wins integer NOT NULL,
+            losses integer NOT NULL,
+            ties integer NOT NULL,
+            fpts integer NOT NULL,
+            fpts_decimal integer NOT NULL,
+            fpts_against integer NOT NULL,
+            fpts_against_decimal integer NOT NULL,
+            roster_id integer NOT NULL
+        )
+        "
+    ).await.unwrap();
+
+    con.batch_execute(
+        "
+        CREATE TABLE IF NOT EXISTS players (
+           id varchar(64) PRIMARY KEY,
+           first_name varchar(64) NOT NULL,
+           last_name varchar(64) NOT NULL,
+           team varchar(64),
+           position varchar(64),
+           status varchar(64)
+        )
+        "
+    ).await.unwrap();
+
+    con.batch_execute(
+        "
+        CREATE TABLE IF NOT EXISTS ownership (
+            user_id varchar(64) NOT NULL,
+            league_id varchar(64) NOT NULL,
+            player_id varchar(64) NOT NULL,
+            starter integer,
+            PRIMARY KEY (user_id, league_id, player_id)
+        )
+        "
+    ).await.unwrap();
+
+    con.batch_execute(
+        "
+        CREATE TABLE IF NOT EXISTS state (
+            season integer NOT NULL,
+            week integer NOT NULL,
+            league_season integer NOT NULL,
+            display_week integer NOT NULL,
+            season_type varchar(64) NOT NULL,
+            PRIMARY KEY (season, week)
+        )
+        "
+    ).await.unwrap();
+
+    con.batch_execute(
+        "
+        CREATE TABLE IF NOT EXISTS matchups (
+            season integer NOT NULL,
+            week integer NOT NULL,
+            league_id varchar(64) NOT NULL,
+            user_id varchar(64) NOT NULL,
+            opponent_id varchar(64) NOT NULL,
+            points real NOT NULL,
+            PRIMARY KEY (season, week, league_id, user_id, opponent_id)
+        )
+        "
+    ).await.unwrap();
+
+    con.batch_execute(
+        "
+        CREATE TABLE IF NOT EXISTS scores (
+            player_id varchar(64) NOT NULL,
+            league_id varchar(64) NOT NULL,
+            season integer NOT NULL,
+            week integer NOT NULL,
+            points real NOT NULL,
+            PRIMARY KEY (player_id, league_id, season, week)
+        )
+        "
+    ).await.unwrap();
+
+    // The leagues that make up the big league, seeded from the config
+    con.batch_execute(
+        "
+        CREATE TABLE IF NOT EXISTS managed_leagues (
+            league_id varchar(64) PRIMARY KEY,
+            added_at timestamptz NOT NULL DEFAULT NOW()
+        )
+        "
+    ).await.unwrap();
+
+    con.batch_execute(
+        "
+        CREATE TABLE IF NOT EXISTS accounts (
+            id serial PRIMARY KEY,
+            username varchar(64) UNIQUE NOT NULL,
+            password_hash text NOT NULL,
+            role varchar(16) NOT NULL,
+            created_at timestamptz NOT NULL DEFAULT NOW()
+        )
+        "
+    ).await.unwrap();
+
+    con.batch_execute(
+        "
+        CREATE TABLE IF NOT EXISTS sessions (
+            token varchar(64) PRIMARY KEY,
+            account_id integer NOT NULL REFERENCES accounts ON DELETE CASCADE,
+            csrf_token varchar(64) NOT NULL,
+            expires_at timestamptz NOT NULL
+        )
+        "
+    ).await.unwrap();
+
+    // Corrections made by commissioners, kept after they're removed
+    // so there is a record of who changed what
+    con.batch_execute(
+        "
+        CREATE TABLE IF NOT EXISTS overrides (
+            id serial PRIMARY KEY,
+            kind varchar(16) NOT NULL,
+            user_id varchar(64) NOT NULL,
+            season integer,
+            week integer,
+            points real,
+            result varchar(8),
+            seed integer,
+            reason text NOT NULL,
+            created_by varchar(64) NOT NULL,
+            created_at timestamptz NOT NULL DEFAULT NOW(),
+            removed_by varchar(64),
+            removed_at timestamptz
+        )
+        "
+    ).await.unwrap();
+
+    // Trades, waivers and free agent moves, with the players and
+    // draft picks that changed hands in them
+    con.batch_execute(
+        "
+        CREATE TABLE IF NOT EXISTS transactions (
+            id varchar(64) PRIMARY KEY,
+            league_id varchar(64) NOT NULL,
+            season integer NOT NULL,
+            week integer NOT NULL,
+            kind varchar(32) NOT NULL,
+            status varchar(32) NOT NULL,
+            creator_id varchar(64),
+            waiver_bid integer,
+            created_at timestamptz NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS transactions_league_id ON transactions (league_id, season);
+        "
+    ).await.unwrap();
+
+    con.batch_execute(
+        "
+        CREATE TABLE IF NOT EXISTS transaction_players (
+            transaction_id varchar(64) NOT NULL,
+            player_id varchar(64) NOT NULL,
+            action varchar(8) NOT NULL,
+            user_id varchar(64) NOT NULL,
+            PRIMARY KEY (transaction_id, player_id, action)
+        );
+        CREATE INDEX IF NOT EXISTS transaction_players_user_id ON transaction_players (user_id);
+        "
+    ).await.unwrap();
+
+    // original_user_id is whose pick it was to begin with
+    con.batch_execute(
+        "
+        CREATE TABLE IF NOT EXISTS transaction_picks (
+            transaction_id varchar(64) NOT NULL,
+            season integer NOT NULL,
+            round integer NOT NULL,
+            original_user_id varchar(64) NOT NULL,
+            previous_user_id varchar(64) NOT NULL,
+            user_id varchar(64) NOT NULL,
+            PRIMARY KEY (transaction_id, season, round, original_user_id)
+        )
+        "
+    ).await.unwrap();
+
+    // The lineup slots each league starts, in Sleeper's order,
+    // including bench and reserve slots
+    con.batch_execute(
+        "
+        CREATE TABLE IF NOT EXISTS roster_positions (
+            league_id varchar(64) NOT NULL,
+            slot integer NOT NULL,
+            position varchar(32) NOT NULL,
+            PRIMARY KEY (league_id, slot)
+        )
+        "
+    ).await.unwrap();
+
+    // The rest of each league's settings that matter for comparing
+    // leagues, trade_deadline is a week and waiver_type is Sleeper's
+    // 0 (rolling), 1 (reverse standings) or 2 (FAAB)
+    con.batch_execute(
+        "
+        CREATE TABLE IF NOT EXISTS league_settings (
+            league_id varchar(64) PRIMARY KEY,
+            total_rosters integer,
+            playoff_teams integer,
+            trade_deadline integer,
+            waiver_type integer
+        )
+        "
+    ).await.unwrap();
+
+    // Points each league gives per stat, e.g. rec or pass_td
+    con.batch_execute(
+        "
+        CREATE TABLE IF NOT EXISTS scoring_settings (
+            league_id varchar(64) NOT NULL,
+            stat varchar(64) NOT NULL,
+            points double precision NOT NULL,
+            PRIMARY KEY (league_id, stat)
+        )
+        "
+    ).await.unwrap();
+
+    // Each player's stats for a week from Sleeper, stats that are
+    // still zero are left out
+    con.batch_execute(
+        "
+        CREATE TABLE IF NOT EXISTS player_stats (
+            season integer NOT NULL,
+            week integer NOT NULL,
+            player_id varchar(64) NOT NULL,
+            stat varchar(64) NOT NULL,
+            value double precision NOT NULL,
+            PRIMARY KEY (season, week, player_id, stat)
+        )
+        "
+    ).await.unwrap();
+
+    // The big league's own scoring from the config, empty unless
+    // bigleague.scoring is set
+    con.batch_execute(
+        "
+        CREATE TABLE IF NOT EXISTS normalized_scoring (
+            stat varchar(64) PRIMARY KEY,
+            points double precision NOT NULL
+        )
+        "
+    ).await.unwrap();
+
+    // Everyone on each roster every week and whether they started,
+    // points are in scores
+    con.batch_execute(
+        "
+        CREATE TABLE IF NOT EXISTS lineups (
+            season integer NOT NULL,
+            week integer NOT NULL,
+            league_id varchar(64) NOT NULL,
+            user_id varchar(64) NOT NULL,
+            player_id varchar(64) NOT NULL,
+            starter boolean NOT NULL,
+            PRIMARY KEY (season, week, league_id, player_id)
+        );
+        CREATE INDEX IF NOT EXISTS lineups_user_id ON lineups (user_id, season);
+        "
+    ).await.unwrap();
+
+    // Each league's drafts and every pick made in them, rounds and
+    // teams come from the draft's settings
+    con.batch_execute(
+        "
+        CREATE TABLE IF NOT EXISTS drafts (
+            id varchar(64) PRIMARY KEY,
+            league_id varchar(64) NOT NULL,
+            season integer NOT NULL,
+            kind varchar(32) NOT NULL,
+            status varchar(32) NOT NULL,
+            rounds integer NOT NULL,
+            teams integer NOT NULL,
+            start_time timestamptz
+        );
+        CREATE INDEX IF NOT EXISTS drafts_league_id ON drafts (league_id, season);
+        "
+    ).await.unwrap();
+
+    con.batch_execute(
+        "
+        CREATE TABLE IF NOT EXISTS draft_picks (
+            draft_id varchar(64) NOT NULL,
+            pick_no integer NOT NULL,
+            round integer NOT NULL,
+            draft_slot integer NOT NULL,
+            player_id varchar(64) NOT NULL,
+            user_id varchar(64),
+            amount integer,
+            is_keeper boolean NOT NULL,
+            PRIMARY KEY (draft_id, pick_no)
+        );
+        CREATE INDEX IF NOT EXISTS draft_picks_user_id ON draft_picks (user_id);
+        "
+    ).await.unwrap();
+
+    // Every change ingestion makes to these tables is logged, tagged
+    // with the job that made it (see get_job_con). Players and scores change
+    // too often to be worth keeping track of.
+    con.batch_execute(
+        "
+        CREATE TABLE IF NOT EXISTS change_log (
+            id bigserial PRIMARY KEY,
+            table_name varchar(64) NOT NULL,
+            operation varchar(8) NOT NULL,
+            job varchar(64),
+            league_id varchar(64),
+            user_id varchar(64),
+            season integer,
+            week integer,
+            old_values jsonb,
+            new_values jsonb,
+            changed_at timestamptz NOT NULL DEFAULT NOW()
+        );
+        CREATE INDEX IF NOT EXISTS change_log_changed_at ON change_log (changed_at);
+        CREATE INDEX IF NOT EXISTS change_log_user_id ON change_log (user_id);
+        CREATE INDEX IF NOT EXISTS change_log_league_id ON change_log (league_id);
+        "
+    ).await.unwrap();
+
+    // Updates only keep the columns that changed, and upserts that
+    // didn't change anything aren't logged at all
+    con.batch_execute(
+        "
+        CREATE OR REPLACE FUNCTION log_change() RETURNS trigger AS $$
+        DECLARE
+            row_values jsonb;
+            old_values jsonb;
+            new_values jsonb;
+        BEGIN
+            IF TG_OP = 'UPDATE' THEN
+                row_values := TO_JSONB(NEW);
+                SELECT JSONB_OBJECT_AGG(O.KEY, O.VALUE), JSONB_OBJECT_AGG(O.KEY, N.VALUE)
+                INTO old_values, new_values
+                FROM JSONB_EACH(TO_JSONB(OLD)) O
+                JOIN JSONB_EACH(row_values) N ON N.KEY = O.KEY
+                WHERE O.VALUE <> N.VALUE;
+                IF old_values IS NULL THEN
+                    RETURN NULL;
+                END IF;
+            ELSIF TG_OP = 'INSERT' THEN
+                row_values := TO_JSONB(NEW);
+                new_values := row_values;
+            ELSE
+                row_values := TO_JSONB(OLD);
+                old_values := row_values;
+            END IF;
+
+            INSERT INTO CHANGE_LOG (TABLE_NAME, OPERATION, JOB, LEAGUE_ID, USER_ID, SEASON, WEEK, OLD_VALUES, NEW_VALUES)
+            VALUES (
+                TG_TABLE_NAME,
+                TG_OP,
+                NULLIF(CURRENT_SETTING('bigleague.job', TRUE), ''),
+                CASE TG_TABLE_NAME WHEN 'leagues' THEN row_values->>'id' ELSE row_values->>'league_id' END,
+                CASE TG_TABLE_NAME WHEN 'users' THEN row_values->>'id' ELSE row_values->>'user_id' END,
+                (row_values->>'season')::INTEGER,
+                (row_values->>'week')::INTEGER,
+                old_values,
+                new_values
+            );
+            RETURN NULL;
+        END;
+        $$ LANGUAGE plpgsql;
+        "
+    ).await.unwrap();
+
+    for table in LOGGED_TABLES {
+        con.batch_execute(&format!(
+            "
+            DROP TRIGGER IF EXISTS {table}_change_log ON {table};
+            CREATE TRIGGER {table}_change_log
+                AFTER INSERT OR UPDATE OR DELETE ON {table}
+                FOR EACH ROW EXECUTE FUNCTION log_change();
+            "
+        )).await.unwrap();
+    }
+
+    // Matchups with points overrides applied, result is only set when
+    // a commissioner decided the week for either side
+    con.batch_execute(
+        "
+        CREATE OR REPLACE VIEW adjusted_matchups AS
+            SELECT M.SEASON,
+                M.WEEK,
+                M.LEAGUE_ID,
+                M.USER_ID,
+                M.OPPONENT_ID,
+                COALESCE(P.POINTS, M.POINTS) AS POINTS,
+                M.POINTS AS INGESTED_POINTS,
+                COALESCE(R.RESULT, CASE O.RESULT WHEN 'win' THEN 'loss' WHEN 'loss' THEN 'win' ELSE O.RESULT END) AS RESULT
+            FROM MATCHUPS M
+            LEFT JOIN OVERRIDES P ON P.KIND = 'points' AND P.REMOVED_AT IS NULL
+                AND P.USER_ID = M.USER_ID AND P.SEASON = M.SEASON AND P.WEEK = M.WEEK
+            LEFT JOIN OVERRIDES R ON R.KIND = 'result' AND R.REMOVED_AT IS NULL
+                AND R.USER_ID = M.USER_ID AND R.SEASON = M.SEASON AND R.WEEK = M.WEEK
+            LEFT JOIN OVERRIDES O ON O.KIND = 'result' AND O.REMOVED_AT IS NULL
+                AND O.USER_ID = M.OPPONENT_ID AND O.SEASON = M.SEASON AND O.WEEK = M.WEEK
+        "
+    ).await.unwrap();
+
+    // Rosters with the records and points Sleeper gave them corrected
+    // by any overridden weeks. Only weeks Sleeper has already counted
+    // (a team's games played) are corrected, so playoff weeks are left
+    // out of the record.
+    con.batch_execute(
+        "
+        CREATE OR REPLACE VIEW adjusted_rosters AS
+            SELECT USER_ID,
+                LEAGUE_ID,
+                WINS,
+                LOSSES,
+                TIES,
+                FPTS_CENTS / 100 AS FPTS,
+                FPTS_CENTS % 100 AS FPTS_DECIMAL,
+                FPTS_AGAINST_CENTS / 100 AS FPTS_AGAINST,
+                FPTS_AGAINST_CENTS % 100 AS FPTS_AGAINST_DECIMAL,
+                ROSTER_ID
+            FROM (
+                SELECT R.USER_ID,
+                    R.LEAGUE_ID,
+                    R.WINS + COALESCE(C.WINS, 0)::INTEGER AS WINS,
+                    R.LOSSES + COALESCE(C.LOSSES, 0)::INTEGER AS LOSSES,
+                    R.TIES + COALESCE(C.TIES, 0)::INTEGER AS TIES,
+                    R.FPTS * 100 + R.FPTS_DECIMAL + ROUND(COALESCE(C.FPTS, 0) * 100)::INTEGER AS FPTS_CENTS,
+                    R.FPTS_AGAINST * 100 + R.FPTS_AGAINST_DECIMAL + ROUND(COALESCE(C.FPTS_AGAINST, 0) * 100)::INTEGER AS FPTS_AGAINST_CENTS,
+                    R.ROSTER_ID
+                FROM ROSTERS R
+                LEFT JOIN (
+                    SELECT USER_ID,
+                        SUM((NEW = 1)::INTEGER - (OLD = 1)::INTEGER) AS WINS,
+                        SUM((NEW = -1)::INTEGER - (OLD = -1)::INTEGER) AS LOSSES,
+                        SUM((NEW = 0)::INTEGER - (OLD = 0)::INTEGER) AS TIES,
+                        SUM(POINTS - INGESTED_POINTS) AS FPTS,
+                        SUM(OPPONENT_POINTS - OPPONENT_INGESTED_POINTS) AS FPTS_AGAINST
+                    FROM (
+                        SELECT M1.USER_ID,
+                            M1.POINTS,
+                            M1.INGESTED_POINTS,
+                            M2.POINTS AS OPPONENT_POINTS,
+                            M2.INGESTED_POINTS AS OPPONENT_INGESTED_POINTS,
+                            SIGN(M1.INGESTED_POINTS - M2.INGESTED_POINTS) AS OLD,
+                            CASE M1.RESULT
+                                WHEN 'win' THEN 1
+                                WHEN 'loss' THEN -1
+                                WHEN 'tie' THEN 0
+                                ELSE SIGN(M1.POINTS - M2.POINTS)
+                            END AS NEW
+                        FROM ADJUSTED_MATCHUPS M1
+                        JOIN ADJUSTED_MATCHUPS M2 ON M1.OPPONENT_ID = M2.USER_ID
+                            AND M1.SEASON = M2.SEASON
+                            AND M1.WEEK = M2.WEEK
+                        JOIN ROSTERS ON ROSTERS.USER_ID = M1.USER_ID
+                        WHERE M1.SEASON = (SELECT MAX(SEASON) FROM STATE)
+                            AND M1.WEEK <= ROSTERS.WINS + ROSTERS.LOSSES + ROSTERS.TIES
+                    ) WEEKS
+                    GROUP BY USER_ID
+                ) C ON C.USER_ID = R.USER_ID
+            ) TOTALS
+        "
+    ).await.unwrap();
+
+    // Matchups with each side's starters rescored under the big
+    // league's scoring. Weeks without stats yet, and points that were
+    // overridden, keep their adjusted points, so this is the same as
+    // adjusted_matchups when normalized_scoring is empty.
+    con.batch_execute(
+        "
+        CREATE OR REPLACE VIEW normalized_matchups AS
+            SELECT M.SEASON,
+                M.WEEK,
+                M.LEAGUE_ID,
+                M.USER_ID,
+                M.OPPONENT_ID,
+                CASE WHEN M.POINTS <> M.INGESTED_POINTS THEN M.POINTS
+                    ELSE COALESCE(N.POINTS::REAL, M.POINTS)
+                END AS POINTS,
+                M.INGESTED_POINTS,
+                M.RESULT
+            FROM ADJUSTED_MATCHUPS M
+            LEFT JOIN (
+                SELECT L.SEASON,
+                    L.WEEK,
+                    L.LEAGUE_ID,
+                    L.USER_ID,
+                    SUM(S.VALUE * R.POINTS) AS POINTS
+                FROM LINEUPS L
+                JOIN PLAYER_STATS S ON S.SEASON = L.SEASON
+                    AND S.WEEK = L.WEEK
+                    AND S.PLAYER_ID = L.PLAYER_ID
+                JOIN NORMALIZED_SCORING R ON R.STAT = S.STAT
+                WHERE L.STARTER
+                GROUP BY L.SEASON, L.WEEK, L.LEAGUE_ID, L.USER_ID
+            ) N ON N.SEASON = M.SEASON
+                AND N.WEEK = M.WEEK
+                AND N.LEAGUE_ID = M.LEAGUE_ID
+                AND N.USER_ID = M.USER_ID
+        "
+    ).await.unwrap();
+
+    // Seed overrides take their spot and everyone else fills in the
+    // open spots in the order of their record. With the big league's
+    // own scoring set, points for are rescored under it for the
+    // weeks each team has played this season.
+    con.batch_execute(
+        "
+        CREATE OR REPLACE VIEW ranks AS
+            WITH NORMALIZED AS (
+                SELECT M.USER_ID, SUM(M.POINTS)::FLOAT8 AS NORMALIZED_FPTS
+                FROM NORMALIZED_MATCHUPS M
+                JOIN ROSTERS ON ROSTERS.USER_ID = M.USER_ID
+                WHERE M.SEASON = (SELECT MAX(SEASON) FROM STATE)
+                    AND M.WEEK <= ROSTERS.WINS + ROSTERS.LOSSES + ROSTERS.TIES
+                    AND EXISTS (SELECT 1 FROM NORMALIZED_SCORING)
+                GROUP BY M.USER_ID
+            ),
+            STANDINGS AS (
+                SELECT adjusted_rosters.user_id, ROW_NUMBER() OVER (ORDER BY wins DESC, NORMALIZED_FPTS DESC NULLS LAST, fpts DESC, fpts_decimal DESC, fpts_against DESC, fpts_against_decimal DESC) as rank
+                FROM adjusted_rosters
+                JOIN users ON adjusted_rosters.user_id = users.id
+                LEFT JOIN NORMALIZED ON NORMALIZED.USER_ID = adjusted_rosters.user_id
+            ),
+            SEEDS AS (
+                SELECT OVERRIDES.USER_ID, OVERRIDES.SEED
+                FROM OVERRIDES, STANDINGS
+                WHERE OVERRIDES.USER_ID = STANDINGS.USER_ID
+                    AND OVERRIDES.KIND = 'seed'
+                    AND OVERRIDES.REMOVED_AT IS NULL
+                    AND OVERRIDES.SEED <= (SELECT COUNT(*) FROM STANDINGS)
+            ),
+            UNSEEDED AS (
+                SELECT USER_ID, ROW_NUMBER() OVER (ORDER BY RANK) AS N
+                FROM STANDINGS
+                WHERE USER_ID NOT IN (SELECT USER_ID FROM SEEDS)
+            ),
+            SLOTS AS (
+                SELECT SLOT, ROW_NUMBER() OVER (ORDER BY SLOT) AS N
+                FROM GENERATE_SERIES(1, (SELECT COUNT(*) FROM STANDINGS)) AS SLOT
+                WHERE SLOT NOT IN (SELECT SEED FROM SEEDS)
+            )
+            SELECT SEEDS.USER_ID, SEED::BIGINT AS RANK, NORMALIZED_FPTS
+            FROM SEEDS
+            LEFT JOIN NORMALIZED ON NORMALIZED.USER_ID = SEEDS.USER_ID
+            UNION ALL
+            SELECT UNSEEDED.USER_ID, SLOTS.SLOT AS RANK, NORMALIZED_FPTS
+            FROM UNSEEDED
+            JOIN SLOTS ON UNSEEDED.N = SLOTS.N
+            LEFT JOIN NORMALIZED ON NORMALIZED.USER_ID = UNSEEDED.USER_ID
+        "
+    ).await.unwrap();
+    Ok(())
+}
+
+// Fails until the state has been fetched, see get_time_period_if_fetched
+pub async fn get_time_period(con: &DBCon) -> Result<(i32, i32), error::Error> {
+    get_time_period_if_fetched(con)
+        .await?
+        .ok_or_else(|| error::Error::NotFound(String::from("current season")))
+}
+
+// The current season and week, None until the state has been fetched
+pub async fn get_time_period_if_fetched(con: &DBCon) -> Result<Option<(i32, i32)>, tokio_postgres::Error> {
+    Ok(con.query_opt("
+            SELECT SEASON,
+                WEEK
+            FROM STATE
+            ORDER BY SEASON DESC,
+                WEEK DESC
+            LIMIT 1
+              ",
+              &[])
+        .await?
+        .map(|time| (time.get("season"), time.get("week"))))
+}
+
+// Everything create_tables is expected to have made, other than
+// accounts and sessions which are kept out of exports
+pub const TABLES: [&str; 23] = [
+    "managed_leagues",
+    "leagues",
+    "users",
+    "rosters",
+    "players",
+    "ownership",
+    "state",
+    "matchups",
+    "scores",
+    "ranks",
+    "overrides",
+    "change_log",
+    "transactions",
+    "transaction_players",
+    "transaction_picks",
+    "drafts",
+    "draft_picks",
+    "lineups",
+    "roster_positions",
+    "league_settings",
+    "scoring_settings",
+    "player_stats",
+    "normalized_scoring",
+];
+
+pub async fn get_missing_tables(con: &DBCon) -> Result<Vec<String>, tokio_postgres::Error> {
+    let found: Vec<String> = con.query("
+            SELECT TABLE_NAME::TEXT AS NAME
+            FROM INFORMATION_SCHEMA.TABLES
+            WHERE TABLE_SCHEMA = CURRENT_SCHEMA()
+              ",
+              &[])
+        .await?
+        .iter()
+        .map(|row| row.get("name"))
+        .collect();
+
+    Ok(TABLES
+        .iter()
+        .filter(|t| !found.iter().any(|f| f == *t))
+        .map(|t| t.to_string())
+        .collect())
+}
+
+pub async fn has_state(con: &DBCon) -> Result<bool, tokio_postgres::Error> {
+    let row = con.query_one("SELECT EXISTS (SELECT 1 FROM STATE) AS FETCHED", &[]).await?;
+    Ok(row.get("fetched"))
+}
+
+// Dumps a table as a JSON array of its rows
+pub async fn export_table(con: &DBCon, table: &str) -> Result<serde_json::Value, error::Error> {
+    if !TABLES.contains(&table) {
+        return Err(error::Error::BadRequest(format!("unknown table {}", table)));
+    }
+
+    let row = con.query_one(
+            &format!("SELECT COALESCE(JSON_AGG(T), '[]')::TEXT AS ROWS FROM {} T", table),
+            &[])
+        .await
+        .map_err(error::Error::Database)?;
+    let rows: String = row.get("rows");
+    Ok(serde_json::from_str(&rows)?)
+}
+
+// league_id -> number of teams
+pub async fn get_league_sizes(con: &DBCon) -> Result<HashMap<String, i64>, tokio_postgres::Error> {
+    Ok(con.query("
+            SELECT LEAGUE_ID,
+                COUNT(*) AS TEAMS
+            FROM ROSTERS
+            GROUP BY LEAGUE_ID
+              ",
+              &[])
+        .await?
+        .iter()
+        .map(|row| (row.get("league_id"), row.get("teams")))
+        .collect())
+}
+
+pub async fn get_managed_leagues(con: &DBCon) -> Result<Vec<ManagedLeague>, tokio_postgres::Error> {
+    Ok(con.query("
+            SELECT MANAGED_LEAGUES.LEAGUE_ID,
+                LEAGUES.NAME,
+                MANAGED_LEAGUES.ADDED_AT
+            FROM MANAGED_LEAGUES
+            LEFT JOIN LEAGUES ON LEAGUES.ID = MANAGED_LEAGUES.LEAGUE_ID
+            ORDER BY MANAGED_LEAGUES.ADDED_AT,
+                MANAGED_LEAGUES.LEAGUE_ID
+              ",
+              &[])
+        .await?
+        .iter()
+        .map(|row| ManagedLeague {
+            id: row.get("league_id"),
+            name: row.get("name"),
+            added_at: row.get("added_at"),
+        })
+        .collect())
+}
+
+// Returns false when the league was already there
+pub async fn add_managed_league(con: &DBCon, league_id: &str) -> Result<bool, tokio_postgres::Error> {
+    let added = con.execute("
+            INSERT INTO MANAGED_LEAGUES (LEAGUE_ID) VALUES ($1)
+            ON CONFLICT DO NOTHING
+              ",
+              &[&league_id])
+        .await?;
+    Ok(added > 0)
+}
+
+// Removes the league along with everything ingested for it
+pub async fn remove_managed_league(con: &mut DBCon, league_id: &str) -> Result<bool, tokio_postgres::Error> {
+    let transaction = con.transaction().await?;
+    transaction.execute("SELECT SET_CONFIG('bigleague.job', 'remove league', TRUE)", &[]).await?;
+    let removed = transaction.execute("DELETE FROM MANAGED_LEAGUES WHERE LEAGUE_ID = $1", &[&league_id]).await?;
+    for table in ["transaction_players", "transaction_picks"] {
+        transaction.execute(
+            &format!("DELETE FROM {} WHERE TRANSACTION_ID IN (SELECT ID FROM TRANSACTIONS WHERE LEAGUE_ID = $1)", table),
+            &[&league_id]).await?;
+    }
+    transaction.execute(
+        "DELETE FROM DRAFT_PICKS WHERE DRAFT_ID IN (SELECT ID FROM DRAFTS WHERE LEAGUE_ID = $1)",
+        &[&league_id]).await?;
+    for table in ["drafts", "transactions", "lineups", "roster_positions", "league_settings", "scoring_settings", "ownership", "scores", "matchups", "rosters"] {
+        transaction.execute(&format!("DELETE FROM {} WHERE LEAGUE_ID = $1", table), &[&league_id]).await?;
+    }
+    transaction.execute("DELETE FROM LEAGUES WHERE ID = $1", &[&league_id]).await?;
+    transaction.commit().await?;
+    Ok(removed > 0)
+}
+
+// Replaces the big league's scoring with the config's, clearing it
+// when the config doesn't have any
+pub async fn set_normalized_scoring(con: &mut DBCon, scoring: &Option<HashMap<String, f64>>) -> Result<(), tokio_postgres::Error> {
+    let transaction = con.transaction().await?;
+    transaction.execute("DELETE FROM NORMALIZED_SCORING", &[]).await?;
+    for (stat, points) in scoring.iter().flatten() {
+        transaction.execute("INSERT INTO NORMALIZED_SCORING VALUES ($1, $2)", &[stat, points]).await?;
+    }
+    transaction.commit().await?;
+    Ok(())
+}
+
+// Weeks that have lineups but no player stats yet
+pub async fn get_weeks_missing_stats(con: &DBCon) -> Result<Vec<(i32, i32)>, tokio_postgres::Error> {
+    Ok(con.query("
+            SELECT DISTINCT SEASON, WEEK
+            FROM LINEUPS
+            WHERE NOT EXISTS (
+                SELECT 1 FROM PLAYER_STATS
+                WHERE PLAYER_STATS.SEASON = LINEUPS.SEASON
+                    AND PLAYER_STATS.WEEK = LINEUPS.WEEK
+            )
+            ORDER BY SEASON, WEEK
+              ",
+              &[])
+        .await?
+        .iter()
+        .map(|row| (row.get("season"), row.get("week")))
+        .collect())
+}
+
+// The config only fills in the leagues the first time around, after
+// that they're managed from the admin page
+pub async fn seed_managed_leagues(con: &DBCon, leagues: &[String]) -> Result<(), tokio_postgres::Error> {
+    let seeded = con.query_one("SELECT EXISTS (SELECT 1 FROM MANAGED_LEAGUES) AS SEEDED", &[]).await?;
+    if seeded.get("seeded") {
+        return Ok(());
+    }
+    info!("seeding managed leagues from config");
+    for league_id in leagues {
+        add_managed_league(con, league_id).await?;
+    }
+    Ok(())
+}
+
+pub async fn get_account_login(con: &DBCon, username: &str) -> Result<Option<(Account, String)>, tokio_postgres::Error> {
+    let row = con.query_opt("
+            SELECT ID,
+                USERNAME,
+                ROLE,
+                PASSWORD_HASH
+            FROM ACCOUNTS
+            WHERE USERNAME = $1
+              ",
+              &[&username])
+        .await?;
+    Ok(row.map(|row| (account_from_row(&row), row.get("password_hash"))))
+}
+
+pub async fn get_accounts(con: &DBCon) -> Result<Vec<Account>, tokio_postgres::Error> {
+    Ok(con.query("SELECT ID, USERNAME, ROLE FROM ACCOUNTS ORDER BY USERNAME", &[])
+        .await?
+        .iter()
+        .map(account_from_row)
+        .collect())
+}
+
+// Adds the account, or replaces the password and role of an existing one
+pub async fn upsert_account(con: &DBCon, username: &str, password_hash: &str, role: &str) -> Result<(), tokio_postgres::Error> {
+    con.execute("
+            INSERT INTO ACCOUNTS (USERNAME, PASSWORD_HASH, ROLE) VALUES ($1, $2, $3)
+            ON CONFLICT (USERNAME) DO UPDATE SET
+                PASSWORD_HASH = EXCLUDED.PASSWORD_HASH,
+                ROLE = EXCLUDED.ROLE
+              ",
+              &[&username, &password_hash, &role])
+        .await?;
+    // Changing the password logs the account out everywhere
+    con.execute("
+            DELETE FROM SESSIONS
+            WHERE ACCOUNT_ID = (SELECT ID FROM ACCOUNTS WHERE USERNAME = $1)
+              ",
+              &[&username])
+        .await?;
+    Ok(())
+}
+
+pub async fn remove_account(con: &DBCon, username: &str) -> Result<bool, tokio_postgres::Error> {
+    Ok(con.execute("DELETE FROM ACCOUNTS WHERE USERNAME = $1", &[&username]).await? > 0)
+}
+
+pub async fn create_session(con: &DBCon, token: &str, account_id: i32, csrf_token: &str, expires_at: DateTime<Utc>) -> Result<(), tokio_postgres::Error> {
+    con.execute("DELETE FROM SESSIONS WHERE EXPIRES_AT < NOW()", &[]).await?;
+    con.execute("
+            INSERT INTO SESSIONS (TOKEN, ACCOUNT_ID, CSRF_TOKEN, EXPIRES_AT) VALUES ($1, $2, $3, $4)
+              ",
+              &[&token, &account_id, &csrf_token, &expires_at])
+        .await?;
+    Ok(())
+}
+
+// The account logged in with the token and its CSRF token
+pub async fn get_session(con: &DBCon, token: &str) -> Result<Option<(Account, String)>, tokio_postgres::Error> {
+    let row = con.query_opt("
+            SELECT ACCOUNTS.ID,
+                ACCOUNTS.USERNAME,
+                ACCOUNTS.ROLE,
+                SESSIONS.CSRF_TOKEN
+            FROM SESSIONS
+            JOIN ACCOUNTS ON ACCOUNTS.ID = SESSIONS.ACCOUNT_ID
+            WHERE SESSIONS.TOKEN = $1
+                AND SESSIONS.EXPIRES_AT > NOW()
+              ",
+              &[&token])
+        .await?;
+    Ok(row.map(|row| (account_from_row(&row), row.get("csrf_token"))))
+}
+
+pub async fn delete_session(con: &DBCon, token: &str) -> Result<(), tokio_postgres::Error> {
+    con.execute("DELETE FROM SESSIONS WHERE TOKEN = $1", &[&token]).await?;
+    Ok(())
+}
+
+fn account_from_row(row: &Row) -> Account {
+    Account {
+        id: row.get("id"),
+        username: row.get("username"),
+        role: row.get("role"),
+    }
+}
+
+// A connection whose changes are labelled with the job making them
+// in the change log. The label sticks to the connection, so it's
+// reset before the connection goes back to the pool.
+pub struct JobCon(Option<DBCon>);
+
+pub async fn get_job_con(db_pool: &DBPool, job: &str) -> Result<JobCon, error::Error> {
+    let con = get_db_con(db_pool).await?;
+    con.execute("SELECT SET_CONFIG('bigleague.job', $1, FALSE)", &[&job]).await?;
+    Ok(JobCon(Some(con)))
+}
+
+impl Deref for JobCon {
+    type Target = DBCon;
+
+    fn deref(&self) -> &DBCon {
+        self.0.as_ref().expect("job connection used after being returned")
+    }
+}
+
+impl DerefMut for JobCon {
+    fn deref_mut(&mut self) -> &mut DBCon {
+        self.0.as_mut().expect("job connection used after being returned")
+    }
+}
+
+impl Drop for JobCon {
+    fn drop(&mut self) {
+        if let Some(con) = self.0.take() {
+            tokio::spawn(async move {
+                if let Err(e) = con.batch_execute("RESET bigleague.job").await {
+                    error!("couldn't reset the job on a connection: {}", e);
+                }
+            });
+        }
+    }
+}
+
+// The most recent changes matching every filter that is set
+pub async fn get_changes(con: &DBCon, filter: &ChangeFilter, limit: i64) -> Result<Vec<Change>, error::Error> {
+    con.query("
+            SELECT CHANGE_LOG.ID,
+                CHANGE_LOG.TABLE_NAME,
+                CHANGE_LOG.OPERATION,
+                CHANGE_LOG.JOB,
+                CHANGE_LOG.LEAGUE_ID,
+                LEAGUES.NAME AS LEAGUE_NAME,
+                CHANGE_LOG.USER_ID,
+                USERS.NAME AS USER_NAME,
+                CHANGE_LOG.SEASON,
+                CHANGE_LOG.WEEK,
+                CHANGE_LOG.OLD_VALUES::TEXT AS OLD_VALUES,
+                CHANGE_LOG.NEW_VALUES::TEXT AS NEW_VALUES,
+                CHANGE_LOG.CHANGED_AT
+            FROM CHANGE_LOG
+            LEFT JOIN LEAGUES ON LEAGUES.ID = CHANGE_LOG.LEAGUE_ID
+            LEFT JOIN USERS ON USERS.ID = CHANGE_LOG.USER_ID
+            WHERE ($1::TEXT IS NULL OR CHANGE_LOG.USER_ID = $1)
+                AND ($2::TEXT IS NULL OR CHANGE_LOG.LEAGUE_ID = $2)
+                AND ($3::INTEGER IS NULL OR CHANGE_LOG.SEASON = $3)
+                AND ($4::INTEGER IS NULL OR CHANGE_LOG.WEEK = $4)
+                AND ($5::TEXT IS NULL OR CHANGE_LOG.TABLE_NAME = $5)
+            ORDER BY CHANGE_LOG.ID DESC
+            LIMIT $6
+              ",
+              &[&filter.user_id, &filter.league_id, &filter.season, &filter.week, &filter.table, &limit])
+        .await
+        .map_err(error::Error::Database)?
+        .iter()
+        .map(|row| {
+            let values = |column: &str| -> Result<Option<serde_json::Value>, error::Error> {
+                match row.get::<&str, Option<&str>>(column) {
+                    Some(v) => Ok(Some(serde_json::from_str(v)?)),
+                    None => Ok(None),
+                }
+            };
+            Ok(Change {
+                id: row.get("id"),
+                table_name: row.get("table_name"),
+                operation: row.get("operation"),
+                job: row.get("job"),
+                league_id: row.get("league_id"),
+                league_name: row.get("league_name"),
+                user_id: row.get("user_id"),
+                user_name: row.get("user_name"),
+                season: row.get("season"),
+                week: row.get("week"),
+                old_values: values("old_values")?,
+                new_values: values("new_values")?,
+                changed_at: row.get("changed_at"),
+            })
+        })
+        .collect()
+}
+
+pub async fn has_transactions(con: &DBCon, league_id: &str, season: i32) -> Result<bool, tokio_postgres::Error> {
+    let row = con.query_one("
+            SELECT EXISTS (SELECT 1 FROM TRANSACTIONS WHERE LEAGUE_ID = $1 AND SEASON = $2) AS FETCHED
+              ",
+              &[&league_id, &season])
+        .await?;
+    Ok(row.get("fetched"))
+}
+
+// Completed transactions, newest first. Users match any transaction
+// they added, dropped or traded a player or pick in. No limit returns
+// all of them.
+pub async fn get_transactions(con: &DBCon, filter: &TransactionFilter, limit: Option<i64>) -> Result<Vec<Transaction>, tokio_postgres::Error> {
+    let mut transactions: Vec<Transaction> = con.query("
+            SELECT TRANSACTIONS.*,
+                LEAGUES.NAME AS LEAGUE_NAME
+            FROM TRANSACTIONS
+            LEFT JOIN LEAGUES ON LEAGUES.ID = TRANSACTIONS.LEAGUE_ID
+            WHERE TRANSACTIONS.STATUS = 'complete'
+                AND ($1::TEXT IS NULL OR TRANSACTIONS.LEAGUE_ID = $1)
+                AND ($4::INTEGER IS NULL OR TRANSACTIONS.SEASON = $4)
+                AND ($5::INTEGER IS NULL OR TRANSACTIONS.WEEK = $5)
+                AND ($6::TEXT IS NULL OR TRANSACTIONS.KIND = $6)
+                AND ($7::TEXT IS NULL
+                    OR EXISTS (
+                        SELECT 1 FROM TRANSACTION_PLAYERS
+                        JOIN PLAYERS ON PLAYERS.ID = TRANSACTION_PLAYERS.PLAYER_ID
+                        WHERE TRANSACTION_PLAYERS.TRANSACTION_ID = TRANSACTIONS.ID
+                            AND PLAYERS.POSITION = $7
+                    ))
+                AND ($2::TEXT IS NULL
+                    OR EXISTS (
+                        SELECT 1 FROM TRANSACTION_PLAYERS
+                        WHERE TRANSACTION_PLAYERS.TRANSACTION_ID = TRANSACTIONS.ID
+                            AND TRANSACTION_PLAYERS.USER_ID = $2
+                    )
+                    OR EXISTS (
+                        SELECT 1 FROM TRANSACTION_PICKS
+                        WHERE TRANSACTION_PICKS.TRANSACTION_ID = TRANSACTIONS.ID
+                            AND $2 IN (TRANSACTION_PICKS.USER_ID, TRANSACTION_PICKS.PREVIOUS_USER_ID)
+                    ))
+            ORDER BY TRANSACTIONS.CREATED_AT DESC
+            LIMIT $3
+              ",
+              &[&filter.league_id, &filter.user_id, &limit, &filter.season, &filter.week, &filter.kind, &filter.position])
+        .await?
+        .iter()
+        .map(|row| {
+            Transaction {
+                id: row.get("id"),
+                league_id: row.get("league_id"),
+                league_name: row.get("league_name"),
+                season: row.get("season"),
+                week: row.get("week"),
+                kind: row.get("kind"),
+                creator_id: row.get("creator_id"),
+                waiver_bid: row.get("waiver_bid"),
+                created_at: row.get("created_at"),
+                players: vec![],
+                picks: vec![],
+            }
+        })
+        .collect();
+
+    let ids: Vec<String> = transactions.iter().map(|t| t.id.clone()).collect();
+
+    let mut players: HashMap<String, Vec<TransactionPlayer>> = HashMap::new();
+    for row in con.query("
+            SELECT TRANSACTION_PLAYERS.*,
+                PLAYERS.FIRST_NAME,
+                PLAYERS.LAST_NAME,
+                PLAYERS.POSITION,
+                PLAYERS.TEAM,
+                USERS.NAME AS USER_NAME
+            FROM TRANSACTION_PLAYERS
+            LEFT JOIN PLAYERS ON PLAYERS.ID = TRANSACTION_PLAYERS.PLAYER_ID
+            LEFT JOIN USERS ON USERS.ID = TRANSACTION_PLAYERS.USER_ID
+            WHERE TRANSACTION_PLAYERS.TRANSACTION_ID = ANY($1)
+            ORDER BY TRANSACTION_PLAYERS.ACTION ASC, TRANSACTION_PLAYERS.PLAYER_ID ASC
+              ",
+              &[&ids])
+        .await?
+    {
+        players.entry(row.get("transaction_id")).or_default().push(TransactionPlayer {
+            player_id: row.get("player_id"),
+            first_name: row.get("first_name"),
+            last_name: row.get("last_name"),
+            position: row.get("position"),
+            team: row.get("team"),
+            action: row.get("action"),
+            user_id: row.get("user_id"),
+            user_name: row.get("user_name"),
+        });
+    }
+
+    let mut picks: HashMap<String, Vec<TransactionPick>> = HashMap::new();
+    for row in con.query("
+            SELECT TRANSACTION_PICKS.*,
+                ORIGINAL.NAME AS ORIGINAL_USER_NAME,
+                PREVIOUS.NAME AS PREVIOUS_USER_NAME,
+                OWNER.NAME AS USER_NAME
+            FROM TRANSACTION_PICKS
+            LEFT JOIN USERS AS ORIGINAL ON ORIGINAL.ID = TRANSACTION_PICKS.ORIGINAL_USER_ID
+            LEFT JOIN USERS AS PREVIOUS ON PREVIOUS.ID = TRANSACTION_PICKS.PREVIOUS_USER_ID
+            LEFT JOIN USERS AS OWNER ON OWNER.ID = TRANSACTION_PICKS.USER_ID
+            WHERE TRANSACTION_PICKS.TRANSACTION_ID = ANY($1)
+            ORDER BY TRANSACTION_PICKS.SEASON ASC, TRANSACTION_PICKS.ROUND ASC
+              ",
+              &[&ids])
+        .await?
+    {
+        picks.entry(row.get("transaction_id")).or_default().push(TransactionPick {
+            season: row.get("season"),
+            round: row.get("round"),
+            original_user_id: row.get("original_user_id"),
+            original_user_name: row.get("original_user_name"),
+            previous_user_id: row.get("previous_user_id"),
+            previous_user_name: row.get("previous_user_name"),
+            user_id: row.get("user_id"),
+            user_name: row.get("user_name"),
+        });
+    }
+
+    for t in transactions.iter_mut() {
+        t.players = players.remove(&t.id).unwrap_or_default();
+        t.picks = picks.remove(&t.id).unwrap_or_default();
+    }
+    Ok(transactions)
+}
+
+// Players added (or dropped) by the most teams in a week across every
+// league, most moved first. A team moving the same player more than
+// once only counts once.
+pub async fn get_most_moved(con: &DBCon, season: i32, week: i32, action: &str, position: Option<&str>, limit: i64) -> Result<Vec<PlayerMoves>, tokio_postgres::Error> {
+    Ok(con.query("
+            SELECT TRANSACTION_PLAYERS.PLAYER_ID,
+                PLAYERS.FIRST_NAME,
+                PLAYERS.LAST_NAME,
+                PLAYERS.POSITION,
+                PLAYERS.TEAM,
+                COUNT(DISTINCT TRANSACTION_PLAYERS.USER_ID) AS COUNT
+            FROM TRANSACTION_PLAYERS
+            JOIN TRANSACTIONS ON TRANSACTIONS.ID = TRANSACTION_PLAYERS.TRANSACTION_ID
+            LEFT JOIN PLAYERS ON PLAYERS.ID = TRANSACTION_PLAYERS.PLAYER_ID
+            WHERE TRANSACTIONS.STATUS = 'complete'
+                AND TRANSACTIONS.SEASON = $1
+                AND TRANSACTIONS.WEEK = $2
+                AND TRANSACTION_PLAYERS.ACTION = $3
+                AND ($4::TEXT IS NULL OR PLAYERS.POSITION = $4)
+            GROUP BY TRANSACTION_PLAYERS.PLAYER_ID, PLAYERS.FIRST_NAME, PLAYERS.LAST_NAME, PLAYERS.POSITION, PLAYERS.TEAM
+            ORDER BY COUNT DESC, TRANSACTION_PLAYERS.PLAYER_ID ASC
+            LIMIT $5
+              ",
+              &[&season, &week, &action, &position, &limit])
+        .await?
+        .iter()
+        .map(|row| {
+            PlayerMoves {
+                player_id: row.get("player_id"),
+                first_name: row.get("first_name"),
+                last_name: row.get("last_name"),
+                position: row.get("position"),
+                team: row.get("team"),
+                count: row.get("count"),
+            }
+        })
+        .collect())
+}
+
+// Whether a draft's picks have been stored, finished drafts don't
+// need fetching again once they have
+pub async fn has_draft_picks(con: &DBCon, draft_id: &str) -> Result<bool, tokio_postgres::Error> {
+    let row = con.query_one("
+            SELECT EXISTS (SELECT 1 FROM DRAFT_PICKS WHERE DRAFT_ID = $1) AS FETCHED
+              ",
+              &[&draft_id])
+        .await?;
+    Ok(row.get("fetched"))
+}
+
+// Every league's drafts for a season with their picks in order,
+// league_id narrows it down to one league
+pub async fn get_drafts(con: &DBCon, season: i32, league_id: Option<&str>) -> Result<Vec<Draft>, tokio_postgres::Error> {
+    let mut drafts: Vec<Draft> = con.query("
+            SELECT DRAFTS.*,
+                LEAGUES.NAME AS LEAGUE_NAME
+            FROM DRAFTS
+            LEFT JOIN LEAGUES ON LEAGUES.ID = DRAFTS.LEAGUE_ID
+            WHERE DRAFTS.SEASON = $1
+                AND ($2::TEXT IS NULL OR DRAFTS.LEAGUE_ID = $2)
+            ORDER BY LEAGUES.NAME ASC, DRAFTS.START_TIME ASC
+              ",
+              &[&season, &league_id])
+        .await?
+        .iter()
+        .map(|row| {
+            Draft {
+                id: row.get("id"),
+                league_id: row.get("league_id"),
+                league_name: row.get("league_name"),
+                season: row.get("season"),
+                kind: row.get("kind"),
+                status: row.get("status"),
+                rounds: row.get("rounds"),
+                teams: row.get("teams"),
+                start_time: row.get("start_time"),
+                picks: vec![],
+            }
+        })
+        .collect();
+
+    let ids: Vec<String> = drafts.iter().map(|d| d.id.clone()).collect();
+
+    let mut picks: HashMap<String, Vec<DraftPick>> = HashMap::new();
+    for row in con.query("
+            SELECT DRAFT_PICKS.*,
+                PLAYERS.FIRST_NAME,
+                PLAYERS.LAST_NAME,
+                PLAYERS.POSITION,
+                PLAYERS.TEAM,
+                USERS.NAME AS USER_NAME
+            FROM DRAFT_PICKS
+            LEFT JOIN PLAYERS ON PLAYERS.ID = DRAFT_PICKS.PLAYER_ID
+            LEFT JOIN USERS ON USERS.ID = DRAFT_PICKS.USER_ID
+            WHERE DRAFT_PICKS.DRAFT_ID = ANY($1)
+            ORDER BY DRAFT_PICKS.PICK_NO ASC
+              ",
+              &[&ids])
+        .await?
+    {
+        picks.entry(row.get("draft_id")).or_default().push(DraftPick {
+            pick_no: row.get("pick_no"),
+            round: row.get("round"),
+            draft_slot: row.get("draft_slot"),
+            player_id: row.get("player_id"),
+            first_name: row.get("first_name"),
+            last_name: row.get("last_name"),
+            position: row.get("position"),
+            team: row.get("team"),
+            user_id: row.get("user_id"),
+            user_name: row.get("user_name"),
+            amount: row.get("amount"),
+            is_keeper: row.get("is_keeper"),
+        });
+    }
+
+    for d in drafts.iter_mut() {
+        d.picks = picks.remove(&d.id).unwrap_or_default();
+    }
+    Ok(drafts)
+}
+
+// Average draft position across every league's draft for a season,
+// earliest going first. Keepers aren't drafted so they're left out.
+pub async fn get_adp(con: &DBCon, season: i32) -> Result<Vec<Adp>, tokio_postgres::Error> {
+    Ok(con.query("
+            SELECT DRAFT_PICKS.PLAYER_ID,
+                PLAYERS.FIRST_NAME,
+                PLAYERS.LAST_NAME,
+                PLAYERS.POSITION,
+                PLAYERS.TEAM,
+                COUNT(*) AS DRAFTS,
+                AVG(DRAFT_PICKS.PICK_NO)::FLOAT8 AS AVERAGE_PICK,
+                MIN(DRAFT_PICKS.PICK_NO) AS EARLIEST_PICK,
+                MAX(DRAFT_PICKS.PICK_NO) AS LATEST_PICK
+            FROM DRAFT_PICKS
+            JOIN DRAFTS ON DRAFTS.ID = DRAFT_PICKS.DRAFT_ID
+            LEFT JOIN PLAYERS ON PLAYERS.ID = DRAFT_PICKS.PLAYER_ID
+            WHERE DRAFTS.SEASON = $1
+                AND NOT DRAFT_PICKS.IS_KEEPER
+            GROUP BY DRAFT_PICKS.PLAYER_ID, PLAYERS.FIRST_NAME, PLAYERS.LAST_NAME, PLAYERS.POSITION, PLAYERS.TEAM
+            ORDER BY AVERAGE_PICK ASC, DRAFTS DESC, DRAFT_PICKS.PLAYER_ID ASC
+              ",
+              &[&season])
+        .await?
+        .iter()
+        .map(|row| {
+            Adp {
+                player_id: row.get("player_id"),
+                first_name: row.get("first_name"),
+                last_name: row.get("last_name"),
+                position: row.get("position"),
+                team: row.get("team"),
+                drafts: row.get("drafts"),
+                average_pick: row.get("average_pick"),
+                earliest_pick: row.get("earliest_pick"),
+                latest_pick: row.get("latest_pick"),
+            }
+        })
+        .collect())
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct State {
-    pub season: i32,
-    pub week: i32,
-    pub league_season: i32,
-    pub display_week: i32,
-    pub season_type: String,
+// Every pick in a season's drafts with the points its player has
+// scored in that league, players who never scored have 0
+pub async fn get_scored_picks(con: &DBCon, season: i32) -> Result<Vec<ScoredPick>, tokio_postgres::Error> {
+    Ok(con.query("
+            SELECT DRAFT_PICKS.*,
+                DRAFTS.LEAGUE_ID,
+                LEAGUES.NAME AS LEAGUE_NAME,
+                PLAYERS.FIRST_NAME,
+                PLAYERS.LAST_NAME,
+                PLAYERS.POSITION,
+                USERS.NAME AS USER_NAME,
+                COALESCE(TOTALS.POINTS, 0) AS POINTS
+            FROM DRAFT_PICKS
+            JOIN DRAFTS ON DRAFTS.ID = DRAFT_PICKS.DRAFT_ID
+            LEFT JOIN LEAGUES ON LEAGUES.ID = DRAFTS.LEAGUE_ID
+            LEFT JOIN PLAYERS ON PLAYERS.ID = DRAFT_PICKS.PLAYER_ID
+            LEFT JOIN USERS ON USERS.ID = DRAFT_PICKS.USER_ID
+            LEFT JOIN (
+                SELECT PLAYER_ID,
+                    LEAGUE_ID,
+                    SUM(POINTS)::FLOAT8 AS POINTS
+                FROM SCORES
+                WHERE SEASON = $1
+                GROUP BY PLAYER_ID, LEAGUE_ID
+            ) AS TOTALS ON TOTALS.PLAYER_ID = DRAFT_PICKS.PLAYER_ID AND TOTALS.LEAGUE_ID = DRAFTS.LEAGUE_ID
+            WHERE DRAFTS.SEASON = $1
+            ORDER BY DRAFT_PICKS.DRAFT_ID ASC, DRAFT_PICKS.PICK_NO ASC
+              ",
+              &[&season])
+        .await?
+        .iter()
+        .map(|row| {
+            ScoredPick {
+                draft_id: row.get("draft_id"),
+                league_id: row.get("league_id"),
+                league_name: row.get("league_name"),
+                pick_no: row.get("pick_no"),
+                round: row.get("round"),
+                player_id: row.get("player_id"),
+                first_name: row.get("first_name"),
+                last_name: row.get("last_name"),
+                position: row.get("position"),
+                user_id: row.get("user_id"),
+                user_name: row.get("user_name"),
+                is_keeper: row.get("is_keeper"),
+                points: row.get("points"),
+            }
+        })
+        .collect())
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct Matchup {
-    pub season: i32,
-    pub week: i32,
-    pub league_id: String,
-    pub user_id: String,
-    pub opponent_id: String,
-    pub points: f32,
+// Points each player acquired in a season's trades has scored while
+// starting for the team that got them, from the week after the trade
+// on. Keyed by transaction and player id, with how many weeks they
+// started.
+pub async fn get_trade_points(con: &DBCon, season: i32) -> Result<HashMap<(String, String), (f64, i64)>, tokio_postgres::Error> {
+    Ok(con.query("
+            SELECT TRANSACTIONS.ID,
+                TRANSACTION_PLAYERS.PLAYER_ID,
+                COALESCE(SUM(SCORES.POINTS), 0)::FLOAT8 AS POINTS,
+                COUNT(LINEUPS.WEEK) AS STARTS
+            FROM TRANSACTIONS
+            JOIN TRANSACTION_PLAYERS ON TRANSACTION_PLAYERS.TRANSACTION_ID = TRANSACTIONS.ID
+                AND TRANSACTION_PLAYERS.ACTION = 'add'
+            LEFT JOIN LINEUPS ON LINEUPS.LEAGUE_ID = TRANSACTIONS.LEAGUE_ID
+                AND LINEUPS.SEASON = TRANSACTIONS.SEASON
+                AND LINEUPS.WEEK > TRANSACTIONS.WEEK
+                AND LINEUPS.USER_ID = TRANSACTION_PLAYERS.USER_ID
+                AND LINEUPS.PLAYER_ID = TRANSACTION_PLAYERS.PLAYER_ID
+                AND LINEUPS.STARTER
+            LEFT JOIN SCORES ON SCORES.PLAYER_ID = LINEUPS.PLAYER_ID
+                AND SCORES.LEAGUE_ID = LINEUPS.LEAGUE_ID
+                AND SCORES.SEASON = LINEUPS.SEASON
+                AND SCORES.WEEK = LINEUPS.WEEK
+            WHERE TRANSACTIONS.KIND = 'trade'
+                AND TRANSACTIONS.STATUS = 'complete'
+                AND TRANSACTIONS.SEASON = $1
+            GROUP BY TRANSACTIONS.ID, TRANSACTION_PLAYERS.PLAYER_ID
+              ",
+              &[&season])
+        .await?
+        .iter()
+        .map(|row| ((row.get("id"), row.get("player_id")), (row.get("points"), row.get("starts"))))
+        .collect())
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct Score {
-    pub player_id: String,
-    pub league_id: String,
-    pub season: i32,
-    pub week: i32,
-    pub points: f32,
+pub async fn get_roster_positions(con: &DBCon, league_id: &str) -> Result<Vec<String>, tokio_postgres::Error> {
+    Ok(con.query("
+            SELECT POSITION FROM ROSTER_POSITIONS WHERE LEAGUE_ID = $1 ORDER BY SLOT ASC
+              ",
+              &[&league_id])
+        .await?
+        .iter()
+        .map(|row| row.get("position"))
+        .collect())
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct Week {
-    pub league_id: String,
-    pub season: i32,
-    pub week: i32,
-    pub user_id: String,
-    pub user_name: String,
-    pub user_avatar: String,
-    pub user_points: f32,
-    pub opponent_id: String,
-    pub opponent_name: String,
-    pub opponent_avatar: String,
-    pub opponent_points: f32,
-}
+// Every league's settings, by league name
+pub async fn get_league_settings(con: &DBCon) -> Result<Vec<LeagueSettings>, tokio_postgres::Error> {
+    let mut leagues: Vec<LeagueSettings> = con.query("
+            SELECT LEAGUES.ID,
+                LEAGUES.NAME,
+                LEAGUE_SETTINGS.TOTAL_ROSTERS,
+                LEAGUE_SETTINGS.PLAYOFF_TEAMS,
+                LEAGUE_SETTINGS.TRADE_DEADLINE,
+                LEAGUE_SETTINGS.WAIVER_TYPE
+            FROM LEAGUES
+            LEFT JOIN LEAGUE_SETTINGS ON LEAGUE_SETTINGS.LEAGUE_ID = LEAGUES.ID
+            ORDER BY LEAGUES.NAME ASC, LEAGUES.ID ASC
+              ",
+              &[])
+        .await?
+        .iter()
+        .map(|row| {
+            LeagueSettings {
+                league_id: row.get("id"),
+                league_name: row.get("name"),
+                total_rosters: row.get("total_rosters"),
+                playoff_teams: row.get("playoff_teams"),
+                trade_deadline: row.get("trade_deadline"),
+                waiver_type: row.get("waiver_type"),
+                roster_positions: vec![],
+                scoring: BTreeMap::new(),
+            }
+        })
+        .collect();
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct Bracket {
-    pub num_teams: usize,
-    pub start_week: i32,
-    pub champ_week: i32,
-    pub stages: Vec<Vec<PlayoffTeam>>,
+    for row in con.query("SELECT LEAGUE_ID, POSITION FROM ROSTER_POSITIONS ORDER BY LEAGUE_ID, SLOT ASC", &[]).await? {
+        let league_id: String = row.get("league_id");
+        if let Some(league) = leagues.iter_mut().find(|l| l.league_id == league_id) {
+            league.roster_positions.push(row.get("position"));
+        }
+    }
+    for row in con.query("SELECT LEAGUE_ID, STAT, POINTS FROM SCORING_SETTINGS", &[]).await? {
+        let league_id: String = row.get("league_id");
+        if let Some(league) = leagues.iter_mut().find(|l| l.league_id == league_id) {
+            league.scoring.insert(row.get("stat"), row.get("points"));
+        }
+    }
+    Ok(leagues)
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
-pub struct PlayoffTeam {
-    pub week: i32,
-    pub rank: i64,
-    pub user: User,
-    pub points: f32,
+// Everyone on every roster in a league each week of a season, up to
+// but not including last_week
+pub async fn get_lineup_players(con: &DBCon, league_id: &str, season: i32, last_week: i32) -> Result<Vec<LineupPlayer>, tokio_postgres::Error> {
+    Ok(con.query("
+            SELECT LINEUPS.WEEK,
+                LINEUPS.USER_ID,
+                LINEUPS.PLAYER_ID,
+                LINEUPS.STARTER,
+                PLAYERS.POSITION,
+                COALESCE(SCORES.POINTS, 0)::FLOAT8 AS POINTS
+            FROM LINEUPS
+            LEFT JOIN PLAYERS ON PLAYERS.ID = LINEUPS.PLAYER_ID
+            LEFT JOIN SCORES ON SCORES.PLAYER_ID = LINEUPS.PLAYER_ID
+                AND SCORES.LEAGUE_ID = LINEUPS.LEAGUE_ID
+                AND SCORES.SEASON = LINEUPS.SEASON
+                AND SCORES.WEEK = LINEUPS.WEEK
+            WHERE LINEUPS.LEAGUE_ID = $1
+                AND LINEUPS.SEASON = $2
+                AND LINEUPS.WEEK < $3
+              ",
+              &[&league_id, &season, &last_week])
+        .await?
+        .iter()
+        .map(|row| {
+            LineupPlayer {
+                week: row.get("week"),
+                user_id: row.get("user_id"),
+                player_id: row.get("player_id"),
+                position: row.get("position"),
+                starter: row.get("starter"),
+                points: row.get("points"),
+            }
+        })
+        .collect())
 }
 
-pub async fn get_db_con(db_pool: &DBPool) -> DBCon {
-    db_pool.get().await.unwrap()
+// Every matchup in a league for a season as ingested, before any
+// overrides, up to but not including last_week
+pub async fn get_league_matchup_points(con: &DBCon, league_id: &str, season: i32, last_week: i32) -> Result<Vec<MatchupPoints>, tokio_postgres::Error> {
+    Ok(con.query("
+            SELECT M1.WEEK,
+                M1.USER_ID,
+                USERS.NAME AS USER_NAME,
+                M1.POINTS::FLOAT8 AS POINTS,
+                M1.OPPONENT_ID,
+                M2.POINTS::FLOAT8 AS OPPONENT_POINTS
+            FROM MATCHUPS AS M1
+            JOIN MATCHUPS AS M2 ON M2.SEASON = M1.SEASON
+                AND M2.WEEK = M1.WEEK
+                AND M2.LEAGUE_ID = M1.LEAGUE_ID
+                AND M2.USER_ID = M1.OPPONENT_ID
+            LEFT JOIN USERS ON USERS.ID = M1.USER_ID
+            WHERE M1.LEAGUE_ID = $1
+                AND M1.SEASON = $2
+                AND M1.WEEK < $3
+            ORDER BY M1.WEEK ASC, M1.USER_ID ASC
+              ",
+              &[&league_id, &season, &last_week])
+        .await?
+        .iter()
+        .map(|row| {
+            MatchupPoints {
+                week: row.get("week"),
+                user_id: row.get("user_id"),
+                user_name: row.get("user_name"),
+                points: row.get("points"),
+                opponent_id: row.get("opponent_id"),
+                opponent_points: row.get("opponent_points"),
+            }
+        })
+        .collect())
 }
 
-pub fn with_db(db_pool: Arc<DBPool>) -> impl Filter<Extract = (Arc<DBPool>,), Error = Infallible> + Clone {
-    warp::any().map(move || db_pool.clone())
+// Every override, newest first, including removed ones
+pub async fn get_overrides(con: &DBCon) -> Result<Vec<Override>, tokio_postgres::Error> {
+    Ok(con.query("
+            SELECT OVERRIDES.*,
+                USERS.NAME AS USER_NAME
+            FROM OVERRIDES
+            LEFT JOIN USERS ON USERS.ID = OVERRIDES.USER_ID
+            ORDER BY OVERRIDES.CREATED_AT DESC, OVERRIDES.ID DESC
+              ",
+              &[])
+        .await?
+        .iter()
+        .map(|row| {
+            Override {
+                id: row.get("id"),
+                kind: row.get("kind"),
+                user_id: row.get("user_id"),
+                user_name: row.get("user_name"),
+                season: row.get("season"),
+                week: row.get("week"),
+                points: row.get("points"),
+                result: row.get("result"),
+                seed: row.get("seed"),
+                reason: row.get("reason"),
+                created_by: row.get("created_by"),
+                created_at: row.get("created_at"),
+                removed_by: row.get("removed_by"),
+                removed_at: row.get("removed_at"),
+            }
+        })
+        .collect())
 }
 
-pub fn create_pool(bl_config: config::Config) -> std::result::Result<DBPool, mobc::Error<Error>> {
-    
-    info!("creating database pool");
+// Adds an override, removing any active ones it conflicts with: the
+// same user's points or seed, a result for either side of the same
+// matchup, or another user's claim on the same seed
+pub async fn add_override(con: &mut DBCon, new: &NewOverride, username: &str) -> Result<i32, tokio_postgres::Error> {
+    let transaction = con.transaction().await?;
 
-    let pg_config = Config::from_str(
-        &format!("host={} port={} user={} password={} dbname={}",
-            bl_config.database.host,
-            bl_config.database.port.unwrap_or(5432),
-            bl_config.database.user,
-            bl_config.database.password,
-            bl_config.database.dbname,
-        )
-    )?;
+    match new.kind.as_str() {
+        "points" => transaction.execute("
+                UPDATE OVERRIDES
+                SET REMOVED_BY = $1,
+                    REMOVED_AT = NOW()
+                WHERE REMOVED_AT IS NULL
+                    AND KIND = 'points'
+                    AND USER_ID = $2
+                    AND SEASON = $3
+                    AND WEEK = $4
+                  ",
+                  &[&username, &new.user_id, &new.season, &new.week]).await?,
+        "result" => transaction.execute("
+                UPDATE OVERRIDES
+                SET REMOVED_BY = $1,
+                    REMOVED_AT = NOW()
+                WHERE REMOVED_AT IS NULL
+                    AND KIND = 'result'
+                    AND SEASON = $3
+                    AND WEEK = $4
+                    AND (USER_ID = $2 OR USER_ID IN (
+                        SELECT OPPONENT_ID
+                        FROM MATCHUPS
+                        WHERE USER_ID = $2
+                            AND SEASON = $3
+                            AND WEEK = $4
+                    ))
+                  ",
+                  &[&username, &new.user_id, &new.season, &new.week]).await?,
+        _ => transaction.execute("
+                UPDATE OVERRIDES
+                SET REMOVED_BY = $1,
+                    REMOVED_AT = NOW()
+                WHERE REMOVED_AT IS NULL
+                    AND KIND = 'seed'
+                    AND (USER_ID = $2 OR SEED = $3)
+                  ",
+                  &[&username, &new.user_id, &new.seed]).await?,
+    };
 
-    let manager = PgConnectionManager::new(pg_config, NoTls);
-    Ok(Pool::builder()
-            .max_open(bl_config.database.max_open)
-            .max_idle(bl_config.database.max_idle)
-            .get_timeout(Some(Duration::from_secs(bl_config.database.timeout)))
-            .build(manager))
+    let row = transaction.query_one("
+            INSERT INTO OVERRIDES (KIND, USER_ID, SEASON, WEEK, POINTS, RESULT, SEED, REASON, CREATED_BY)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            RETURNING ID
+              ",
+              &[&new.kind, &new.user_id, &new.season, &new.week, &new.points, &new.result, &new.seed, &new.reason, &username])
+        .await?;
+
+    transaction.commit().await?;
+    Ok(row.get("id"))
 }
 
-pub async fn create_tables(db_pool: Arc<DBPool>) -> Result<(), tokio_postgres::Error>{
+// Marks an active override as removed, false if there wasn't one
+pub async fn remove_override(con: &DBCon, id: i32, username: &str) -> Result<bool, tokio_postgres::Error> {
+    let removed = con.execute("
+            UPDATE OVERRIDES
+            SET REMOVED_BY = $2,
+                REMOVED_AT = NOW()
+            WHERE ID = $1
+                AND REMOVED_AT IS NULL
+              ",
+              &[&id, &username])
+        .await?;
+    Ok(removed == 1)
+}
 
-    info!("creating tables");
+// user_id -> (league_id, rank)
+pub async fn get_ranks(con: &DBCon) -> Result<HashMap<String, (String, i64)>, tokio_postgres::Error> {
+    Ok(
+        con.query("SELECT ranks.user_id, rosters.league_id, ranks.rank FROM ranks, rosters WHERE ranks.user_id = rosters.user_id", &[])
+            .await?
+            .into_iter()
+            .map(|row| (row.get("user_id"), (row.get("league_id"), row.get("rank"))))
+            .collect()
+    )
+}
 
-    let con = get_db_con(&db_pool).await;
+pub fn collect_standings(rows: Vec<Row>) -> Vec<Standing> {
+    rows.into_iter()
+        .map(|row| {
+            let user = User {
+                id: row.get(0),
+                name: row.get(1),
+                avatar: row.get(2),
+            };
 
-    // Create table for the leagues that the users are in
-    con.batch_execute(
-        "
-        CREATE TABLE IF NOT EXISTS leagues (
-            id varchar(64) PRIMARY KEY,
-            name varchar(64) NOT NULL,
-            avatar varchar(64)
-        ) 
-        "
-    ).await.unwrap();
+            let roster = Roster {
+                user_id: row.get(3),
+                league_id: row.get(4),
+                wins: row.get(5),
+                losses: row.get(6),
+                ties: row.get(7),
+                fpts: row.get(8),
+                fpts_decimal: row.get(9),
+                fpts_against: row.get(10),
+                fpts_against_decimal: row.get(11),
+                roster_id: row.get(12),
+            };
 
+            let league = League {
+                id: row.get(13),
+                name: row.get(14),
+                avatar: row.get(15),
+            };
 
-    con.batch_execute(
-        "
-        CREATE TABLE IF NOT EXISTS users (
-            id varchar(64) PRIMARY KEY,
-            name varchar(64) NOT NULL,
-            avatar varchar(64)
-        )
-        "
-    ).await.unwrap();
-    
-    // Create table for users in the big league
-    con.batch_execute(
-        "
-        CREATE TABLE IF NOT EXISTS rosters (
-            user_id varchar(64) PRIMARY KEY,
-            league_id varchar(64),
-            wins integer NOT NULL,
-            losses integer NOT NULL,
-            ties integer NOT NULL,
-            fpts integer NOT NULL,
-            fpts_decimal integer NOT NULL,
-            fpts_against integer NOT NULL,
-            fpts_against_decimal integer NOT NULL,
-            roster_id integer NOT NULL
-        )
-        "
-    ).await.unwrap();
+            let rank: i64 = row.get(17);
+            let normalized_fpts: Option<f64> = row.get(18);
 
-    con.batch_execute(
-        "
-        CREATE TABLE IF NOT EXISTS players (
-           id varchar(64) PRIMARY KEY,
-           first_name varchar(64) NOT NULL,
-           last_name varchar(64) NOT NULL,
-           team varchar(64),
-           position varchar(64),
-           status varchar(64)
-        )
-        "
-    ).await.unwrap();
+            Standing {
+                user,
+                roster,
+                league,
+                rank,
+                normalized_fpts,
+            }
+        })
+        .collect()
+}
 
-    con.batch_execute(
-        "
-        CREATE TABLE IF NOT EXISTS ownership (
-            user_id varchar(64) NOT NULL,
-            league_id varchar(64) NOT NULL,
-            player_id varchar(64) NOT NULL,
-            starter integer,
-            PRIMARY KEY (user_id, league_id, player_id)
-        )
-        "
-    ).await.unwrap();
+pub async fn get_standings(con: &DBCon) -> Result<Vec<Standing>, tokio_postgres::Error> {
+    let rows = con.query("SELECT * FROM users, adjusted_rosters, leagues, ranks WHERE users.id = adjusted_rosters.user_id AND leagues.id = adjusted_rosters.league_id AND ranks.user_id = users.id ORDER BY ranks.rank ASC", &[])
+        .await?;
+    Ok(collect_standings(rows))
+}
 
-    con.batch_execute(
-        "
-        CREATE TABLE IF NOT EXISTS state (
-            season integer NOT NULL,
-            week integer NOT NULL,
-            league_season integer NOT NULL,
-            display_week integer NOT NULL,
-            season_type varchar(64) NOT NULL,
-            PRIMARY KEY (season, week)
-        )
-        "
-    ).await.unwrap();
+pub async fn get_league_standings(con: &DBCon, league_id: &str) -> Result<Vec<Standing>, tokio_postgres::Error> {
+    let rows = con.query("SELECT * FROM users, adjusted_rosters, leagues, ranks WHERE users.id = adjusted_rosters.user_id AND leagues.id = adjusted_rosters.league_id AND ranks.user_id = users.id AND leagues.id = $1 ORDER BY ranks.rank ASC", &[&league_id])
+        .await?;
+    Ok(collect_standings(rows))
+}
 
-    con.batch_execute(
-        "
-        CREATE TABLE IF NOT EXISTS matchups (
-            season integer NOT NULL,
-            week integer NOT NULL,
-            league_id varchar(64) NOT NULL,
-            user_id varchar(64) NOT NULL,
-            opponent_id varchar(64) NOT NULL,
-            points real NOT NULL,
-            PRIMARY KEY (season, week, league_id, user_id, opponent_id)
-        )
-        "
-    ).await.unwrap();
+pub async fn get_league(con: &DBCon, league_id: &str) -> Result<Option<League>, tokio_postgres::Error> {
+    let rows = con.query("SELECT * FROM leagues WHERE id = $1", &[&league_id])
+        .await?;
+    Ok(rows.first().map(|row| {
+        League {
+            id: row.get(0),
+            name: row.get(1),
+            avatar: row.get(2),
+        }
+    }))
+}
 
-    con.batch_execute(
-        "
-        CREATE TABLE IF NOT EXISTS scores (
-            player_id varchar(64) NOT NULL,
-            league_id varchar(64) NOT NULL,
-            season integer NOT NULL,
-            week integer NOT NULL,
-            points real NOT NULL,
-            PRIMARY KEY (player_id, league_id, season, week)
-        )
-        "
-    ).await.unwrap();
+pub async fn get_user(con: &DBCon, user_id: &str) -> Result<Option<(User, Roster)>, tokio_postgres::Error> {
+    let rows = con.query("SELECT * FROM users, adjusted_rosters WHERE users.id = $1 AND users.id = adjusted_rosters.user_id", &[&user_id])
+        .await?;
+    Ok(rows.first().map(|row| {
+        let user = User {
+            id: row.get(0),
+            name: row.get(1),
+            avatar: row.get(2),
+        };
 
-    con.batch_execute(
-        "
-        CREATE OR REPLACE VIEW ranks AS
-            SELECT user_id, ROW_NUMBER() OVER (ORDER BY wins DESC, fpts DESC, fpts_decimal DESC, fpts_against DESC, fpts_against_decimal DESC) as rank
-            FROM rosters, users 
-            WHERE rosters.user_id = users.id
-        "
-    ).await.unwrap();
-    Ok(())
+        let roster = Roster {
+            user_id: row.get(3),
+            league_id: row.get(4),
+            wins: row.get(5),
+            losses: row.get(6),
+            ties: row.get(7),
+            fpts: row.get(8),
+            fpts_decimal: row.get(9),
+            fpts_against: row.get(10),
+            fpts_against_decimal: row.get(11),
+            roster_id: row.get(12),
+        };
+
+        (user, roster)
+    }))
 }
 
-pub async fn get_num_leagues(con: DBCon) -> Result<i64, tokio_postgres::Error> {
+pub async fn get_players(con: &DBCon, user_id: &str, league_id: &str, season: i32, week: i32) -> Result<Vec<Player>, tokio_postgres::Error> {
     Ok(
         con.query(
-            "SELECT count(*) FROM leagues",
-            &[])
-        .await?[0]
-        .get::<usize, i64>(0))
+            "
+            SELECT ID,
+                FIRST_NAME,
+                LAST_NAME,
+                TEAM,
+                POSITION,
+                STATUS,
+                STARTER,
+                POINTS
+            FROM PLAYERS,
+                OWNERSHIP,
+                SCORES
+            WHERE PLAYERS.ID = OWNERSHIP.PLAYER_ID
+                AND OWNERSHIP.USER_ID = $1
+                AND SCORES.SEASON = $2
+                AND SCORES.WEEK = $3
+                AND SCORES.PLAYER_ID = PLAYERS.ID
+                AND SCORES.LEAGUE_ID = $4
+            ORDER BY STARTER DESC, (CASE POSITION
+                            WHEN 'QB' THEN 1
+                            WHEN 'RB' THEN 2
+                            WHEN 'WR' THEN 3
+                            WHEN 'TE' THEN 4
+                            WHEN 'K' THEN 5
+                            WHEN 'DEF' THEN 6
+            END) ASC
+            ",
+            &[&user_id, &season, &week, &league_id]
+        )
+        .await?
+        .into_iter()
+        .map(|player| {
+            Player {
+                id: player.get("id"),
+                first_name: player.get("first_name"),
+                last_name: player.get("last_name"),
+                team: player.get("team"),
+                position: player.get("position"),
+                status: player.get("status"),
+                starter: player.get("starter"),
+                points: player.get("points"),
+            }
+        })
+        .collect()
+    )
 }
 
-pub async fn get_time_period(con: &DBCon) -> Result<(i32, i32), tokio_postgres::Error> {
-    let time = &con.query("
-            SELECT SEASON,
-                WEEK
-            FROM STATE
-            ORDER BY SEASON DESC,
-                WEEK DESC
-            LIMIT 1
-              ",
-              &[])
-        .await?[0];
-    Ok((time.get("season"), time.get("week")))
+fn collect_weeks(rows: Vec<Row>, season: i32) -> Vec<Week> {
+    rows.into_iter()
+        .map(|row| {
+            Week {
+                league_id: row.get("league_id"),
+                season,
+                week: row.get("week"),
+                user_id: row.get("user_id"),
+                user_name: row.get("user_name"),
+                user_avatar: row.get("user_avatar"),
+                user_points: row.get("user_points"),
+                opponent_id: row.get("opponent_id"),
+                opponent_name: row.get("opponent_name"),
+                opponent_avatar: row.get("opponent_avatar"),
+                opponent_points: row.get("opponent_points"),
+                result: row.get("result"),
+            }
+        })
+        .collect()
+}
+
+pub async fn get_user_matchups(con: &DBCon, user_id: &str, season: i32) -> Result<Vec<Week>, tokio_postgres::Error> {
+    let rows = con.query(
+           "
+            SELECT M1.WEEK,
+                M1.LEAGUE_ID,
+                M1.USER_ID,
+                U1.NAME AS USER_NAME,
+                U1.AVATAR AS USER_AVATAR,
+                M1.POINTS AS USER_POINTS,
+                M1.OPPONENT_ID,
+                U2.NAME AS OPPONENT_NAME,
+                U2.AVATAR AS OPPONENT_AVATAR,
+                M2.POINTS AS OPPONENT_POINTS,
+                M1.RESULT
+            FROM ADJUSTED_MATCHUPS AS M1,
+                ADJUSTED_MATCHUPS AS M2,
+                USERS AS U1,
+                USERS AS U2
+            WHERE M1.OPPONENT_ID = M2.USER_ID
+                AND M1.WEEK = M2.WEEK
+                AND M1.SEASON = M2.SEASON
+                AND U1.ID = M1.USER_ID
+                AND U2.ID = M1.OPPONENT_ID
+                AND M1.SEASON = $1
+                AND M1.USER_ID = $2
+            ORDER BY M1.WEEK ASC
+           ",
+           &[&season, &user_id]
+        )
+        .await?;
+    Ok(collect_weeks(rows, season))
+}
+
+pub async fn get_week_matchups(con: &DBCon, season: i32, week: i32) -> Result<Vec<Week>, tokio_postgres::Error> {
+    // Every pairing is stored once from each side, only keep one of them
+    let rows = con.query(
+           "
+            SELECT M1.WEEK,
+                M1.LEAGUE_ID,
+                M1.USER_ID,
+                U1.NAME AS USER_NAME,
+                U1.AVATAR AS USER_AVATAR,
+                M1.POINTS AS USER_POINTS,
+                M1.OPPONENT_ID,
+                U2.NAME AS OPPONENT_NAME,
+                U2.AVATAR AS OPPONENT_AVATAR,
+                M2.POINTS AS OPPONENT_POINTS,
+                M1.RESULT
+            FROM ADJUSTED_MATCHUPS AS M1,
+                ADJUSTED_MATCHUPS AS M2,
+                USERS AS U1,
+                USERS AS U2
+            WHERE M1.OPPONENT_ID = M2.USER_ID
+                AND M1.WEEK = M2.WEEK
+                AND M1.SEASON = M2.SEASON
+                AND U1.ID = M1.USER_ID
+                AND U2.ID = M1.OPPONENT_ID
+                AND M1.USER_ID < M1.OPPONENT_ID
+                AND M1.SEASON = $1
+                AND M1.WEEK = $2
+            ORDER BY M1.LEAGUE_ID ASC, M1.USER_ID ASC
+           ",
+           &[&season, &week]
+        )
+        .await?;
+    Ok(collect_weeks(rows, season))
 }
 
-pub fn resolve_bracket(initial_round: Vec<PlayoffTeam>, start_week: i32, end_week: i32, weeks: HashMap<(i32, i64), f32>) -> Option<Vec<Vec<PlayoffTeam>>> {
+// results holds the weeks a commissioner decided, (week, rank) -> won
+pub fn resolve_bracket(initial_round: Vec<PlayoffTeam>, start_week: i32, end_week: i32, weeks: HashMap<(i32, i64), f32>, results: HashMap<(i32, i64), bool>) -> Option<Vec<Vec<PlayoffTeam>>> {
     let mut bracket = vec![initial_round.clone()];
     let mut curr_round = initial_round.clone();
     for r in start_week..end_week {
@@ -322,7 +2251,12 @@ pub fn resolve_bracket(initial_round: Vec<PlayoffTeam>, start_week: i32, end_wee
                     matchup[1].user.name,
                     team2_pts,
                     );
-                if team1_pts > team2_pts {
+                let team1_wins = match (results.get(&(r, matchup[0].rank)), results.get(&(r, matchup[1].rank))) {
+                    (Some(won), _) => *won,
+                    (None, Some(won)) => !*won,
+                    (None, None) => team1_pts > team2_pts,
+                };
+                if team1_wins {
                     trace!("team1 ({:?}) wins!", matchup[0].user.name);
                     Some(
                         PlayoffTeam {
@@ -351,7 +2285,7 @@ pub fn resolve_bracket(initial_round: Vec<PlayoffTeam>, start_week: i32, end_wee
     Some(bracket)
 }
 
-pub async fn get_bracket(con: DBCon, config: config::Config) -> Result<Bracket, tokio_postgres::Error>{
+pub async fn get_bracket(con: DBCon, config: config::Config) -> Result<Bracket, error::Error>{
 
     let start_week = config.bigleague.playoffs_start_week;
     let champ_week = config.bigleague.playoffs_championship_week;
@@ -359,25 +2293,41 @@ pub async fn get_bracket(con: DBCon, config: config::Config) -> Result<Bracket,
 
     let (curr_season, curr_week) = get_time_period(&con).await?;
 
-    let possible_user_weeks: Vec<PlayoffTeam> = con.query("
+    let rows = con.query("
             SELECT WEEK,
                 RANKS.RANK as RANK,
                 USERS.ID,
                 USERS.NAME,
                 USERS.AVATAR,
-                POINTS
-            FROM MATCHUPS,
+                POINTS,
+                RESULT
+            FROM NORMALIZED_MATCHUPS,
                 RANKS,
                 USERS
             WHERE WEEK >= $1
                 AND SEASON = $2
-                AND RANKS.USER_ID = MATCHUPS.USER_ID
+                AND RANKS.USER_ID = NORMALIZED_MATCHUPS.USER_ID
                 AND RANKS.USER_ID = USERS.ID
                 AND RANKS.RANK <= $3
             ORDER BY WEEK ASC, RANK ASC;
               ",
               &[&curr_week, &curr_season, &bids])
-        .await?
+        .await?;
+
+    // Ties are left to the points
+    let results: HashMap<(i32, i64), bool> = rows
+        .iter()
+        .filter_map(|row| {
+            let won = match row.get::<&str, Option<&str>>("result") {
+                Some("win") => true,
+                Some("loss") => false,
+                _ => return None,
+            };
+            Some(((row.get("week"), row.get("rank")), won))
+        })
+        .collect();
+
+    let possible_user_weeks: Vec<PlayoffTeam> = rows
         .into_iter()
         .map(|row| {
             PlayoffTeam {
@@ -419,8 +2369,7 @@ pub async fn get_bracket(con: DBCon, config: config::Config) -> Result<Bracket,
 
     let matched: Vec<PlayoffTeam> = top_half
         .zip(bottom_half)
-        .map(|m| vec![m.0, m.1])
-        .flatten()
+        .flat_map(|m| vec![m.0, m.1])
         .collect();
 
     let stages: Vec<Vec<PlayoffTeam>> = 
@@ -428,7 +2377,8 @@ pub async fn get_bracket(con: DBCon, config: config::Config) -> Result<Bracket,
             matched,
             start_week,
             if curr_week < start_week { start_week } else { curr_week },
-            week_rank
+            week_rank,
+            results,
         ) {
             Some(s) => s,
             None => {
@@ -482,7 +2432,7 @@ mod tests {
         ]);
 
         let base = vec![team1.clone(), team2.clone()];
-        let resolved_bracket = db::resolve_bracket(base, 0, 1, matchups).unwrap();
+        let resolved_bracket = db::resolve_bracket(base.clone(), 0, 1, matchups.clone(), HashMap::new()).unwrap();
 
         assert_eq!(
             resolved_bracket.into_iter().last().unwrap(),
@@ -490,9 +2440,14 @@ mod tests {
                 db::PlayoffTeam {
                     points: 0.0,
                     week: 1,
-                    ..team1
+                    ..team1.clone()
                 }
             ]
             );
+
+        // A commissioner can hand the week to the team with fewer points
+        let ruled = HashMap::from([((0, 2), true)]);
+        let resolved_bracket = db::resolve_bracket(base, 0, 1, matchups, ruled).unwrap();
+        assert_eq!(resolved_bracket.into_iter().last().unwrap()[0].user.name, team2.user.name);
     }
 }
@@ -0,0 +1,121 @@
+use std::fmt;
+use warp::Rejection;
+use warp::http::StatusCode;
+use log::error;
+
+#[derive(Debug)]
+pub enum Error {
+    NotFound(String),
+    BadRequest(String),
+    Unauthorized,
+    Forbidden,
+    Database(tokio_postgres::Error),
+    Pool(mobc::Error<tokio_postgres::Error>),
+    Template(tera::Error),
+    Sleeper(reqwest::Error),
+    Json(serde_json::Error),
+    Io(std::io::Error),
+    // Sleeper returned something we didn't expect
+    Ingest(String),
+    // Something we depend on isn't running
+    Unavailable(String),
+    Config(crate::config::ConfigError),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::NotFound(what) => write!(f, "{} not found", what),
+            Error::BadRequest(msg) => write!(f, "{}", msg),
+            Error::Unauthorized => write!(f, "you need to log in to see this"),
+            Error::Forbidden => write!(f, "you aren't allowed to do that"),
+            Error::Database(e) => write!(f, "database error: {}", e),
+            Error::Pool(e) => write!(f, "couldn't get a database connection: {}", e),
+            Error::Template(e) => write!(f, "template error: {:?}", e),
+            Error::Sleeper(e) => write!(f, "request to sleeper failed: {}", e),
+            Error::Json(e) => write!(f, "couldn't parse sleeper response: {}", e),
+            Error::Io(e) => write!(f, "io error: {}", e),
+            Error::Ingest(msg) => write!(f, "unexpected sleeper data: {}", msg),
+            Error::Unavailable(what) => write!(f, "{} isn't running", what),
+            Error::Config(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl warp::reject::Reject for Error {}
+
+impl From<tokio_postgres::Error> for Error {
+    fn from(e: tokio_postgres::Error) -> Error {
+        Error::Database(e)
+    }
+}
+
+impl From<mobc::Error<tokio_postgres::Error>> for Error {
+    fn from(e: mobc::Error<tokio_postgres::Error>) -> Error {
+        Error::Pool(e)
+    }
+}
+
+impl From<tera::Error> for Error {
+    fn from(e: tera::Error) -> Error {
+        Error::Template(e)
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Error {
+        Error::Sleeper(e)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Error {
+        Error::Json(e)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+impl Error {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Error::NotFound(_) => StatusCode::NOT_FOUND,
+            Error::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Error::Unauthorized => StatusCode::UNAUTHORIZED,
+            Error::Forbidden => StatusCode::FORBIDDEN,
+            Error::Sleeper(_) | Error::Json(_) | Error::Ingest(_) => StatusCode::BAD_GATEWAY,
+            Error::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            Error::Database(_) | Error::Pool(_) | Error::Template(_) | Error::Io(_) | Error::Config(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+// Works out the status and a message that is safe to show to
+// clients for a rejection. Internal failures are logged here and
+// only a generic message is returned.
+pub fn describe_rejection(err: &Rejection) -> (StatusCode, String) {
+    if err.is_not_found() {
+        (StatusCode::NOT_FOUND, String::from("not found"))
+    } else if let Some(e) = err.find::<Error>() {
+        let status = e.status();
+        if status.is_server_error() {
+            error!("request failed: {}", e);
+            (status, String::from("internal server error"))
+        } else {
+            (status, e.to_string())
+        }
+    } else if let Some(e) = err.find::<warp::reject::InvalidQuery>() {
+        (StatusCode::BAD_REQUEST, e.to_string())
+    } else if err.find::<warp::reject::MethodNotAllowed>().is_some() {
+        (StatusCode::METHOD_NOT_ALLOWED, String::from("method not allowed"))
+    } else {
+        error!("unhandled rejection: {:?}", err);
+        (StatusCode::INTERNAL_SERVER_ERROR, String::from("internal server error"))
+    }
+}
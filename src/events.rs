@@ -0,0 +1,140 @@
+use serde::{Serialize, Deserialize};
+use tokio::sync::broadcast;
+use warp::Filter;
+use std::convert::Infallible;
+
+// Events are only kept around long enough for slow subscribers
+// to catch up, anything older is dropped.
+const CAPACITY: usize = 1024;
+
+pub type EventSender = broadcast::Sender<Event>;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    ScoreChange {
+        league_id: String,
+        season: i32,
+        week: i32,
+        user_id: String,
+        points: f32,
+    },
+    // user_id is the team that just took the lead
+    LeadChange {
+        league_id: String,
+        season: i32,
+        week: i32,
+        user_id: String,
+        opponent_id: String,
+        user_points: f32,
+        opponent_points: f32,
+    },
+    // Sent for every matchup once the NFL week is over,
+    // winner_id is empty for ties
+    Final {
+        league_id: String,
+        season: i32,
+        week: i32,
+        user_id: String,
+        opponent_id: String,
+        user_points: f32,
+        opponent_points: f32,
+        winner_id: Option<String>,
+    },
+    StandingsChange {
+        league_id: String,
+        user_id: String,
+        previous_rank: i64,
+        rank: i64,
+    },
+    BracketAdvance {
+        league_id: String,
+        week: i32,
+        user_id: String,
+        rank: i64,
+    },
+}
+
+impl Event {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::ScoreChange { .. } => "score_change",
+            Event::LeadChange { .. } => "lead_change",
+            Event::Final { .. } => "final",
+            Event::StandingsChange { .. } => "standings_change",
+            Event::BracketAdvance { .. } => "bracket_advance",
+        }
+    }
+
+    pub fn league_id(&self) -> &str {
+        match self {
+            Event::ScoreChange { league_id, .. }
+            | Event::LeadChange { league_id, .. }
+            | Event::Final { league_id, .. }
+            | Event::StandingsChange { league_id, .. }
+            | Event::BracketAdvance { league_id, .. } => league_id,
+        }
+    }
+
+    pub fn involves(&self, user: &str) -> bool {
+        match self {
+            Event::LeadChange { user_id, opponent_id, .. }
+            | Event::Final { user_id, opponent_id, .. } => user_id == user || opponent_id == user,
+            Event::ScoreChange { user_id, .. }
+            | Event::StandingsChange { user_id, .. }
+            | Event::BracketAdvance { user_id, .. } => user_id == user,
+        }
+    }
+}
+
+// What a feed subscriber wants to hear about, anything left
+// empty matches every event
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Subscription {
+    pub league: Option<String>,
+    pub user: Option<String>,
+}
+
+impl Subscription {
+    pub fn matches(&self, event: &Event) -> bool {
+        self.league.as_ref().is_none_or(|l| event.league_id() == l)
+            && self.user.as_ref().is_none_or(|u| event.involves(u))
+    }
+}
+
+pub fn channel() -> EventSender {
+    let (sender, _) = broadcast::channel(CAPACITY);
+    sender
+}
+
+// Sending only fails when nobody is listening, which is fine
+pub fn publish(events: &EventSender, event: Event) {
+    let _ = events.send(event);
+}
+
+pub fn with_events(events: EventSender) -> impl Filter<Extract = (EventSender,), Error = Infallible> + Clone {
+    warp::any().map(move || events.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::events::{Event, Subscription};
+
+    #[test]
+    fn test_subscription_matches() {
+        let event = Event::LeadChange {
+            league_id: String::from("league"),
+            season: 2023,
+            week: 3,
+            user_id: String::from("todd"),
+            opponent_id: String::from("eve"),
+            user_points: 80.5,
+            opponent_points: 79.0,
+        };
+
+        assert!(Subscription::default().matches(&event));
+        assert!(Subscription { league: Some(String::from("league")), user: Some(String::from("eve")) }.matches(&event));
+        assert!(!Subscription { league: Some(String::from("other")), user: None }.matches(&event));
+        assert!(!Subscription { league: None, user: Some(String::from("bob")) }.matches(&event));
+    }
+}
@@ -0,0 +1,165 @@
+use serde::{Serialize, Deserialize};
+use utoipa::ToSchema;
+use std::collections::HashMap;
+
+use crate::db;
+
+// Letter grades from the best drafts down, each handed to an equal
+// share of everyone who drafted
+const GRADES: [&str; 5] = ["A", "B", "C", "D", "F"];
+
+// How a pick has done against what its spot in the draft is expected
+// to produce
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct PickValue {
+    pub pick: db::ScoredPick,
+    pub expected: f64,
+    /// Points over (or under) expected
+    pub value: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct DraftGrade {
+    pub user_id: String,
+    pub user_name: Option<String>,
+    pub league_id: String,
+    pub league_name: Option<String>,
+    /// Across every drafter in the big league, 1 is the best draft
+    pub rank: usize,
+    pub grade: String,
+    pub points: f64,
+    pub expected: f64,
+    pub value: f64,
+    pub picks: Vec<PickValue>,
+}
+
+// Fits points = a + b * ln(pick_no) across every pick, since what a
+// pick is worth falls off quickly through the early rounds and then
+// flattens out. Returns (a, b).
+pub fn expectation(picks: &[db::ScoredPick]) -> (f64, f64) {
+    if picks.is_empty() {
+        return (0.0, 0.0);
+    }
+    let n = picks.len() as f64;
+    let xs: Vec<f64> = picks.iter().map(|p| f64::from(p.pick_no.max(1)).ln()).collect();
+    let mean_x = xs.iter().sum::<f64>() / n;
+    let mean_y = picks.iter().map(|p| p.points).sum::<f64>() / n;
+
+    let (mut covariance, mut variance) = (0.0, 0.0);
+    for (x, p) in xs.iter().zip(picks) {
+        covariance += (x - mean_x) * (p.points - mean_y);
+        variance += (x - mean_x) * (x - mean_x);
+    }
+    // Every pick at the same spot, nothing to fit a slope to
+    if variance == 0.0 {
+        return (mean_y, 0.0);
+    }
+    let b = covariance / variance;
+    (mean_y - b * mean_x, b)
+}
+
+// Grades every user's draft in a season against the big league as a
+// whole, best first. Keepers weren't drafted so they don't count, and
+// neither do picks made for rosters without an owner.
+pub fn grade(picks: Vec<db::ScoredPick>) -> Vec<DraftGrade> {
+    let picks: Vec<db::ScoredPick> = picks.into_iter().filter(|p| !p.is_keeper).collect();
+    let (a, b) = expectation(&picks);
+
+    let mut drafts: HashMap<(String, String), DraftGrade> = HashMap::new();
+    for pick in picks {
+        let user_id = match &pick.user_id {
+            Some(u) => u.clone(),
+            None => continue,
+        };
+        let expected = a + b * f64::from(pick.pick_no.max(1)).ln();
+        let draft = drafts.entry((pick.draft_id.clone(), user_id.clone())).or_insert_with(|| DraftGrade {
+            user_id,
+            user_name: pick.user_name.clone(),
+            league_id: pick.league_id.clone(),
+            league_name: pick.league_name.clone(),
+            rank: 0,
+            grade: String::new(),
+            points: 0.0,
+            expected: 0.0,
+            value: 0.0,
+            picks: vec![],
+        });
+        draft.points += pick.points;
+        draft.expected += expected;
+        draft.value += pick.points - expected;
+        draft.picks.push(PickValue { value: pick.points - expected, expected, pick });
+    }
+
+    let mut grades: Vec<DraftGrade> = drafts.into_values().collect();
+    grades.sort_by(|x, y| y.value.total_cmp(&x.value).then_with(|| x.user_id.cmp(&y.user_id)));
+    let total = grades.len();
+    for (i, g) in grades.iter_mut().enumerate() {
+        g.rank = i + 1;
+        g.grade = GRADES[i * GRADES.len() / total].to_string();
+    }
+    grades
+}
+
+pub async fn get_grades(con: &db::DBCon, season: i32) -> Result<Vec<DraftGrade>, tokio_postgres::Error> {
+    Ok(grade(db::get_scored_picks(con, season).await?))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::ScoredPick;
+    use crate::grades;
+
+    fn pick(user_id: Option<&str>, pick_no: i32, points: f64) -> ScoredPick {
+        ScoredPick {
+            draft_id: String::from("1"),
+            league_id: String::from("1"),
+            league_name: None,
+            pick_no,
+            round: 1,
+            player_id: pick_no.to_string(),
+            first_name: None,
+            last_name: None,
+            position: None,
+            user_id: user_id.map(String::from),
+            user_name: None,
+            is_keeper: false,
+            points,
+        }
+    }
+
+    #[test]
+    fn test_expectation() {
+        // points = 100 - 10 * ln(pick_no)
+        let picks: Vec<ScoredPick> = (1..=20)
+            .map(|n| pick(Some("1"), n, 100.0 - 10.0 * f64::from(n).ln()))
+            .collect();
+        let (a, b) = grades::expectation(&picks);
+        assert!((a - 100.0).abs() < 1e-9);
+        assert!((b + 10.0).abs() < 1e-9);
+
+        assert_eq!(grades::expectation(&[pick(Some("1"), 3, 50.0), pick(Some("2"), 3, 70.0)]), (60.0, 0.0));
+    }
+
+    #[test]
+    fn test_grade() {
+        let mut keeper = pick(Some("2"), 4, 1000.0);
+        keeper.is_keeper = true;
+        let picks = vec![
+            pick(Some("1"), 1, 100.0),
+            pick(Some("2"), 2, 150.0),
+            pick(Some("2"), 3, 20.0),
+            pick(Some("1"), 4, 120.0),
+            pick(None, 5, 10.0),
+            keeper,
+        ];
+
+        let grades = grades::grade(picks);
+        assert_eq!(grades.len(), 2);
+        assert_eq!(grades[0].user_id, "1");
+        assert_eq!((grades[0].rank, grades[0].grade.as_str()), (1, "A"));
+        assert_eq!((grades[1].rank, grades[1].grade.as_str()), (2, "C"));
+        assert_eq!(grades[0].picks.len(), 2);
+        assert_eq!(grades[1].points, 170.0);
+        assert!(grades[0].value > grades[1].value);
+    }
+}
@@ -1,15 +1,56 @@
+use serde::Deserialize;
 use tera::{Tera, Context};
 use warp::{Reply, Rejection};
-use tokio_postgres::row::Row;
+use warp::http::{StatusCode, Uri};
+use std::convert::Infallible;
 use std::sync::Arc;
 use log::{info, error};
 
 use crate::db;
 use crate::config;
+use crate::grades;
+use crate::lineups;
+use crate::settings;
+use crate::trades;
+use crate::error::{self, Error};
 
-fn render(template: &str, ctx: Context, tera: Arc<Tera>) -> impl Reply {
-    let render = tera.render(template, &ctx).unwrap();
-    warp::reply::html(render)
+// How many transactions the league and user pages show
+const RECENT_TRANSACTIONS: i64 = 50;
+
+// How many transactions the wire shows at once
+const WIRE_LIMIT: i64 = 200;
+
+// How many of the most added and dropped players the wire shows
+pub const MOST_MOVED: i64 = 10;
+
+// Positions the wire can be narrowed down to
+const WIRE_POSITIONS: [&str; 6] = ["QB", "RB", "WR", "TE", "K", "DEF"];
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct DraftQuery {
+    /// Defaults to the current season
+    pub season: Option<i32>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct TradesQuery {
+    /// Defaults to the current season
+    pub season: Option<i32>,
+    pub league: Option<String>,
+    pub user: Option<String>,
+}
+
+// Left as strings so an empty field in the form means "any"
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct WireQuery {
+    pub kind: Option<String>,
+    pub position: Option<String>,
+    pub week: Option<String>,
+}
+
+pub fn render(template: &str, ctx: Context, tera: Arc<Tera>) -> std::result::Result<impl Reply, Rejection> {
+    let render = tera.render(template, &ctx).map_err(Error::Template)?;
+    Ok(warp::reply::html(render))
 }
 
 pub async fn league_handler(id: String, db_pool: Arc<db::DBPool>, tera: Arc<Tera>) -> std::result::Result<impl Reply, Rejection> {
@@ -17,29 +58,33 @@ pub async fn league_handler(id: String, db_pool: Arc<db::DBPool>, tera: Arc<Tera
     info!("GET /league/{}", id);
 
     let db = db::get_db_con(&db_pool)
-            .await;
+            .await?;
 
-    let idstr = id.to_string();
-    let rows = db.query("SELECT * FROM leagues WHERE id = $1", &[&idstr])
-            .await
-            .unwrap();
+    let league = db::get_league(&db, &id)
+        .await
+        .map_err(Error::Database)?
+        .ok_or_else(|| Error::NotFound(String::from("league")))?;
 
-    let league = db::League {
-        id: rows[0].get(0),
-        name: rows[0].get(1),
-        avatar: rows[0].get(2),
-    };
+    let standings = db::get_league_standings(&db, &id)
+        .await
+        .map_err(Error::Database)?;
 
-    let rows = db.query("SELECT * FROM users, rosters, leagues, ranks WHERE users.id = rosters.user_id AND leagues.id = rosters.league_id AND ranks.user_id = users.id AND leagues.id = $1 ORDER BY ranks.rank ASC", &[&idstr])
+    let weeks = lineups::get_current_lineups(&db, &id)
         .await
-        .unwrap();
+        .map_err(Error::Database)?;
+    let summaries = lineups::summarize(&weeks);
 
-    let standings = collect_standings(rows);
+    let filter = db::TransactionFilter { league_id: Some(id), ..Default::default() };
+    let transactions = db::get_transactions(&db, &filter, Some(RECENT_TRANSACTIONS))
+        .await
+        .map_err(Error::Database)?;
 
     let mut ctx = Context::new();
     ctx.insert("league", &league);
+    ctx.insert("lineups", &summaries);
     ctx.insert("standings", &standings);
-    Ok(render("league.html", ctx, tera)) 
+    ctx.insert("transactions", &transactions);
+    render("league.html", ctx, tera)
 }
 
 pub async fn user_handler(id: String, db_pool: Arc<db::DBPool>, tera: Arc<Tera>) -> std::result::Result<impl Reply, Rejection> {
@@ -47,138 +92,52 @@ pub async fn user_handler(id: String, db_pool: Arc<db::DBPool>, tera: Arc<Tera>)
     info!("GET /user/{}", id);
 
     let db = db::get_db_con(&db_pool)
-            .await;
+            .await?;
 
-    let current_period = db.query("SELECT season, week FROM state ORDER BY season DESC, week DESC LIMIT 1", &[])
+    let (user, roster) = db::get_user(&db, &id)
         .await
-        .unwrap();
-
-    let season: i32 = current_period[0].get("season");
-    let week: i32 = current_period[0].get("week");
+        .map_err(Error::Database)?
+        .ok_or_else(|| Error::NotFound(String::from("user")))?;
 
-    let rows = db.query("SELECT * FROM users, rosters WHERE users.id = $1 AND users.id = rosters.user_id", &[&id])
-            .await
-            .unwrap();
+    let (season, week) = db::get_time_period(&db)
+        .await?;
 
-    let row = &rows[0];
+    let players = db::get_players(&db, &id, &roster.league_id, season, week)
+        .await
+        .map_err(Error::Database)?;
 
-    let user = db::User {
-        id: row.get(0),
-        name: row.get(1),
-        avatar: row.get(2),
-    };
+    let matchups = db::get_user_matchups(&db, &id, season)
+        .await
+        .map_err(Error::Database)?;
 
-    let roster = db::Roster {
-        user_id: row.get(3),
-        league_id: row.get(4),
-        wins: row.get(5),
-        losses: row.get(6),
-        ties: row.get(7),
-        fpts: row.get(8),
-        fpts_decimal: row.get(9),
-        fpts_against: row.get(10),
-        fpts_against_decimal: row.get(11),
-        roster_id: row.get(12),
-    };
+    let filter = db::TransactionFilter { user_id: Some(id.clone()), ..Default::default() };
+    let transactions = db::get_transactions(&db, &filter, Some(RECENT_TRANSACTIONS))
+        .await
+        .map_err(Error::Database)?;
 
-    let player_rows = db.query(
-        "
-        SELECT ID,
-            FIRST_NAME,
-            LAST_NAME,
-            TEAM,
-            POSITION,
-            STATUS,
-            STARTER,
-            POINTS
-        FROM PLAYERS,
-            OWNERSHIP,
-            SCORES
-        WHERE PLAYERS.ID = OWNERSHIP.PLAYER_ID
-            AND OWNERSHIP.USER_ID = $1
-            AND SCORES.SEASON = $2
-            AND SCORES.WEEK = $3
-            AND SCORES.PLAYER_ID = PLAYERS.ID
-            AND SCORES.LEAGUE_ID = $4
-        ORDER BY STARTER DESC, (CASE POSITION
-                        WHEN 'QB' THEN 1
-                        WHEN 'RB' THEN 2
-                        WHEN 'WR' THEN 3
-                        WHEN 'TE' THEN 4
-                        WHEN 'K' THEN 5
-                        WHEN 'DEF' THEN 6
-        END) ASC
-        ",
-        &[&id, &season, &week, &roster.league_id]
-    ).await.unwrap();
-
-    let players: Vec<db::Player> = player_rows.into_iter()
-        .map(|player| {
-            db::Player {
-                id: player.get("id"),
-                first_name: player.get("first_name"),
-                last_name: player.get("last_name"),
-                team: player.get("team"),
-                position: player.get("position"),
-                status: player.get("status"),
-                starter: player.get("starter"),
-                points: player.get("points"),
-            }
-        })
-        .collect();
+    let grades = grades::get_grades(&db, season)
+        .await
+        .map_err(Error::Database)?;
+    let drafters = grades.len();
+    let draft_grade = grades.into_iter().find(|g| g.user_id == id);
 
-    let matchups_rows = db.query(
-           "
-            SELECT M1.WEEK,
-                M1.USER_ID,
-                U1.NAME AS USER_NAME,
-                U1.AVATAR AS USER_AVATAR,
-                M1.POINTS AS USER_POINTS,
-                M1.OPPONENT_ID,
-                U2.NAME AS OPPONENT_NAME,
-                U2.AVATAR AS OPPONENT_AVATAR,
-                M2.POINTS AS OPPONENT_POINTS
-            FROM MATCHUPS AS M1,
-                MATCHUPS AS M2,
-                USERS AS U1,
-                USERS AS U2
-            WHERE M1.OPPONENT_ID = M2.USER_ID
-                AND M1.WEEK = M2.WEEK
-                AND U1.ID = M1.USER_ID
-                AND U2.ID = M1.OPPONENT_ID
-                AND M1.SEASON = $1
-                AND M1.USER_ID = $2
-           ",
-           &[&season, &id]
-        )
+    let weeks: Vec<lineups::WeekLineup> = lineups::get_league_lineups(&db, &roster.league_id, season)
         .await
-        .unwrap();
-
-    let matchups: Vec<db::Week> = matchups_rows
-        .iter()
-        .map(|row| {
-            db::Week {
-                league_id: id.clone(),
-                season: season,
-                week: row.get("week"),
-                user_id: row.get("user_id"),
-                user_name: row.get("user_name"),
-                user_avatar: row.get("user_avatar"),
-                user_points: row.get("user_points"),
-                opponent_id: row.get("opponent_id"),
-                opponent_name: row.get("opponent_name"),
-                opponent_avatar: row.get("opponent_avatar"),
-                opponent_points: row.get("opponent_points"),
-            } 
-        })
+        .map_err(Error::Database)?
+        .into_iter()
+        .filter(|l| l.user_id == id)
         .collect();
 
     let mut ctx = Context::new();
+    ctx.insert("lineups", &weeks);
+    ctx.insert("draft_grade", &draft_grade);
+    ctx.insert("drafters", &drafters);
+    ctx.insert("transactions", &transactions);
     ctx.insert("user", &user);
     ctx.insert("matchups", &matchups);
     ctx.insert("roster", &roster);
     ctx.insert("players", &players);
-    Ok(render("user.html", ctx, tera))
+    render("user.html", ctx, tera)
 }
 
 pub async fn standings_handler(db_pool: Arc<db::DBPool>, tera: Arc<Tera>, config: config::Config) -> std::result::Result<impl Reply, Rejection> {
@@ -186,83 +145,230 @@ pub async fn standings_handler(db_pool: Arc<db::DBPool>, tera: Arc<Tera>, config
     info!("GET /");
 
     let db = db::get_db_con(&db_pool)
-            .await;
-
-    let current_period = db.query("SELECT season, week FROM state ORDER BY season DESC, week DESC LIMIT 1", &[])
-        .await
-        .unwrap();
-
-    let season: i32 = current_period[0].get("season");
-    let week: i32 = current_period[0].get("week");
+            .await?;
 
-    let rows = db.query("SELECT * FROM users, rosters, leagues, ranks WHERE users.id = rosters.user_id AND leagues.id = rosters.league_id AND ranks.user_id = users.id", &[])
+    let standings = db::get_standings(&db)
         .await
-        .unwrap();
+        .map_err(Error::Database)?;
 
-    let standings = collect_standings(rows);
+    let normalized = config.bigleague.scoring.is_some();
     let bracket = match db::get_bracket(db, config).await {
         Ok(b) => b,
         Err(e) => {
             error!("Couldn't get bracket: {}", e);
-            db::Bracket {
-                num_teams: 0,
-                start_week: 0,
-                champ_week: 0,
-                stages: vec![],
-            }
+            db::Bracket::empty()
         }
     };
 
     let mut ctx = Context::new();
     ctx.insert("standings", &standings);
+    ctx.insert("normalized", &normalized);
     ctx.insert("bracket", &bracket);
-    Ok(render("standings.html", ctx, tera))
+    render("standings.html", ctx, tera)
 }
 
-fn collect_standings(rows: Vec<Row>) -> Vec<db::Standing> {
-    rows.into_iter()
-        .map(|row| {
-            let user = db::User {
-                id: row.get(0),
-                name: row.get(1),
-                avatar: row.get(2),
-            };
-
-            let roster = db::Roster {
-                user_id: row.get(3),
-                league_id: row.get(4),
-                wins: row.get(5),
-                losses: row.get(6),
-                ties: row.get(7),
-                fpts: row.get(8),
-                fpts_decimal: row.get(9),
-                fpts_against: row.get(10),
-                fpts_against_decimal: row.get(11),
-                roster_id: row.get(12),
-            };
-          
-            let league = db::League {
-                id: row.get(13),
-                name: row.get(14),
-                avatar: row.get(15),
-            };
-
-            let rank: i64 = row.get(17);
-
-            db::Standing {
-                user,
-                roster,
-                league,
-                rank,
-            }
-        })
-        .collect()
+// The big league wide transaction feed for a season
+pub fn wire_filter(season: Option<i32>, kind: Option<String>, position: Option<String>, week: Option<i32>) -> Result<db::TransactionFilter, Error> {
+    let set = |field: Option<String>| field.map(|f| f.trim().to_string()).filter(|f| !f.is_empty());
+    let kind = set(kind);
+    if let Some(k) = &kind {
+        if !db::TRANSACTION_KINDS.contains(&k.as_str()) {
+            return Err(Error::BadRequest(format!("kind must be one of {}", db::TRANSACTION_KINDS.join(", "))));
+        }
+    }
+    if week.is_some_and(|w| w < 1) {
+        return Err(Error::BadRequest(String::from("week starts at 1")));
+    }
+    Ok(db::TransactionFilter {
+        season,
+        week,
+        kind,
+        position: set(position).map(|p| p.to_uppercase()),
+        ..Default::default()
+    })
 }
 
-pub async fn not_found_handler(tera: Arc<Tera>) -> std::result::Result<impl Reply, Rejection> {
+pub async fn wire_handler(query: WireQuery, db_pool: Arc<db::DBPool>, tera: Arc<Tera>) -> std::result::Result<impl Reply, Rejection> {
+
+    info!("GET /wire");
 
-    info!("GET unknown endpoint");
+    let week = match query.week.as_deref().map(str::trim).filter(|w| !w.is_empty()) {
+        Some(w) => Some(w.parse::<i32>().map_err(|_| Error::BadRequest(String::from("week has to be a number")))?),
+        None => None,
+    };
+
+    let db = db::get_db_con(&db_pool)
+            .await?;
+
+    let period = db::get_time_period_if_fetched(&db)
+        .await
+        .map_err(Error::Database)?;
+
+    let filter = wire_filter(period.map(|(season, _)| season), query.kind, query.position, week)?;
+
+    let (transactions, most_added, most_dropped, moves_week) = match period {
+        Some((season, current_week)) => {
+            let transactions = db::get_transactions(&db, &filter, Some(WIRE_LIMIT))
+                .await
+                .map_err(Error::Database)?;
+
+            // The most moved players are for a single week, the one being
+            // looked at or the current one
+            let moves_week = week.unwrap_or(current_week);
+            let most_added = db::get_most_moved(&db, season, moves_week, "add", filter.position.as_deref(), MOST_MOVED)
+                .await
+                .map_err(Error::Database)?;
+            let most_dropped = db::get_most_moved(&db, season, moves_week, "drop", filter.position.as_deref(), MOST_MOVED)
+                .await
+                .map_err(Error::Database)?;
+            (transactions, most_added, most_dropped, Some(moves_week))
+        },
+        // Nothing has been ingested until the state is fetched
+        None => (vec![], vec![], vec![], week),
+    };
+
+    let mut ctx = Context::new();
+    ctx.insert("transactions", &transactions);
+    ctx.insert("most_added", &most_added);
+    ctx.insert("most_dropped", &most_dropped);
+    ctx.insert("moves_week", &moves_week);
+    ctx.insert("filter", &filter);
+    ctx.insert("limit", &WIRE_LIMIT);
+    ctx.insert("kinds", &db::TRANSACTION_KINDS);
+    ctx.insert("positions", &WIRE_POSITIONS);
+    render("wire.html", ctx, tera)
+}
+
+pub async fn draft_handler(query: DraftQuery, db_pool: Arc<db::DBPool>, tera: Arc<Tera>) -> std::result::Result<impl Reply, Rejection> {
+
+    info!("GET /draft?season={:?}", query.season);
+
+    let db = db::get_db_con(&db_pool)
+            .await?;
+
+    let season = match query.season {
+        Some(s) => Some(s),
+        None => db::get_time_period_if_fetched(&db).await.map_err(Error::Database)?.map(|(season, _)| season),
+    };
+
+    let (drafts, adp, grades) = match season {
+        Some(season) => {
+            let drafts = db::get_drafts(&db, season, None)
+                .await
+                .map_err(Error::Database)?;
+
+            let adp = db::get_adp(&db, season)
+                .await
+                .map_err(Error::Database)?;
+
+            let grades = grades::get_grades(&db, season)
+                .await
+                .map_err(Error::Database)?;
+            (drafts, adp, grades)
+        },
+        // Nothing has been ingested until the state is fetched
+        None => (vec![], vec![], vec![]),
+    };
+
+    let mut ctx = Context::new();
+    ctx.insert("grades", &grades);
+    ctx.insert("season", &season);
+    ctx.insert("drafts", &drafts);
+    ctx.insert("adp", &adp);
+    render("draft.html", ctx, tera)
+}
+
+pub async fn trades_handler(query: TradesQuery, db_pool: Arc<db::DBPool>, tera: Arc<Tera>) -> std::result::Result<impl Reply, Rejection> {
+
+    info!("GET /trades?season={:?}&league={:?}&user={:?}", query.season, query.league, query.user);
+
+    let db = db::get_db_con(&db_pool)
+            .await?;
+
+    let season = match query.season {
+        Some(s) => Some(s),
+        None => db::get_time_period_if_fetched(&db).await.map_err(Error::Database)?.map(|(season, _)| season),
+    };
+
+    let trades = match season {
+        Some(season) => trades::get_trades(&db, season, query.league, query.user)
+            .await
+            .map_err(Error::Database)?,
+        // Nothing has been ingested until the state is fetched
+        None => vec![],
+    };
+
+    let mut ctx = Context::new();
+    ctx.insert("season", &season);
+    ctx.insert("trades", &trades);
+    render("trades.html", ctx, tera)
+}
+
+pub async fn settings_handler(db_pool: Arc<db::DBPool>, tera: Arc<Tera>) -> std::result::Result<impl Reply, Rejection> {
+
+    info!("GET /settings");
+
+    let db = db::get_db_con(&db_pool)
+            .await?;
+
+    let leagues = db::get_league_settings(&db)
+        .await
+        .map_err(Error::Database)?;
+
+    let mut ctx = Context::new();
+    ctx.insert("comparison", &settings::compare(leagues));
+    render("settings.html", ctx, tera)
+}
+
+pub async fn handle_rejection(err: Rejection, tera: Arc<Tera>) -> std::result::Result<impl Reply, Infallible> {
+
+    let (status, message) = error::describe_rejection(&err);
+
+    // Send anyone who isn't logged in to log in
+    if status == StatusCode::UNAUTHORIZED {
+        return Ok(warp::redirect::see_other(Uri::from_static("/login")).into_response());
+    }
+
+    let template = if status == StatusCode::NOT_FOUND {
+        info!("GET unknown endpoint");
+        "notfound.html"
+    } else {
+        "error.html"
+    };
+
+    let mut ctx = Context::new();
+    ctx.insert("status", &status.as_u16());
+    ctx.insert("message", &message);
+
+    let body = match tera.render(template, &ctx) {
+        Ok(body) => body,
+        Err(e) => {
+            error!("couldn't render {}: {:?}", template, e);
+            message
+        }
+    };
+
+    Ok(warp::reply::with_status(warp::reply::html(body), status).into_response())
+}
 
-    let ctx = Context::new();
-    Ok(render("notfound.html", ctx, tera)) 
+#[cfg(test)]
+mod tests {
+    use crate::db;
+    use crate::handlers;
+
+    #[test]
+    fn test_wire_filter() {
+        let filter = handlers::wire_filter(Some(2023), Some(String::from("waiver")), Some(String::from(" rb ")), Some(4)).unwrap();
+        assert_eq!(filter, db::TransactionFilter {
+            season: Some(2023),
+            week: Some(4),
+            kind: Some(String::from("waiver")),
+            position: Some(String::from("RB")),
+            ..Default::default()
+        });
+
+        assert_eq!(handlers::wire_filter(Some(2023), Some(String::from("")), None, None).unwrap().kind, None);
+        assert!(handlers::wire_filter(Some(2023), Some(String::from("steal")), None, None).is_err());
+        assert!(handlers::wire_filter(Some(2023), None, None, Some(0)).is_err());
+    }
 }
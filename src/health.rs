@@ -0,0 +1,197 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use warp::{Filter, Reply, Rejection};
+use warp::http::StatusCode;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::Infallible;
+use std::sync::{Arc, RwLock};
+use log::{debug, warn};
+
+use crate::config;
+use crate::db;
+
+// How many runs in a row a job can fail before we stop
+// reporting ready
+const DEFAULT_MAX_FAILED_INTERVALS: u32 = 3;
+
+// Outcome of the most recent runs of each ingestion job, shared
+// between the stats loop and the readiness check. Jobs that run per
+// league are tracked separately for every league, so one league
+// succeeding doesn't hide another that keeps failing.
+#[derive(Clone, Default)]
+pub struct Jobs {
+    statuses: Arc<RwLock<HashMap<String, JobStatus>>>,
+}
+
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct JobStatus {
+    pub consecutive_failures: u32,
+    pub last_success: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Check {
+    pub ok: bool,
+    pub detail: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Readiness {
+    pub ready: bool,
+    pub checks: BTreeMap<&'static str, Check>,
+    pub jobs: BTreeMap<String, JobStatus>,
+}
+
+impl Jobs {
+    pub fn new() -> Jobs {
+        Jobs::default()
+    }
+
+    pub fn succeeded(&self, job: &str, league_id: Option<&str>) {
+        if let Ok(mut statuses) = self.statuses.write() {
+            let status = statuses.entry(key(job, league_id)).or_default();
+            status.consecutive_failures = 0;
+            status.last_success = Some(Utc::now());
+        }
+    }
+
+    pub fn failed(&self, job: &str, league_id: Option<&str>, error: String) {
+        if let Ok(mut statuses) = self.statuses.write() {
+            let status = statuses.entry(key(job, league_id)).or_default();
+            status.consecutive_failures += 1;
+            status.last_error = Some(error);
+        }
+    }
+
+    // Stops tracking a league's jobs once it's no longer ingested
+    pub fn remove_league(&self, league_id: &str) {
+        let suffix = key("", Some(league_id));
+        if let Ok(mut statuses) = self.statuses.write() {
+            statuses.retain(|name, _| !name.ends_with(&suffix));
+        }
+    }
+
+    pub fn statuses(&self) -> BTreeMap<String, JobStatus> {
+        match self.statuses.read() {
+            Ok(statuses) => statuses.iter().map(|(k, v)| (k.clone(), v.clone())).collect(),
+            Err(e) => {
+                warn!("couldn't read job statuses: {}", e);
+                BTreeMap::new()
+            }
+        }
+    }
+
+    // Jobs that have failed more than max_failures runs in a row
+    pub fn failing(&self, max_failures: u32) -> Vec<String> {
+        self.statuses()
+            .into_iter()
+            .filter(|(_, status)| status.consecutive_failures > max_failures)
+            .map(|(job, _)| job)
+            .collect()
+    }
+}
+
+// Statuses are listed as the job name, followed by the league for
+// jobs that run per league
+fn key(job: &str, league_id: Option<&str>) -> String {
+    match league_id {
+        Some(league_id) => format!("{} {}", job, league_id),
+        None => job.to_string(),
+    }
+}
+
+impl Check {
+    fn pass(detail: impl Into<String>) -> Check {
+        Check { ok: true, detail: detail.into() }
+    }
+
+    fn fail(detail: impl Into<String>) -> Check {
+        Check { ok: false, detail: detail.into() }
+    }
+}
+
+pub fn with_jobs(jobs: Jobs) -> impl Filter<Extract = (Jobs,), Error = Infallible> + Clone {
+    warp::any().map(move || jobs.clone())
+}
+
+pub async fn healthz_handler() -> std::result::Result<impl Reply, Rejection> {
+
+    debug!("GET /healthz");
+
+    Ok(warp::reply::json(&Check::pass("up")))
+}
+
+pub async fn readyz_handler(db_pool: Arc<db::DBPool>, jobs: Jobs, config: config::Config) -> std::result::Result<impl Reply, Rejection> {
+
+    debug!("GET /readyz");
+
+    let mut checks = BTreeMap::new();
+
+    match db::get_db_con(&db_pool).await {
+        Ok(con) => {
+            checks.insert("database", Check::pass("connection checked out"));
+
+            let schema = match db::get_missing_tables(&con).await {
+                Ok(missing) if missing.is_empty() => Check::pass("all tables present"),
+                Ok(missing) => Check::fail(format!("missing {}", missing.join(", "))),
+                Err(e) => Check::fail(e.to_string()),
+            };
+            checks.insert("schema", schema);
+
+            let state = match db::has_state(&con).await {
+                Ok(true) => Check::pass("state fetched"),
+                Ok(false) => Check::fail("state hasn't been fetched yet"),
+                Err(e) => Check::fail(e.to_string()),
+            };
+            checks.insert("state", state);
+        },
+        Err(e) => {
+            checks.insert("database", Check::fail(e.to_string()));
+        },
+    }
+
+    let max_failures = config.stats.max_failed_intervals.unwrap_or(DEFAULT_MAX_FAILED_INTERVALS);
+    let failing = jobs.failing(max_failures);
+    let ingestion = if failing.is_empty() {
+        Check::pass("no jobs failing")
+    } else {
+        Check::fail(format!("failing for more than {} intervals: {}", max_failures, failing.join(", ")))
+    };
+    checks.insert("ingestion", ingestion);
+
+    let ready = checks.values().all(|c| c.ok);
+    let status = if ready { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    let readiness = Readiness { ready, checks, jobs: jobs.statuses() };
+
+    Ok(warp::reply::with_status(warp::reply::json(&readiness), status))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::health;
+
+    #[test]
+    fn test_failing_jobs() {
+        let jobs = health::Jobs::new();
+        jobs.succeeded("state", None);
+        for _ in 0..3 {
+            jobs.failed("rosters", Some("1"), String::from("sleeper is down"));
+        }
+        assert!(jobs.failing(3).is_empty());
+
+        // Another league doing fine doesn't reset the failing one
+        jobs.succeeded("rosters", Some("2"));
+        jobs.failed("rosters", Some("1"), String::from("sleeper is down"));
+        assert_eq!(jobs.failing(3), vec![String::from("rosters 1")]);
+
+        jobs.succeeded("rosters", Some("1"));
+        assert!(jobs.failing(3).is_empty());
+        assert_eq!(jobs.statuses()["rosters 1"].consecutive_failures, 0);
+        assert_eq!(jobs.statuses()["rosters 1"].last_error.as_deref(), Some("sleeper is down"));
+
+        jobs.remove_league("1");
+        assert!(!jobs.statuses().contains_key("rosters 1"));
+        assert!(jobs.statuses().contains_key("rosters 2"));
+    }
+}
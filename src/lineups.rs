@@ -0,0 +1,238 @@
+use serde::{Serialize, Deserialize};
+use utoipa::ToSchema;
+use std::collections::HashMap;
+
+use crate::db;
+
+// Slots that don't score
+const BENCH_SLOTS: [&str; 3] = ["BN", "IR", "TAXI"];
+
+// The player positions each of Sleeper's lineup slots takes
+fn slot_positions(slot: &str) -> Vec<&str> {
+    match slot {
+        "FLEX" => vec!["RB", "WR", "TE"],
+        "WRRB_FLEX" => vec!["RB", "WR"],
+        "REC_FLEX" => vec!["WR", "TE"],
+        "SUPER_FLEX" => vec!["QB", "RB", "WR", "TE"],
+        "DL" => vec!["DL", "DE", "DT"],
+        "DB" => vec!["DB", "CB", "S"],
+        "IDP_FLEX" => vec!["DL", "DE", "DT", "LB", "DB", "CB", "S"],
+        slot => vec![slot],
+    }
+}
+
+// The most a lineup could have scored with the players on the roster.
+// Slots are filled from the pickiest to the most flexible, each with
+// the best player left that can play it, which is optimal as long as
+// every pair of slots either shares no positions or one takes all of
+// the other's (true for every standard lineup).
+pub fn optimal_points(roster_positions: &[String], players: &[&db::LineupPlayer]) -> f64 {
+    let mut slots: Vec<Vec<&str>> = roster_positions
+        .iter()
+        .filter(|s| !BENCH_SLOTS.contains(&s.as_str()))
+        .map(|s| slot_positions(s))
+        .collect();
+    slots.sort_by_key(|s| s.len());
+
+    let mut available: Vec<&db::LineupPlayer> = players.to_vec();
+    available.sort_by(|a, b| b.points.total_cmp(&a.points));
+
+    let mut total = 0.0;
+    for slot in slots {
+        let best = available
+            .iter()
+            .position(|p| p.position.as_deref().is_some_and(|pos| slot.contains(&pos)));
+        if let Some(i) = best {
+            total += available.remove(i).points;
+        }
+    }
+    total
+}
+
+// How one user's lineup did against the best they could have set
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct WeekLineup {
+    pub week: i32,
+    pub user_id: String,
+    pub user_name: Option<String>,
+    pub points: f64,
+    pub optimal: f64,
+    /// Points scored by players left on the bench
+    pub bench: f64,
+    /// points as a percentage of optimal
+    pub efficiency: f64,
+    pub opponent_id: String,
+    pub opponent_points: f64,
+    /// Lost the week, but the optimal lineup would have won it
+    pub optimal_win: bool,
+}
+
+// A user's lineups over a season added up
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct LineupSummary {
+    pub user_id: String,
+    pub user_name: Option<String>,
+    pub points: f64,
+    pub optimal: f64,
+    pub bench: f64,
+    pub efficiency: f64,
+    pub optimal_wins: usize,
+}
+
+fn efficiency(points: f64, optimal: f64) -> f64 {
+    if optimal > 0.0 {
+        100.0 * points / optimal
+    } else {
+        100.0
+    }
+}
+
+// Every user's lineup each week that has a matchup, by week
+pub fn analyze(roster_positions: &[String], players: &[db::LineupPlayer], matchups: Vec<db::MatchupPoints>) -> Vec<WeekLineup> {
+    let mut rosters: HashMap<(i32, &str), Vec<&db::LineupPlayer>> = HashMap::new();
+    for p in players {
+        rosters.entry((p.week, p.user_id.as_str())).or_default().push(p);
+    }
+
+    matchups
+        .into_iter()
+        .map(|m| {
+            let roster = rosters.get(&(m.week, m.user_id.as_str())).cloned().unwrap_or_default();
+            // Never less than what was actually scored, in case a
+            // player's position has changed since
+            let optimal = optimal_points(roster_positions, &roster).max(m.points);
+            let bench = roster.iter().filter(|p| !p.starter).map(|p| p.points).sum();
+            WeekLineup {
+                week: m.week,
+                efficiency: efficiency(m.points, optimal),
+                optimal_win: m.points < m.opponent_points && optimal > m.opponent_points,
+                user_id: m.user_id,
+                user_name: m.user_name,
+                points: m.points,
+                optimal,
+                bench,
+                opponent_id: m.opponent_id,
+                opponent_points: m.opponent_points,
+            }
+        })
+        .collect()
+}
+
+// Season totals for everyone in the league, most efficient first
+pub fn summarize(weeks: &[WeekLineup]) -> Vec<LineupSummary> {
+    let mut summaries: Vec<LineupSummary> = vec![];
+    for w in weeks {
+        let i = match summaries.iter().position(|s| s.user_id == w.user_id) {
+            Some(i) => i,
+            None => {
+                summaries.push(LineupSummary {
+                    user_id: w.user_id.clone(),
+                    user_name: w.user_name.clone(),
+                    points: 0.0,
+                    optimal: 0.0,
+                    bench: 0.0,
+                    efficiency: 0.0,
+                    optimal_wins: 0,
+                });
+                summaries.len() - 1
+            },
+        };
+        let s = &mut summaries[i];
+        s.points += w.points;
+        s.optimal += w.optimal;
+        s.bench += w.bench;
+        s.optimal_wins += usize::from(w.optimal_win);
+    }
+    for s in summaries.iter_mut() {
+        s.efficiency = efficiency(s.points, s.optimal);
+    }
+    summaries.sort_by(|a, b| b.efficiency.total_cmp(&a.efficiency).then_with(|| a.user_id.cmp(&b.user_id)));
+    summaries
+}
+
+// A league's lineups for the current season, none until the state
+// has been fetched
+pub async fn get_current_lineups(con: &db::DBCon, league_id: &str) -> Result<Vec<WeekLineup>, tokio_postgres::Error> {
+    match db::get_time_period_if_fetched(con).await? {
+        Some((season, _)) => get_league_lineups(con, league_id, season).await,
+        None => Ok(vec![]),
+    }
+}
+
+// A league's lineups for the weeks of a season that are over
+pub async fn get_league_lineups(con: &db::DBCon, league_id: &str, season: i32) -> Result<Vec<WeekLineup>, tokio_postgres::Error> {
+    let (current_season, current_week) = match db::get_time_period_if_fetched(con).await? {
+        Some(period) => period,
+        None => return Ok(vec![]),
+    };
+    let last_week = if season < current_season { i32::MAX } else { current_week };
+
+    let roster_positions = db::get_roster_positions(con, league_id).await?;
+    let players = db::get_lineup_players(con, league_id, season, last_week).await?;
+    let matchups = db::get_league_matchup_points(con, league_id, season, last_week).await?;
+    Ok(analyze(&roster_positions, &players, matchups))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{LineupPlayer, MatchupPoints};
+    use crate::lineups;
+
+    fn player(player_id: &str, position: &str, starter: bool, points: f64) -> LineupPlayer {
+        LineupPlayer {
+            week: 1,
+            user_id: String::from("1"),
+            player_id: player_id.to_string(),
+            position: Some(position.to_string()),
+            starter,
+            points,
+        }
+    }
+
+    #[test]
+    fn test_optimal_points() {
+        let positions: Vec<String> = ["QB", "RB", "WR", "FLEX", "SUPER_FLEX", "BN", "BN"]
+            .iter()
+            .map(|p| p.to_string())
+            .collect();
+        let players = [
+            player("qb1", "QB", true, 20.0),
+            player("qb2", "QB", false, 18.0),
+            player("rb1", "RB", true, 5.0),
+            player("rb2", "RB", false, 15.0),
+            player("wr1", "WR", true, 10.0),
+            player("te1", "TE", false, 12.0),
+            player("k1", "K", false, 30.0),
+        ];
+        let refs: Vec<&LineupPlayer> = players.iter().collect();
+
+        // QB 20, RB 15, WR 10, FLEX TE 12, SUPER_FLEX QB 18, the
+        // kicker has nowhere to go
+        assert_eq!(lineups::optimal_points(&positions, &refs), 75.0);
+    }
+
+    #[test]
+    fn test_analyze() {
+        let positions = vec![String::from("QB"), String::from("BN")];
+        let players = vec![
+            player("qb1", "QB", true, 10.0),
+            player("qb2", "QB", false, 25.0),
+        ];
+        let matchups = vec![MatchupPoints {
+            week: 1,
+            user_id: String::from("1"),
+            user_name: None,
+            points: 10.0,
+            opponent_id: String::from("2"),
+            opponent_points: 20.0,
+        }];
+
+        let weeks = lineups::analyze(&positions, &players, matchups);
+        assert_eq!(weeks.len(), 1);
+        assert_eq!((weeks[0].optimal, weeks[0].bench, weeks[0].efficiency), (25.0, 25.0, 40.0));
+        assert!(weeks[0].optimal_win);
+
+        let summary = lineups::summarize(&weeks);
+        assert_eq!((summary[0].optimal_wins, summary[0].efficiency), (1, 40.0));
+    }
+}
@@ -0,0 +1,208 @@
+use chrono::{DateTime, Datelike, NaiveTime, Timelike, Utc, Weekday};
+use futures::stream::{self, Stream};
+use futures::{SinkExt, StreamExt};
+use tokio::sync::broadcast::error::RecvError;
+use warp::{Reply, Rejection};
+use warp::ws::{Message, WebSocket, Ws};
+use std::convert::Infallible;
+use log::{debug, info, warn};
+
+use crate::db;
+use crate::events;
+use crate::config;
+
+// Roughly when NFL games are played, in UTC. Windows that end
+// before they start run past midnight into the next day.
+const DEFAULT_WINDOWS: [&str; 3] = [
+    "Fri 00:00-04:30",
+    "Sun 17:00-04:30",
+    "Tue 00:00-04:30",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GameWindow {
+    pub day: Weekday,
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl GameWindow {
+    // Parses windows like "Sun 17:00-04:30"
+    pub fn parse(raw: &str) -> Option<GameWindow> {
+        let (day, times) = raw.trim().split_once(' ')?;
+        let (start, end) = times.trim().split_once('-')?;
+        Some(GameWindow {
+            day: day.parse::<Weekday>().ok()?,
+            start: NaiveTime::parse_from_str(start, "%H:%M").ok()?,
+            end: NaiveTime::parse_from_str(end, "%H:%M").ok()?,
+        })
+    }
+
+    pub fn contains(&self, now: DateTime<Utc>) -> bool {
+        let time = NaiveTime::from_hms_opt(now.hour(), now.minute(), now.second())
+            .unwrap_or(NaiveTime::MIN);
+        let day = now.weekday();
+        if self.start <= self.end {
+            day == self.day && time >= self.start && time <= self.end
+        } else {
+            (day == self.day && time >= self.start)
+                || (day == self.day.succ() && time <= self.end)
+        }
+    }
+}
+
+pub fn game_windows(config: &config::Config) -> Vec<GameWindow> {
+    let raw: Vec<String> = match config.stats.live.as_ref().and_then(|l| l.windows.clone()) {
+        Some(w) => w,
+        None => DEFAULT_WINDOWS.iter().map(|w| w.to_string()).collect(),
+    };
+
+    raw.iter()
+        .filter_map(|w| {
+            let window = GameWindow::parse(w);
+            if window.is_none() {
+                warn!("ignoring invalid live window: {}", w);
+            }
+            window
+        })
+        .collect()
+}
+
+// Games can only be in progress during the regular season or
+// playoffs, and then only inside one of the game windows.
+pub async fn is_live(db_pool: &db::DBPool, windows: &[GameWindow]) -> bool {
+    let now = Utc::now();
+    if !windows.iter().any(|w| w.contains(now)) {
+        return false;
+    }
+
+    let con = match db::get_db_con(db_pool).await {
+        Ok(con) => con,
+        Err(e) => {
+            warn!("couldn't check for live games: {}", e);
+            return false;
+        }
+    };
+
+    match con.query_opt("SELECT season_type FROM state ORDER BY season DESC, week DESC LIMIT 1", &[]).await {
+        Ok(Some(row)) => {
+            let season_type: String = row.get("season_type");
+            season_type == "regular" || season_type == "post"
+        },
+        Ok(None) => false,
+        Err(e) => {
+            warn!("couldn't check for live games: {}", e);
+            false
+        },
+    }
+}
+
+fn event_stream(events: events::EventSender) -> impl Stream<Item = Result<warp::sse::Event, Infallible>> {
+    stream::unfold(events.subscribe(), |mut rx| async move {
+        loop {
+            match rx.recv().await {
+                Ok(event) => {
+                    let sse = warp::sse::Event::default()
+                        .event(event.name())
+                        .json_data(&event)
+                        .unwrap_or_default();
+                    return Some((Ok(sse), rx));
+                },
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("live subscriber fell behind, skipped {} events", skipped);
+                },
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    })
+}
+
+pub async fn live_handler(events: events::EventSender) -> std::result::Result<impl Reply, Rejection> {
+
+    info!("GET /live");
+
+    Ok(warp::sse::reply(warp::sse::keep_alive().stream(event_stream(events))))
+}
+
+pub async fn ws_handler(ws: Ws, subscription: events::Subscription, events: events::EventSender) -> std::result::Result<impl Reply, Rejection> {
+
+    info!("GET /ws {:?}", subscription);
+
+    Ok(ws.on_upgrade(move |socket| feed(socket, subscription, events)))
+}
+
+// Streams events matching the subscription to a websocket client.
+// Clients can change their subscription at any time by sending
+// a new one as JSON, e.g. {"league": "1234", "user": null}
+async fn feed(socket: WebSocket, mut subscription: events::Subscription, events: events::EventSender) {
+    let (mut tx, mut rx) = socket.split();
+    let mut updates = events.subscribe();
+
+    loop {
+        tokio::select! {
+            msg = rx.next() => {
+                match msg {
+                    Some(Ok(msg)) if msg.is_text() => {
+                        match serde_json::from_str::<events::Subscription>(msg.to_str().unwrap_or_default()) {
+                            Ok(s) => {
+                                debug!("feed subscription changed to {:?}", s);
+                                subscription = s;
+                            },
+                            Err(e) => warn!("ignoring invalid feed subscription: {}", e),
+                        }
+                    },
+                    Some(Ok(msg)) if msg.is_close() => break,
+                    Some(Ok(_)) => {},
+                    Some(Err(e)) => {
+                        debug!("feed client error: {}", e);
+                        break;
+                    },
+                    None => break,
+                }
+            }
+            update = updates.recv() => {
+                match update {
+                    Ok(event) => {
+                        if !subscription.matches(&event) {
+                            continue;
+                        }
+                        let text = match serde_json::to_string(&event) {
+                            Ok(t) => t,
+                            Err(e) => {
+                                warn!("couldn't serialize event: {}", e);
+                                continue;
+                            }
+                        };
+                        if tx.send(Message::text(text)).await.is_err() {
+                            break;
+                        }
+                    },
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!("feed subscriber fell behind, skipped {} events", skipped);
+                    },
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        }
+    }
+
+    debug!("feed client disconnected");
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::live::GameWindow;
+    use chrono::{TimeZone, Utc, Weekday};
+
+    #[test]
+    fn test_game_window_wraps_midnight() {
+        let window = GameWindow::parse("Sun 17:00-04:30").unwrap();
+        assert_eq!(window.day, Weekday::Sun);
+
+        // 2023-10-15 was a Sunday
+        assert!(window.contains(Utc.with_ymd_and_hms(2023, 10, 15, 20, 0, 0).unwrap()));
+        assert!(window.contains(Utc.with_ymd_and_hms(2023, 10, 16, 3, 0, 0).unwrap()));
+        assert!(!window.contains(Utc.with_ymd_and_hms(2023, 10, 16, 5, 0, 0).unwrap()));
+        assert!(!window.contains(Utc.with_ymd_and_hms(2023, 10, 15, 12, 0, 0).unwrap()));
+    }
+}
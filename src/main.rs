@@ -3,36 +3,85 @@ use warp::Filter;
 use std::sync::Arc;
 use std::convert::Infallible;
 use std::net::{IpAddr, Ipv4Addr};
-use log::{debug, error, info, warn};
-
+use clap::Parser;
+use log::{info, warn};
+
+mod admin;
+mod api;
+mod auth;
+mod cache;
+mod cli;
 mod db;
 mod stats;
+mod trades;
 mod handlers;
 mod config;
+mod error;
+mod events;
+mod grades;
+mod health;
+mod lineups;
+mod live;
+mod reload;
+mod settings;
+mod metrics;
 
 fn with_tera(tera: Arc<Tera>) -> impl Filter<Extract = (Arc<Tera>,), Error = Infallible> + Clone {
     warp::any().map(move || tera.clone())
 }
 
-fn with_config(config: config::Config) -> impl Filter<Extract = (config::Config,), Error = Infallible> + Clone {
-    warp::any().map(move || config.clone())
-}
-
 #[tokio::main]
 async fn main() {
 
     env_logger::init();
 
-    let config: config::Config = config::read_config("Bigleague.toml").expect("Couldn't parse config file"); 
+    let cli = cli::Cli::parse();
+
+    let config = match config::read_config(&cli.config) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    match cli.command.unwrap_or(cli::Command::Serve) {
+        cli::Command::Serve => serve(cli.config, config).await,
+        command => {
+            if let Err(e) = cli::run(command, cli.config, config).await {
+                log::error!("{}", e);
+                std::process::exit(1);
+            }
+        },
+    }
+}
 
-    let pool = Arc::new(db::create_pool(config.clone()).unwrap());
- 
-    db::create_tables(pool.clone()).await.unwrap();
+async fn serve(config_path: String, config: config::Config) {
+
+    let pool = cli::setup_db(&config).await.unwrap();
+
+    let problems = cli::check_bids(&config, &pool).await;
+    if !problems.is_empty() {
+        eprintln!("{}", config::ConfigError::Invalid(problems));
+        std::process::exit(1);
+    }
     
+    let events = events::channel();
+    let page_cache = cache::Cache::new();
+    let jobs = health::Jobs::new();
+
     let stats_pool = pool.clone();
-    let stats_config = config.clone();
+    // Leagues, intervals and playoff settings are picked up from
+    // here whenever the config changes
+    let live_config = reload::watch_config(config_path, config.clone());
+
+    let stats_config = live_config.clone();
+    let stats_events = events.clone();
+    let stats_cache = page_cache.clone();
+    let stats_jobs = jobs.clone();
+    let (triggers, stats_triggers) = stats::triggers();
     tokio::spawn(async move {
-            let _ = stats::stats_loop(stats_config, stats_pool).await;
+            let _ = stats::stats_loop(stats_config, stats_pool, stats_events, stats_cache, stats_jobs, stats_triggers).await;
         }
     );
 
@@ -49,27 +98,99 @@ async fn main() {
         .and(with_tera(tera.clone()))
         .and_then(handlers::user_handler);
 
+    let wire_route = warp::path!("wire")
+        .and(warp::query::<handlers::WireQuery>())
+        .and(db::with_db(pool.clone()))
+        .and(with_tera(tera.clone()))
+        .and_then(handlers::wire_handler);
+
+    let draft_route = warp::path!("draft")
+        .and(warp::query::<handlers::DraftQuery>())
+        .and(db::with_db(pool.clone()))
+        .and(with_tera(tera.clone()))
+        .and_then(handlers::draft_handler);
+
+    let trades_route = warp::path!("trades")
+        .and(warp::query::<handlers::TradesQuery>())
+        .and(db::with_db(pool.clone()))
+        .and(with_tera(tera.clone()))
+        .and_then(handlers::trades_handler);
+
+    let settings_route = warp::path!("settings")
+        .and(db::with_db(pool.clone()))
+        .and(with_tera(tera.clone()))
+        .and_then(handlers::settings_handler);
+
     let standings_route = warp::path::end()
         .and(db::with_db(pool.clone()))
         .and(with_tera(tera.clone()))
-        .and(with_config(config.clone()))
+        .and(reload::with_config(live_config.clone()))
         .and_then(handlers::standings_handler);
 
-    let not_found_route = warp::any()
-        .and(with_tera(tera.clone()))
-        .and_then(handlers::not_found_handler);
+    let live_route = warp::path!("live")
+        .and(events::with_events(events.clone()))
+        .and_then(live::live_handler);
+
+    let ws_route = warp::path!("ws")
+        .and(warp::ws())
+        .and(warp::query::<events::Subscription>())
+        .and(events::with_events(events.clone()))
+        .and_then(live::ws_handler);
+
+    let metrics_route = warp::path!("metrics")
+        .and(db::with_db(pool.clone()))
+        .and_then(metrics::metrics_handler);
+
+    let healthz_route = warp::path!("healthz")
+        .and_then(health::healthz_handler);
+
+    let readyz_route = warp::path!("readyz")
+        .and(db::with_db(pool.clone()))
+        .and(health::with_jobs(jobs.clone()))
+        .and(reload::with_config(live_config.clone()))
+        .and_then(health::readyz_handler);
 
     let static_route = warp::path("static")
         .and(warp::fs::dir("static"));
 
-    let routes = warp::get().and(
-        league_route
+    let api_route = api::routes(pool.clone(), live_config.clone());
+
+    let secure_cookies = config.web.secure_cookies.unwrap_or(true);
+    let admin_routes = auth::routes(pool.clone(), tera.clone(), secure_cookies)
+        .or(admin::routes(pool.clone(), tera.clone(), triggers, page_cache.clone(), jobs));
+
+    // Pages only change when new data is ingested, so they are
+    // served from the cache until then. Boxed so the type of the whole
+    // route tree stays shallow enough for the compiler.
+    let page_routes = cache::cached(
+        page_cache,
+        api_route
+            .or(league_route)
             .or(user_route)
+            .or(wire_route)
+            .or(draft_route)
+            .or(trades_route)
+            .or(settings_route)
             .or(standings_route)
-            .or(static_route)
-            .or(not_found_route)
-            .with(warp::cors().allow_any_origin())
-    );
+    )
+    .boxed();
+
+    let routes = admin_routes.or(
+        warp::get().and(
+            page_routes
+                .or(live_route)
+                .or(ws_route)
+                .or(metrics_route)
+                .or(healthz_route)
+                .or(readyz_route)
+                .or(static_route)
+                .with(warp::cors().allow_any_origin())
+        )
+    )
+    .recover(move |err| handlers::handle_rejection(err, tera.clone()))
+    .with(warp::log::custom(|info| {
+        metrics::observe_request(info.path(), info.status().as_u16(), info.elapsed())
+    }));
 
     let ip = match config.clone().web.ip.parse::<IpAddr>() {
         Ok(addr) => addr,
@@ -0,0 +1,195 @@
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use warp::{Reply, Rejection};
+use warp::http::header::CONTENT_TYPE;
+use std::sync::{Arc, LazyLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use log::{debug, error};
+
+use crate::db;
+
+pub static REGISTRY: LazyLock<Registry> = LazyLock::new(Registry::new);
+
+pub static HTTP_REQUESTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register(IntCounterVec::new(
+        Opts::new("bigleague_http_requests_total", "HTTP requests by route and status"),
+        &["route", "status"],
+    ))
+});
+
+pub static HTTP_LATENCY: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register(HistogramVec::new(
+        HistogramOpts::new("bigleague_http_request_duration_seconds", "HTTP request latency by route"),
+        &["route"],
+    ))
+});
+
+pub static DB_POOL_CONNECTIONS: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register(IntGaugeVec::new(
+        Opts::new("bigleague_db_pool_connections", "Database pool connections by state"),
+        &["state"],
+    ))
+});
+
+pub static DB_POOL_MAX_OPEN: LazyLock<IntGauge> = LazyLock::new(|| {
+    register(IntGauge::new("bigleague_db_pool_max_open", "Maximum open connections allowed by the database pool"))
+});
+
+pub static DB_POOL_WAITING: LazyLock<IntGauge> = LazyLock::new(|| {
+    register(IntGauge::new("bigleague_db_pool_waiting", "Requests waiting for a database connection"))
+});
+
+pub static SLEEPER_REQUESTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register(IntCounterVec::new(
+        Opts::new("bigleague_sleeper_requests_total", "Calls made to Sleeper's API by endpoint"),
+        &["endpoint"],
+    ))
+});
+
+pub static SLEEPER_ERRORS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register(IntCounterVec::new(
+        Opts::new("bigleague_sleeper_errors_total", "Failed calls to Sleeper's API by endpoint"),
+        &["endpoint"],
+    ))
+});
+
+pub static SLEEPER_LATENCY: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register(HistogramVec::new(
+        HistogramOpts::new("bigleague_sleeper_request_duration_seconds", "Latency of calls to Sleeper's API by endpoint"),
+        &["endpoint"],
+    ))
+});
+
+pub static INGEST_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register(HistogramVec::new(
+        HistogramOpts::new("bigleague_ingest_duration_seconds", "Duration of ingestion jobs")
+            .buckets(vec![0.1, 0.5, 1.0, 5.0, 10.0, 30.0, 60.0, 300.0]),
+        &["job"],
+    ))
+});
+
+pub static INGEST_FAILURES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register(IntCounterVec::new(
+        Opts::new("bigleague_ingest_failures_total", "Failed ingestion job runs"),
+        &["job"],
+    ))
+});
+
+pub static INGEST_LAST_SUCCESS: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register(IntGaugeVec::new(
+        Opts::new("bigleague_ingest_last_success_timestamp_seconds", "Unix time of the last successful run of each ingestion job"),
+        &["job"],
+    ))
+});
+
+fn register<T: prometheus::core::Collector + Clone + 'static>(metric: prometheus::Result<T>) -> T {
+    let metric = metric.expect("invalid metric definition");
+    if let Err(e) = REGISTRY.register(Box::new(metric.clone())) {
+        error!("couldn't register metric: {}", e);
+    }
+    metric
+}
+
+pub fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+// Collapses ids out of request paths so every user and league
+// doesn't get its own set of series
+pub fn route_label(path: &str) -> &'static str {
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    match segments.as_slice() {
+        [""] => "/",
+        ["league", _] => "/league/{id}",
+        ["user", _] => "/user/{id}",
+        ["wire"] => "/wire",
+        ["draft"] => "/draft",
+        ["trades"] => "/trades",
+        ["settings"] => "/settings",
+        ["live"] => "/live",
+        ["ws"] => "/ws",
+        ["metrics"] => "/metrics",
+        ["healthz"] => "/healthz",
+        ["readyz"] => "/readyz",
+        ["admin"] => "/admin",
+        ["login"] => "/login",
+        ["logout"] => "/logout",
+        ["admin", "leagues"] => "/admin/leagues",
+        ["admin", "leagues", _, "remove"] => "/admin/leagues/{id}/remove",
+        ["admin", "ingest"] => "/admin/ingest",
+        ["admin", "overrides"] => "/admin/overrides",
+        ["admin", "changes"] => "/admin/changes",
+        ["admin", "overrides", _, "remove"] => "/admin/overrides/{id}/remove",
+        ["static", ..] => "/static",
+        ["api", "openapi.json"] => "/api/openapi.json",
+        ["api", "v1", "standings"] => "/api/v1/standings",
+        ["api", "v1", "leagues", _] => "/api/v1/leagues/{id}",
+        ["api", "v1", "users", _] => "/api/v1/users/{id}",
+        ["api", "v1", "bracket"] => "/api/v1/bracket",
+        ["api", "v1", "matchups"] => "/api/v1/matchups",
+        ["api", "v1", "leagues", _, "transactions"] => "/api/v1/leagues/{id}/transactions",
+        ["api", "v1", "users", _, "transactions"] => "/api/v1/users/{id}/transactions",
+        ["api", "v1", "wire"] => "/api/v1/wire",
+        ["api", "v1", "wire", "players"] => "/api/v1/wire/players",
+        ["api", "v1", "drafts"] => "/api/v1/drafts",
+        ["api", "v1", "drafts", "adp"] => "/api/v1/drafts/adp",
+        ["api", "v1", "drafts", "grades"] => "/api/v1/drafts/grades",
+        ["api", "v1", "trades"] => "/api/v1/trades",
+        ["api", "v1", "settings"] => "/api/v1/settings",
+        _ => "other",
+    }
+}
+
+pub fn observe_request(path: &str, status: u16, elapsed: Duration) {
+    let route = route_label(path);
+    HTTP_REQUESTS.with_label_values(&[route, &status.to_string()]).inc();
+    HTTP_LATENCY.with_label_values(&[route]).observe(elapsed.as_secs_f64());
+}
+
+pub fn observe_sleeper(endpoint: &str, elapsed: Duration, ok: bool) {
+    SLEEPER_REQUESTS.with_label_values(&[endpoint]).inc();
+    SLEEPER_LATENCY.with_label_values(&[endpoint]).observe(elapsed.as_secs_f64());
+    if !ok {
+        SLEEPER_ERRORS.with_label_values(&[endpoint]).inc();
+    }
+}
+
+pub async fn metrics_handler(db_pool: Arc<db::DBPool>) -> std::result::Result<impl Reply, Rejection> {
+
+    debug!("GET /metrics");
+
+    // Pool usage is read when scraped rather than tracked
+    let state = db_pool.state().await;
+    DB_POOL_MAX_OPEN.set(state.max_open as i64);
+    DB_POOL_CONNECTIONS.with_label_values(&["open"]).set(state.connections as i64);
+    DB_POOL_CONNECTIONS.with_label_values(&["in_use"]).set(state.in_use as i64);
+    DB_POOL_CONNECTIONS.with_label_values(&["idle"]).set(state.idle as i64);
+    DB_POOL_WAITING.set(state.wait_count as i64);
+
+    let encoder = TextEncoder::new();
+    let mut buffer = vec![];
+    if let Err(e) = encoder.encode(&REGISTRY.gather(), &mut buffer) {
+        error!("couldn't encode metrics: {}", e);
+    }
+
+    Ok(warp::reply::with_header(buffer, CONTENT_TYPE, encoder.format_type()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::metrics;
+
+    #[test]
+    fn test_route_label() {
+        assert_eq!(metrics::route_label("/"), "/");
+        assert_eq!(metrics::route_label("/user/1234"), "/user/{id}");
+        assert_eq!(metrics::route_label("/api/v1/leagues/1234"), "/api/v1/leagues/{id}");
+        assert_eq!(metrics::route_label("/api/v1/users/1234/transactions"), "/api/v1/users/{id}/transactions");
+        assert_eq!(metrics::route_label("/static/css/style.css"), "/static");
+        assert_eq!(metrics::route_label("/user/1234/extra"), "other");
+    }
+}
@@ -0,0 +1,103 @@
+use notify::{RecursiveMode, Watcher};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{mpsc, watch};
+use tokio::time;
+use warp::Filter;
+use std::convert::Infallible;
+use std::path::{Path, PathBuf};
+use log::{info, warn, error};
+
+use crate::config;
+
+// Editors tend to write a file in a few steps, so wait for them
+// to settle before reading it
+const SETTLE: time::Duration = time::Duration::from_millis(250);
+
+pub type ConfigReceiver = watch::Receiver<config::Config>;
+
+// Hands out the current config to handlers on every request
+pub fn with_config(config: ConfigReceiver) -> impl Filter<Extract = (config::Config,), Error = Infallible> + Clone {
+    warp::any().map(move || config.borrow().clone())
+}
+
+// Rereads the config whenever the file changes or the process gets a
+// SIGHUP. Configs that fail to load or validate are logged and the
+// current one is kept.
+pub fn watch_config(path: String, initial: config::Config) -> ConfigReceiver {
+    let (sender, receiver) = watch::channel(initial);
+
+    tokio::spawn(async move {
+        let (changed, mut changes) = mpsc::unbounded_channel::<()>();
+
+        // The directory is watched rather than the file, since saving
+        // often replaces the file instead of writing to it
+        let file = PathBuf::from(&path);
+        let name = file.file_name().map(|n| n.to_os_string());
+        let dir = match file.parent() {
+            Some(d) if !d.as_os_str().is_empty() => d.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        let mut watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+            if let Ok(event) = res {
+                if event.paths.iter().any(|p| p.file_name().map(|n| n.to_os_string()) == name) {
+                    let _ = changed.send(());
+                }
+            }
+        });
+        match watcher.as_mut() {
+            Ok(w) => {
+                if let Err(e) = w.watch(&dir, RecursiveMode::NonRecursive) {
+                    warn!("couldn't watch {}: {}", path, e);
+                }
+            },
+            Err(e) => warn!("couldn't watch {}: {}", path, e),
+        }
+
+        let mut hangups = match signal(SignalKind::hangup()) {
+            Ok(s) => Some(s),
+            Err(e) => {
+                warn!("couldn't listen for SIGHUP: {}", e);
+                None
+            }
+        };
+
+        loop {
+            tokio::select! {
+                Some(()) = changes.recv() => {
+                    time::sleep(SETTLE).await;
+                    while changes.try_recv().is_ok() {}
+                    info!("{} changed", path);
+                }
+                Some(()) = async { hangups.as_mut()?.recv().await } => {
+                    info!("got SIGHUP");
+                }
+                else => break,
+            }
+            reload(Path::new(&path), &sender);
+        }
+    });
+
+    receiver
+}
+
+fn reload(path: &Path, sender: &watch::Sender<config::Config>) {
+    let new = match config::read_config(&path.to_string_lossy()) {
+        Ok(c) => c,
+        Err(e) => {
+            error!("not reloading config, {}", e);
+            return;
+        }
+    };
+
+    let current = sender.borrow().clone();
+    if new == current {
+        info!("config is unchanged");
+        return;
+    }
+    if new.web != current.web || new.database != current.database {
+        warn!("changes to web and database settings need a restart");
+    }
+
+    info!("reloaded config");
+    let _ = sender.send(new);
+}
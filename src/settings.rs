@@ -0,0 +1,138 @@
+use serde::{Serialize, Deserialize};
+use utoipa::ToSchema;
+use std::collections::BTreeSet;
+
+use crate::db;
+
+// Sleeper leaves trades open through this week when there's no deadline
+const NO_TRADE_DEADLINE: i32 = 99;
+
+// One setting across every league, in the same order as the leagues
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct SettingRow {
+    /// general, roster or scoring
+    pub section: String,
+    pub name: String,
+    /// None when a league doesn't have the setting
+    pub values: Vec<Option<String>>,
+    /// Not every league has the same value
+    pub differs: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct Comparison {
+    pub leagues: Vec<db::LeagueSettings>,
+    pub rows: Vec<SettingRow>,
+    /// How many rows differ between leagues
+    pub differences: usize,
+}
+
+fn waiver_type(waiver_type: i32) -> String {
+    match waiver_type {
+        0 => String::from("rolling"),
+        1 => String::from("reverse standings"),
+        2 => String::from("FAAB"),
+        other => other.to_string(),
+    }
+}
+
+fn trade_deadline(week: i32) -> String {
+    if week >= NO_TRADE_DEADLINE {
+        String::from("none")
+    } else {
+        format!("week {}", week)
+    }
+}
+
+fn row(section: &str, name: &str, values: Vec<Option<String>>) -> SettingRow {
+    let differs = values.windows(2).any(|w| w[0] != w[1]);
+    SettingRow {
+        section: section.to_string(),
+        name: name.to_string(),
+        values,
+        differs,
+    }
+}
+
+// Lines up every league's settings side by side. Roster slots are
+// compared by how many of each a league has rather than by order, and
+// a stat one league doesn't score counts as 0 points.
+pub fn compare(leagues: Vec<db::LeagueSettings>) -> Comparison {
+    let mut rows = vec![
+        row("general", "teams", leagues.iter().map(|l| l.total_rosters.map(|v| v.to_string())).collect()),
+        row("general", "playoff teams", leagues.iter().map(|l| l.playoff_teams.map(|v| v.to_string())).collect()),
+        row("general", "trade deadline", leagues.iter().map(|l| l.trade_deadline.map(trade_deadline)).collect()),
+        row("general", "waivers", leagues.iter().map(|l| l.waiver_type.map(waiver_type)).collect()),
+    ];
+
+    // Keeps Sleeper's slot order for whichever league lists a slot first
+    let mut slots: Vec<&String> = vec![];
+    for position in leagues.iter().flat_map(|l| &l.roster_positions) {
+        if !slots.contains(&position) {
+            slots.push(position);
+        }
+    }
+    for slot in slots {
+        let values = leagues
+            .iter()
+            .map(|l| Some(l.roster_positions.iter().filter(|p| *p == slot).count().to_string()))
+            .collect();
+        rows.push(row("roster", slot, values));
+    }
+
+    let stats: BTreeSet<&String> = leagues.iter().flat_map(|l| l.scoring.keys()).collect();
+    for stat in stats {
+        let values = leagues
+            .iter()
+            .map(|l| Some(l.scoring.get(stat).copied().unwrap_or(0.0).to_string()))
+            .collect();
+        rows.push(row("scoring", stat, values));
+    }
+
+    let differences = rows.iter().filter(|r| r.differs).count();
+    Comparison { leagues, rows, differences }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use crate::db::LeagueSettings;
+    use crate::settings;
+
+    fn league(league_id: &str, rec: Option<f64>, positions: &[&str]) -> LeagueSettings {
+        let mut scoring = BTreeMap::from([(String::from("pass_td"), 4.0)]);
+        if let Some(rec) = rec {
+            scoring.insert(String::from("rec"), rec);
+        }
+        LeagueSettings {
+            league_id: league_id.to_string(),
+            league_name: league_id.to_string(),
+            total_rosters: Some(12),
+            playoff_teams: Some(6),
+            trade_deadline: Some(99),
+            waiver_type: Some(2),
+            roster_positions: positions.iter().map(|p| p.to_string()).collect(),
+            scoring,
+        }
+    }
+
+    #[test]
+    fn test_compare() {
+        let comparison = settings::compare(vec![
+            league("a", Some(1.0), &["QB", "RB", "FLEX", "BN"]),
+            league("b", Some(0.5), &["QB", "FLEX", "RB", "BN"]),
+            league("c", None, &["QB", "RB", "FLEX", "FLEX"]),
+        ]);
+
+        let find = |name: &str| comparison.rows.iter().find(|r| r.name == name).unwrap();
+        assert!(!find("waivers").differs);
+        assert_eq!(find("trade deadline").values[0].as_deref(), Some("none"));
+        // Same slots in a different order are the same roster
+        assert!(!find("RB").differs);
+        assert_eq!(find("FLEX").values, vec![Some(String::from("1")), Some(String::from("1")), Some(String::from("2"))]);
+        assert_eq!(find("rec").values, vec![Some(String::from("1")), Some(String::from("0.5")), Some(String::from("0"))]);
+        assert!(!find("pass_td").differs);
+        // FLEX, BN and rec
+        assert_eq!(comparison.differences, 3);
+    }
+}
@@ -1,139 +1,507 @@
-use reqwest;
+use chrono::{TimeZone, Utc};
+use serde::Serialize;
 use serde_json::Value;
 use crate::db;
-use std::convert::Infallible;
-use std::error::Error;
+use tokio::sync::{mpsc, oneshot};
 use tokio::time;
+use warp::Filter;
+use std::convert::Infallible;
+use std::future::Future;
 use std::sync::Arc;
-use log::{info, warn};
-use std::collections::{HashMap, HashSet};
+use std::time::Instant;
+use log::{info, warn, error};
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
 
+use crate::cache;
 use crate::config;
+use crate::error::Error;
+use crate::events::{self, Event};
+use crate::health;
+use crate::live;
+use crate::metrics;
+use crate::reload;
 
-pub async fn stats_loop(config: config::Config, db_pool: Arc<db::DBPool>) -> Result<(), Box<dyn Error>> {
+pub async fn stats_loop(mut config_rx: reload::ConfigReceiver, db_pool: Arc<db::DBPool>, events: events::EventSender, cache: cache::Cache, jobs: health::Jobs, mut triggers: TriggerReceiver) -> Result<(), Box<dyn std::error::Error>> {
 
     info!("starting stats loop");
 
-    let dev_mode = match config.clone().stats.dev_mode {
-        Some(m) => m,
-        None => false,
-    };
+    let mut config = config_rx.borrow_and_update().clone();
 
-    if dev_mode {
+    if config.stats.dev_mode.unwrap_or_default() {
         warn!("running in dev mode");
     }
 
-    let players_path = match config.clone().stats.players_path {
-        Some(p) => p,
-        None => {
-            match dev_mode {
-                true => String::from("data/players.json"),
-                false => String::from(""),
-            }
-        },
-    };
-
-    for league_id in config.clone().bigleague.leagues {
-        let _ = fetch_rosters(&db_pool, league_id).await;
-    }
-
-    for league_id in config.clone().bigleague.leagues {
-        let _ = fetch_users(&db_pool, league_id).await;
-    }
-
-    for league_id in config.clone().bigleague.leagues {
-        let _ = fetch_leagues(&db_pool, league_id).await;
-    }
-
-    let _ = fetch_state(&db_pool).await;
-
-    for league_id in config.clone().bigleague.leagues {
-        let _ = fetch_matchups(&db_pool, league_id).await;
-    }
-
-    let mut rosters_interval = time::interval(
-        time::Duration::from_secs(config.stats.rosters_interval)
-    );
-    // Change the missed tick behavior so getting behind doesn't accidentally
-    // result in a burst of calls to Sleeper's API.
-    rosters_interval.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
-
-    let mut users_interval = time::interval(
-        time::Duration::from_secs(config.stats.users_interval)
-    );
-    users_interval.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
-
-    let mut leagues_interval = time::interval(
-        time::Duration::from_secs(config.stats.leagues_interval)
-    );
-    leagues_interval.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
-
-    let mut players_interval = time::interval(
-        time::Duration::from_secs(config.stats.players_interval)
-    );
-    players_interval.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
-
-    let mut state_interval = time::interval(
-        time::Duration::from_secs(config.stats.state_interval)
-    );
-    state_interval.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
-
-    let mut matchups_interval = time::interval(
-        time::Duration::from_secs(config.stats.matchups_interval)
-    );
-    matchups_interval.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+    // Players are left to their own interval since they're slow to fetch
+    let _ = ingest_once(
+        &config,
+        &db_pool,
+        &events,
+        &jobs,
+        &["rosters", "users", "leagues", "state", "matchups", "transactions", "drafts", "player_stats"],
+        &current_leagues(&db_pool, &config).await,
+    ).await;
+
+    let mut bracket: Option<db::Bracket> = None;
+    check_bracket(&db_pool, &config, &events, &mut bracket).await;
+    cache.invalidate();
+
+    let mut rosters_interval = every(config.stats.rosters_interval);
+    let mut users_interval = every(config.stats.users_interval);
+    let mut leagues_interval = every(config.stats.leagues_interval);
+    let mut players_interval = every(config.stats.players_interval);
+    let mut state_interval = every(config.stats.state_interval);
+    let mut matchups_interval = every(config.stats.matchups_interval);
+    let mut transactions_interval = every(transactions_interval_secs(&config));
+    let mut drafts_interval = every(drafts_interval_secs(&config));
+    let mut player_stats_interval = every(player_stats_interval_secs(&config));
+
+    // Live mode polls matchups much more often, but only does any
+    // work while games are being played
+    let mut game_windows = live::game_windows(&config);
+    let mut live_interval = every(live_interval_secs(&config));
 
     loop {
         tokio::select! {
             _ = rosters_interval.tick() => {
-                for league_id in config.clone().bigleague.leagues {
-                    let _ = fetch_rosters(&db_pool, league_id).await;
+                for league_id in current_leagues(&db_pool, &config).await {
+                    let _ = run_job(&jobs, "rosters", Some(&league_id), fetch_rosters(&db_pool, league_id.clone(), &events)).await;
                 }
+                cache.invalidate();
             }
             _ = users_interval.tick() => {
-                for league_id in config.clone().bigleague.leagues {
-                    let _ = fetch_users(&db_pool, league_id).await;
+                for league_id in current_leagues(&db_pool, &config).await {
+                    let _ = run_job(&jobs, "users", Some(&league_id), fetch_users(&db_pool, league_id.clone())).await;
                 }
+                cache.invalidate();
             }
             _ = leagues_interval.tick() => {
-                for league_id in config.clone().bigleague.leagues {
-                    let _ = fetch_leagues(&db_pool, league_id).await;
+                for league_id in current_leagues(&db_pool, &config).await {
+                    let _ = run_job(&jobs, "leagues", Some(&league_id), fetch_leagues(&db_pool, league_id.clone())).await;
                 }
+                cache.invalidate();
             }
             _ = players_interval.tick() => {
-                let _ = fetch_players(&db_pool, dev_mode, players_path.clone()).await;
+                let (dev_mode, players_path) = players_source(&config);
+                let _ = run_job(&jobs, "players", None, fetch_players(&db_pool, dev_mode, players_path)).await;
+                cache.invalidate();
             }
             _ = state_interval.tick() => {
-                let _ = fetch_state(&db_pool).await;
+                let _ = run_job(&jobs, "state", None, fetch_state(&db_pool, &events)).await;
+                check_bracket(&db_pool, &config, &events, &mut bracket).await;
+                cache.invalidate();
             }
             _ = matchups_interval.tick() => {
-                for league_id in config.clone().bigleague.leagues {
-                    let _ = fetch_matchups(&db_pool, league_id).await;
+                for league_id in current_leagues(&db_pool, &config).await {
+                    let _ = run_job(&jobs, "matchups", Some(&league_id), fetch_matchups(&db_pool, league_id.clone(), &events)).await;
                 }
+                check_bracket(&db_pool, &config, &events, &mut bracket).await;
+                cache.invalidate();
+            }
+            _ = transactions_interval.tick() => {
+                for league_id in current_leagues(&db_pool, &config).await {
+                    let _ = run_job(&jobs, "transactions", Some(&league_id), fetch_transactions(&db_pool, league_id.clone())).await;
+                }
+                cache.invalidate();
+            }
+            _ = drafts_interval.tick() => {
+                for league_id in current_leagues(&db_pool, &config).await {
+                    let _ = run_job(&jobs, "drafts", Some(&league_id), fetch_drafts(&db_pool, league_id.clone())).await;
+                }
+                cache.invalidate();
+            }
+            _ = player_stats_interval.tick() => {
+                let _ = run_job(&jobs, "player_stats", None, fetch_player_stats(&db_pool, config.bigleague.scoring.is_some())).await;
+                check_bracket(&db_pool, &config, &events, &mut bracket).await;
+                cache.invalidate();
+            }
+            _ = live_interval.tick() => {
+                if config.stats.live.is_some() && live::is_live(&db_pool, &game_windows).await {
+                    for league_id in current_leagues(&db_pool, &config).await {
+                        let _ = run_job(&jobs, "matchups", Some(&league_id), fetch_matchups(&db_pool, league_id.clone(), &events)).await;
+                    }
+                    check_bracket(&db_pool, &config, &events, &mut bracket).await;
+                    cache.invalidate();
+                }
+            }
+            Some(trigger) = triggers.recv() => {
+                let leagues = match trigger.leagues {
+                    Some(l) => l,
+                    None => current_leagues(&db_pool, &config).await,
+                };
+                let to_run: Vec<&str> = trigger.jobs.iter().map(String::as_str).collect();
+                info!("running {:?} for {:?} on request", to_run, leagues);
+                let results = run_jobs(&config, &db_pool, &events, &jobs, &to_run, &leagues).await;
+                let failed = results.iter().filter(|r| r.error.is_some()).count();
+                info!("requested run finished, {} of {} jobs failed", failed, results.len());
+                check_bracket(&db_pool, &config, &events, &mut bracket).await;
+                cache.invalidate();
+                if let Some(reply) = trigger.reply {
+                    let _ = reply.send(results);
+                }
+            }
+            Ok(()) = config_rx.changed() => {
+                let new = config_rx.borrow_and_update().clone();
+
+                // Only intervals that changed are restarted, the rest
+                // keep their schedule
+                reset_interval(&mut rosters_interval, config.stats.rosters_interval, new.stats.rosters_interval);
+                reset_interval(&mut users_interval, config.stats.users_interval, new.stats.users_interval);
+                reset_interval(&mut leagues_interval, config.stats.leagues_interval, new.stats.leagues_interval);
+                reset_interval(&mut players_interval, config.stats.players_interval, new.stats.players_interval);
+                reset_interval(&mut state_interval, config.stats.state_interval, new.stats.state_interval);
+                reset_interval(&mut matchups_interval, config.stats.matchups_interval, new.stats.matchups_interval);
+                reset_interval(&mut transactions_interval, transactions_interval_secs(&config), transactions_interval_secs(&new));
+                reset_interval(&mut drafts_interval, drafts_interval_secs(&config), drafts_interval_secs(&new));
+                reset_interval(&mut player_stats_interval, player_stats_interval_secs(&config), player_stats_interval_secs(&new));
+                reset_interval(&mut live_interval, live_interval_secs(&config), live_interval_secs(&new));
+                game_windows = live::game_windows(&new);
+
+                // Leagues added to the config are managed like any other,
+                // and fetched right away instead of waiting for each of
+                // their intervals
+                let added = add_leagues(&db_pool, &new.bigleague.leagues, &config.bigleague.leagues).await;
+                if !added.is_empty() {
+                    info!("fetching added leagues: {:?}", added);
+                    let _ = ingest_once(&new, &db_pool, &events, &jobs, &NEW_LEAGUE_JOBS, &added).await;
+                }
+
+                // Standings and the bracket are rescored as soon as the
+                // big league's scoring changes
+                if new.bigleague.scoring != config.bigleague.scoring {
+                    info!("big league scoring changed");
+                    set_scoring(&db_pool, &new).await;
+                    if new.bigleague.scoring.is_some() {
+                        let _ = run_job(&jobs, "player_stats", None, fetch_player_stats(&db_pool, true)).await;
+                    }
+                }
+
+                config = new;
+                check_bracket(&db_pool, &config, &events, &mut bracket).await;
+                cache.invalidate();
             }
         }    
     }
+}
+
+// Jobs that need to run before a newly added league shows up
+pub const NEW_LEAGUE_JOBS: [&str; 6] = ["rosters", "users", "leagues", "matchups", "transactions", "drafts"];
+
+// Asks the stats loop to run jobs now rather than waiting for their
+// intervals, leagues defaults to every managed league. The results
+// are sent back on reply when there is one.
+#[derive(Debug)]
+pub struct Trigger {
+    pub jobs: Vec<String>,
+    pub leagues: Option<Vec<String>>,
+    pub reply: Option<oneshot::Sender<Vec<JobResult>>>,
+}
+
+// How one run of a job went, league_id is None for jobs that
+// aren't per league
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct JobResult {
+    pub job: String,
+    pub league_id: Option<String>,
+    pub error: Option<String>,
+    pub seconds: f64,
+}
+
+pub type TriggerSender = mpsc::UnboundedSender<Trigger>;
+pub type TriggerReceiver = mpsc::UnboundedReceiver<Trigger>;
+
+pub fn triggers() -> (TriggerSender, TriggerReceiver) {
+    mpsc::unbounded_channel()
+}
+
+pub fn with_triggers(triggers: TriggerSender) -> impl Filter<Extract = (TriggerSender,), Error = Infallible> + Clone {
+    warp::any().map(move || triggers.clone())
+}
+
+// The leagues to ingest, falling back to the config when the
+// database can't be reached
+pub async fn current_leagues(db_pool: &db::DBPool, config: &config::Config) -> Vec<String> {
+    let leagues = match db::get_db_con(db_pool).await {
+        Ok(con) => db::get_managed_leagues(&con).await.map_err(Error::Database),
+        Err(e) => Err(e),
+    };
+    match leagues {
+        Ok(l) => l.into_iter().map(|l| l.id).collect(),
+        Err(e) => {
+            warn!("couldn't get managed leagues, using the config: {}", e);
+            config.bigleague.leagues.clone()
+        }
+    }
+}
 
-    Ok(())
+// Copies the big league's scoring from the config into the database
+pub async fn set_scoring(db_pool: &db::DBPool, config: &config::Config) {
+    let result = match db::get_db_con(db_pool).await {
+        Ok(mut con) => db::set_normalized_scoring(&mut con, &config.bigleague.scoring).await.map_err(Error::Database),
+        Err(e) => Err(e),
+    };
+    if let Err(e) = result {
+        error!("couldn't update the big league scoring: {}", e);
+    }
 }
 
-pub async fn fetch_rosters(db_pool: &db::DBPool, league_id: String) -> Result<(), Infallible> {
+// Adds any leagues in `leagues` that aren't in `previous`, returning
+// the ones that weren't already managed
+async fn add_leagues(db_pool: &db::DBPool, leagues: &[String], previous: &[String]) -> Vec<String> {
+    let con = match db::get_db_con(db_pool).await {
+        Ok(con) => con,
+        Err(e) => {
+            warn!("couldn't add leagues from the config: {}", e);
+            return vec![];
+        }
+    };
 
-    info!("fetching rosters for league: {}", league_id);
+    let mut added = vec![];
+    for league_id in leagues.iter().filter(|l| !previous.contains(l)) {
+        match db::add_managed_league(&con, league_id).await {
+            Ok(true) => added.push(league_id.clone()),
+            Ok(false) => (),
+            Err(e) => warn!("couldn't add league {}: {}", league_id, e),
+        }
+    }
+    added
+}
+
+// Looks a league up on Sleeper, None when it doesn't exist
+pub async fn lookup_league(league_id: &str) -> Result<Option<Value>, Error> {
+    let body = sleeper_get("league", format!("https://api.sleeper.app/v1/league/{}", league_id)).await?;
+    let league: Value = serde_json::from_str(&body)?;
+    Ok(if league.is_null() { None } else { Some(league) })
+}
+
+fn every(secs: u64) -> time::Interval {
+    let mut interval = time::interval(time::Duration::from_secs(secs));
+    // Change the missed tick behavior so getting behind doesn't accidentally
+    // result in a burst of calls to Sleeper's API.
+    interval.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+    interval
+}
+
+// Restarts an interval with a new period, without firing right away
+fn reset_interval(interval: &mut time::Interval, old: u64, new: u64) {
+    if old == new {
+        return;
+    }
+    let period = time::Duration::from_secs(new);
+    *interval = time::interval_at(time::Instant::now() + period, period);
+    interval.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+}
+
+// Transactions, drafts and player stats were added after the other
+// jobs, so older configs don't have an interval for them
+const DEFAULT_TRANSACTIONS_INTERVAL: u64 = 3600;
+const DEFAULT_DRAFTS_INTERVAL: u64 = 86400;
+const DEFAULT_PLAYER_STATS_INTERVAL: u64 = 3600;
 
-    let con = db::get_db_con(db_pool).await;
+fn transactions_interval_secs(config: &config::Config) -> u64 {
+    config.stats.transactions_interval.unwrap_or(DEFAULT_TRANSACTIONS_INTERVAL)
+}
+
+fn drafts_interval_secs(config: &config::Config) -> u64 {
+    config.stats.drafts_interval.unwrap_or(DEFAULT_DRAFTS_INTERVAL)
+}
+
+fn player_stats_interval_secs(config: &config::Config) -> u64 {
+    config.stats.player_stats_interval.unwrap_or(DEFAULT_PLAYER_STATS_INTERVAL)
+}
+
+fn live_interval_secs(config: &config::Config) -> u64 {
+    config.stats.live.as_ref().map(|l| l.interval).unwrap_or(config.stats.matchups_interval)
+}
+
+pub const JOBS: [&str; 9] = ["rosters", "users", "leagues", "players", "state", "matchups", "transactions", "drafts", "player_stats"];
+
+// Where players come from, a local file is used in dev mode to
+// avoid pulling the full player list from Sleeper
+fn players_source(config: &config::Config) -> (bool, String) {
+    let dev_mode = config.stats.dev_mode.unwrap_or_default();
+    let players_path = match config.stats.players_path.clone() {
+        Some(p) => p,
+        None => {
+            match dev_mode {
+                true => String::from("data/players.json"),
+                false => String::from(""),
+            }
+        },
+    };
+    (dev_mode, players_path)
+}
 
-    let body = reqwest::get(format!("https://api.sleeper.app/v1/league/{}/rosters", league_id))
+// Runs each of the named jobs once, in order, for the given leagues.
+// Every job is attempted even if an earlier one fails, the first
+// error is returned.
+pub async fn ingest_once(
+    config: &config::Config,
+    db_pool: &db::DBPool,
+    events: &events::EventSender,
+    jobs: &health::Jobs,
+    to_run: &[&str],
+    leagues: &[String],
+) -> Result<(), Error> {
+    run_all(config, db_pool, events, jobs, to_run, leagues)
         .await
-        .unwrap()
-        .text()
+        .into_iter()
+        .map(|(_, outcome)| outcome)
+        .find(Result::is_err)
+        .unwrap_or(Ok(()))
+}
+
+// Like ingest_once, but hands back how every run went
+pub async fn run_jobs(
+    config: &config::Config,
+    db_pool: &db::DBPool,
+    events: &events::EventSender,
+    jobs: &health::Jobs,
+    to_run: &[&str],
+    leagues: &[String],
+) -> Vec<JobResult> {
+    run_all(config, db_pool, events, jobs, to_run, leagues)
         .await
-        .unwrap();
+        .into_iter()
+        .map(|(result, _)| result)
+        .collect()
+}
 
-    let roster_list: Vec<Value> = serde_json::from_str(&body).expect("Couldn't parse request");
+async fn run_all(
+    config: &config::Config,
+    db_pool: &db::DBPool,
+    events: &events::EventSender,
+    jobs: &health::Jobs,
+    to_run: &[&str],
+    leagues: &[String],
+) -> Vec<(JobResult, Result<(), Error>)> {
+    let mut results = vec![];
+
+    for job in to_run {
+        match *job {
+            "rosters" | "users" | "leagues" | "matchups" | "transactions" | "drafts" => {
+                for league_id in leagues.iter().cloned() {
+                    let start = Instant::now();
+                    let outcome = match *job {
+                        "rosters" => run_job(jobs, job, Some(&league_id), fetch_rosters(db_pool, league_id.clone(), events)).await,
+                        "users" => run_job(jobs, job, Some(&league_id), fetch_users(db_pool, league_id.clone())).await,
+                        "leagues" => run_job(jobs, job, Some(&league_id), fetch_leagues(db_pool, league_id.clone())).await,
+                        "transactions" => run_job(jobs, job, Some(&league_id), fetch_transactions(db_pool, league_id.clone())).await,
+                        "drafts" => run_job(jobs, job, Some(&league_id), fetch_drafts(db_pool, league_id.clone())).await,
+                        _ => run_job(jobs, job, Some(&league_id), fetch_matchups(db_pool, league_id.clone(), events)).await,
+                    };
+                    results.push(job_result(job, Some(league_id), outcome, start));
+                }
+            },
+            "players" => {
+                let start = Instant::now();
+                let (dev_mode, players_path) = players_source(config);
+                let outcome = run_job(jobs, job, None, fetch_players(db_pool, dev_mode, players_path)).await;
+                results.push(job_result(job, None, outcome, start));
+            },
+            "state" => {
+                let start = Instant::now();
+                let outcome = run_job(jobs, job, None, fetch_state(db_pool, events)).await;
+                results.push(job_result(job, None, outcome, start));
+            },
+            "player_stats" => {
+                let start = Instant::now();
+                let outcome = run_job(jobs, job, None, fetch_player_stats(db_pool, config.bigleague.scoring.is_some())).await;
+                results.push(job_result(job, None, outcome, start));
+            },
+            _ => results.push(job_result(job, None, Err(Error::BadRequest(format!("unknown job {}", job))), Instant::now())),
+        }
+    }
+
+    results
+}
+
+fn job_result(job: &str, league_id: Option<String>, outcome: Result<(), Error>, start: Instant) -> (JobResult, Result<(), Error>) {
+    let result = JobResult {
+        job: job.to_string(),
+        league_id,
+        error: outcome.as_ref().err().map(|e| e.to_string()),
+        seconds: start.elapsed().as_secs_f64(),
+    };
+    (result, outcome)
+}
+
+// Runs one ingestion job, keeping track of how long it took and
+// whether it worked. Failures are logged and left for the next
+// interval to retry.
+pub async fn run_job(jobs: &health::Jobs, job: &str, league_id: Option<&str>, fetch: impl Future<Output = Result<(), Error>>) -> Result<(), Error> {
+    let timer = metrics::INGEST_DURATION.with_label_values(&[job]).start_timer();
+    let result = fetch.await;
+    timer.observe_duration();
+
+    match &result {
+        Ok(()) => {
+            metrics::INGEST_LAST_SUCCESS.with_label_values(&[job]).set(metrics::unix_now());
+            jobs.succeeded(job, league_id);
+        },
+        Err(e) => {
+            error!("{} job failed: {}", job, e);
+            metrics::INGEST_FAILURES.with_label_values(&[job]).inc();
+            jobs.failed(job, league_id, e.to_string());
+        },
+    }
+
+    result
+}
+
+// Every call to Sleeper's API goes through here so they can be
+// counted and timed per endpoint
+async fn sleeper_get(endpoint: &str, url: String) -> Result<String, Error> {
+    let start = Instant::now();
+    let result = async {
+        reqwest::get(url)
+            .await?
+            .error_for_status()?
+            .text()
+            .await
+    }.await;
+    metrics::observe_sleeper(endpoint, start.elapsed(), result.is_ok());
+    Ok(result?)
+}
+
+fn str_field<'a>(value: &'a Value, field: &str) -> Result<&'a str, Error> {
+    value[field]
+        .as_str()
+        .ok_or_else(|| Error::Ingest(format!("missing {}", field)))
+}
+
+// Sleeper leaves out counters that are still zero
+fn int_or_zero(value: &Value) -> i32 {
+    value
+        .as_i64()
+        .and_then(|n| i32::try_from(n).ok())
+        .unwrap_or(0)
+}
+
+pub async fn fetch_rosters(db_pool: &db::DBPool, league_id: String, events: &events::EventSender) -> Result<(), Error> {
+
+    info!("fetching rosters for league: {}", league_id);
+
+    let con = db::get_job_con(db_pool, "rosters").await?;
+
+    let previous_ranks = db::get_ranks(&con).await?;
+
+    let body = sleeper_get("rosters", format!("https://api.sleeper.app/v1/league/{}/rosters", league_id)).await?;
+
+    let roster_list: Vec<Value> = serde_json::from_str(&body)?;
 
     // This is really ugly, should improve the deserialization later
     for r in roster_list {
+        // Rosters without an owner don't belong to anyone in the big league
+        let owner_id = match r["owner_id"].as_str() {
+            Some(o) => o,
+            None => {
+                warn!("skipping roster {} in league {} without an owner", r["roster_id"], league_id);
+                continue;
+            }
+        };
+        let roster_league_id = str_field(&r, "league_id")?;
+
         con.execute(
             "
             INSERT INTO rosters VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
@@ -150,35 +518,34 @@ pub async fn fetch_rosters(db_pool: &db::DBPool, league_id: String) -> Result<()
                 roster_id = EXCLUDED.roster_id
             ",
             &[
-                &r["owner_id"].as_str().unwrap(),
-                &r["league_id"].as_str().unwrap(),
-                &i32::try_from(r["settings"]["wins"].as_i64().unwrap_or(0)).unwrap(),
-                &i32::try_from(r["settings"]["losses"].as_i64().unwrap_or(0)).unwrap(),
-                &i32::try_from(r["settings"]["ties"].as_i64().unwrap_or(0)).unwrap(),
-                &i32::try_from(r["settings"]["fpts"].as_i64().unwrap_or(0)).unwrap(),
-                &i32::try_from(r["settings"]["fpts_decimal"].as_i64().unwrap_or(0)).unwrap(),
-                &i32::try_from(r["settings"]["fpts_against"].as_u64().unwrap_or(0)).unwrap(),
-                &i32::try_from(r["settings"]["fpts_against_decimal"].as_u64().unwrap_or(0)).unwrap(), 
-                &i32::try_from(r["roster_id"].as_u64().unwrap_or(0)).unwrap(),
+                &owner_id,
+                &roster_league_id,
+                &int_or_zero(&r["settings"]["wins"]),
+                &int_or_zero(&r["settings"]["losses"]),
+                &int_or_zero(&r["settings"]["ties"]),
+                &int_or_zero(&r["settings"]["fpts"]),
+                &int_or_zero(&r["settings"]["fpts_decimal"]),
+                &int_or_zero(&r["settings"]["fpts_against"]),
+                &int_or_zero(&r["settings"]["fpts_against_decimal"]),
+                &int_or_zero(&r["roster_id"]),
             ]
-        ).await.unwrap();
+        ).await?;
 
+        // Empty rosters come back as null
         let players: Vec<String> = r["players"]
             .as_array()
-            .unwrap()
-            .into_iter()
-            .map(|p| p.as_str().unwrap().to_string())
-            .collect();
+            .map(|a| a.iter().filter_map(|p| p.as_str()).map(String::from).collect())
+            .unwrap_or_default();
 
         let starters: Vec<String> = r["starters"]
             .as_array()
-            .unwrap()
-            .into_iter()
-            .map(|p| p.as_str().unwrap().to_string())
-            .collect();
+            .map(|a| a.iter().filter_map(|p| p.as_str()).map(String::from).collect())
+            .unwrap_or_default();
 
-        // Add all their players first
+        // Starters are written in the same upsert so unchanged rosters
+        // don't log a change for every starter on each run
         for p in players {
+            let starter = starters.contains(&p) as i32;
             con.execute(
                 "
                 INSERT INTO ownership VALUES ($1, $2, $3, $4)
@@ -189,49 +556,41 @@ pub async fn fetch_rosters(db_pool: &db::DBPool, league_id: String) -> Result<()
                     starter = EXCLUDED.starter
                 ",
                 &[
-                    &r["owner_id"].as_str().unwrap(),
-                    &r["league_id"].as_str().unwrap(),
+                    &owner_id,
+                    &roster_league_id,
                     &p,
-                    &0i32,
+                    &starter,
                 ]
-            ).await.unwrap();
+            ).await?;
         }
+    }
 
-        // Now update the players for which are starters
-        for s in starters {
-            con.execute(
-                "
-                UPDATE ownership
-                SET starter = 1
-                WHERE user_id = $1 AND
-                league_id = $2 AND
-                player_id = $3
-                ",
-                &[
-                    &r["owner_id"].as_str().unwrap(),
-                    &r["league_id"].as_str().unwrap(),
-                    &s,
-                ]
-            ).await.unwrap();
+    // Ranks are across the whole big league, so a single league's
+    // results can move teams in every other league too
+    for (user_id, (user_league, rank)) in db::get_ranks(&con).await? {
+        if let Some((_, previous_rank)) = previous_ranks.get(&user_id) {
+            if *previous_rank != rank {
+                events::publish(events, Event::StandingsChange {
+                    league_id: user_league,
+                    user_id,
+                    previous_rank: *previous_rank,
+                    rank,
+                });
+            }
         }
     }
 
     Ok(())
 }
 
-pub async fn fetch_leagues(db_pool: &db::DBPool, league_id: String) -> Result<(), Infallible> {
+pub async fn fetch_leagues(db_pool: &db::DBPool, league_id: String) -> Result<(), Error> {
 
     info!("fetching info about league: {}", league_id);
 
-    let con = db::get_db_con(db_pool).await;
+    let mut con = db::get_job_con(db_pool, "leagues").await?;
 
-    let body = reqwest::get(format!("https://api.sleeper.app/v1/league/{}", league_id))
-        .await
-        .unwrap()
-        .text()
-        .await
-        .unwrap();
-    let league: Value = serde_json::from_str(&body).unwrap();
+    let body = sleeper_get("league", format!("https://api.sleeper.app/v1/league/{}", league_id)).await?;
+    let league: Value = serde_json::from_str(&body)?;
 
     con.execute(
         "
@@ -242,35 +601,85 @@ pub async fn fetch_leagues(db_pool: &db::DBPool, league_id: String) -> Result<()
             avatar = EXCLUDED.avatar
         ",
         &[
-            &league["league_id"].as_str().unwrap(),
+            &str_field(&league, "league_id")?,
             &league["name"].as_str().unwrap_or("NA"),
             &league["avatar"].as_str().unwrap_or("NA"),
         ]
-    ).await.unwrap();
+    ).await?;
+
+    let league_id = str_field(&league, "league_id")?;
+    let positions: Vec<&str> = league["roster_positions"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_str)
+        .collect();
+    let scoring: Vec<(&String, f64)> = league["scoring_settings"]
+        .as_object()
+        .into_iter()
+        .flatten()
+        .filter_map(|(stat, points)| Some((stat, points.as_f64()?)))
+        .collect();
+    let stats: Vec<&String> = scoring.iter().map(|(stat, _)| *stat).collect();
+    let settings = &league["settings"];
+    let setting = |key: &str| settings[key].as_i64().map(|v| v as i32);
+
+    // Replaced all at once so lineups are never checked against half
+    // a list of positions. Settings and scoring are upserted so only
+    // real changes end up in the change log.
+    let transaction = con.transaction().await?;
+    transaction.execute("DELETE FROM roster_positions WHERE league_id = $1", &[&league_id]).await?;
+    for (slot, position) in positions.iter().enumerate() {
+        transaction.execute(
+            "INSERT INTO roster_positions VALUES ($1, $2, $3)",
+            &[&league_id, &(slot as i32), position]
+        ).await?;
+    }
+    transaction.execute(
+        "
+        INSERT INTO league_settings VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT(league_id) DO UPDATE SET
+            total_rosters = EXCLUDED.total_rosters,
+            playoff_teams = EXCLUDED.playoff_teams,
+            trade_deadline = EXCLUDED.trade_deadline,
+            waiver_type = EXCLUDED.waiver_type
+        ",
+        &[
+            &league_id,
+            &league["total_rosters"].as_i64().map(|v| v as i32),
+            &setting("playoff_teams"),
+            &setting("trade_deadline"),
+            &setting("waiver_type"),
+        ]
+    ).await?;
+    transaction.execute(
+        "DELETE FROM scoring_settings WHERE league_id = $1 AND stat <> ALL($2)",
+        &[&league_id, &stats]
+    ).await?;
+    for (stat, points) in scoring {
+        transaction.execute(
+            "
+            INSERT INTO scoring_settings VALUES ($1, $2, $3)
+            ON CONFLICT(league_id, stat) DO UPDATE SET
+                points = EXCLUDED.points
+            ",
+            &[&league_id, stat, &points]
+        ).await?;
+    }
+    transaction.commit().await?;
 
     Ok(())
 }
 
-pub async fn fetch_users(db_pool: &db::DBPool, league_id: String) -> Result<(), Infallible> {
+pub async fn fetch_users(db_pool: &db::DBPool, league_id: String) -> Result<(), Error> {
 
     info!("fetching users for league: {}", league_id);
 
-    let con = db::get_db_con(db_pool).await;
+    let con = db::get_job_con(db_pool, "users").await?;
 
-    let rows = con.query("SELECT user_id FROM rosters", &[]).await.unwrap();
+    let body = sleeper_get("users", format!("https://api.sleeper.app/v1/league/{}/users", league_id)).await?;
 
-    let user_ids: Vec<String> = rows.into_iter()
-        .map(|row| row.get(0))
-        .collect();
-
-    let body = reqwest::get(format!("https://api.sleeper.app/v1/league/{}/users", league_id))
-        .await
-        .unwrap()
-        .text()
-        .await
-        .unwrap();
-
-    let users: Vec<Value> = serde_json::from_str(&body).unwrap();
+    let users: Vec<Value> = serde_json::from_str(&body)?;
     for user in users {
         con.execute(
             "
@@ -281,11 +690,11 @@ pub async fn fetch_users(db_pool: &db::DBPool, league_id: String) -> Result<(),
                 avatar = EXCLUDED.avatar
             ",
             &[
-                &user["user_id"].as_str().unwrap(),
+                &str_field(&user, "user_id")?,
                 &user["display_name"].as_str().unwrap_or("NA"),
                 &user["avatar"].as_str().unwrap_or("NA"),
             ]
-        ).await.unwrap();
+        ).await?;
     }
     Ok(())
 }
@@ -294,26 +703,25 @@ pub async fn fetch_users(db_pool: &db::DBPool, league_id: String) -> Result<(),
 // The underlying call to Sleeper's API is expensive and
 // according to their docs, we shouldn't call this more
 // than once a day
-pub async fn fetch_players(db_pool: &db::DBPool, dev_mode: bool, players_path: String) -> Result<(), Infallible> {
+pub async fn fetch_players(db_pool: &db::DBPool, dev_mode: bool, players_path: String) -> Result<(), Error> {
 
     info!("fetching all players");
 
-    let con = db::get_db_con(db_pool).await;
+    let con = db::get_job_con(db_pool, "players").await?;
 
     let body = if dev_mode {
-        std::fs::read_to_string(players_path).expect("couldn't read temp players.json file")
+        std::fs::read_to_string(players_path)?
     } else {
-        reqwest::get("https://api.sleeper.app/v1/players/nfl")
-            .await
-            .unwrap()
-            .text()
-            .await
-            .unwrap()
+        sleeper_get("players", String::from("https://api.sleeper.app/v1/players/nfl")).await?
     };
 
-    let players: Value = serde_json::from_str(&body).unwrap();
+    let players: Value = serde_json::from_str(&body)?;
+
+    let players = players
+        .as_object()
+        .ok_or_else(|| Error::Ingest(String::from("players isn't an object")))?;
 
-    for (player_id, player_data) in players.as_object().unwrap() {
+    for (player_id, player_data) in players {
         con.execute(
             "
             INSERT INTO players VALUES ($1, $2, $3, $4, $5, $6)
@@ -333,26 +741,25 @@ pub async fn fetch_players(db_pool: &db::DBPool, dev_mode: bool, players_path: S
                 &player_data["position"].as_str().unwrap_or("NA"),
                 &player_data["status"].as_str().unwrap_or(""),
             ]
-        ).await.unwrap();
+        ).await?;
     }
 
     Ok(())
 }
 
-pub async fn fetch_state(db_pool: &db::DBPool) -> Result<(), Infallible> {
+pub async fn fetch_state(db_pool: &db::DBPool, events: &events::EventSender) -> Result<(), Error> {
 
     info!("fetching the state of the NFL");
 
-    let con = db::get_db_con(db_pool).await;
+    let con = db::get_job_con(db_pool, "state").await?;
 
-    let body = reqwest::get("https://api.sleeper.app/v1/state/nfl")
-            .await
-            .unwrap()
-            .text()
-            .await
-            .unwrap();
+    let body = sleeper_get("state", String::from("https://api.sleeper.app/v1/state/nfl")).await?;
 
-    let state: Value = serde_json::from_str(&body).unwrap();
+    let state: Value = serde_json::from_str(&body)?;
+
+    let previous_period = db::get_time_period(&con).await.ok();
+    let season = state["season"].as_str().unwrap_or("0").parse::<i32>().unwrap_or(0);
+    let week = int_or_zero(&state["week"]);
 
     con.execute(
         "
@@ -366,76 +773,471 @@ pub async fn fetch_state(db_pool: &db::DBPool) -> Result<(), Infallible> {
             
         ",
         &[
-            &state["season"].as_str().unwrap_or("0").parse::<i32>().unwrap_or(0),
-            &i32::try_from(state["week"].as_u64().unwrap_or(0)).unwrap(),
+            &season,
+            &week,
             &state["league_season"].as_str().unwrap_or("0").parse::<i32>().unwrap_or(0),
-            &i32::try_from(state["display_week"].as_u64().unwrap_or(0)).unwrap(),
+            &int_or_zero(&state["display_week"]),
             &state["season_type"].as_str().unwrap_or("NA"),
         ]
-    ).await.unwrap();
+    ).await?;
+
+    // The week rolling over means last week's matchups are final
+    if let Some((previous_season, previous_week)) = previous_period {
+        if (season, week) > (previous_season, previous_week) {
+            for m in db::get_week_matchups(&con, previous_season, previous_week).await? {
+                let winner_id = match m.result.as_deref() {
+                    Some("win") => Some(m.user_id.clone()),
+                    Some("loss") => Some(m.opponent_id.clone()),
+                    Some(_) => None,
+                    None if m.user_points > m.opponent_points => Some(m.user_id.clone()),
+                    None if m.opponent_points > m.user_points => Some(m.opponent_id.clone()),
+                    None => None,
+                };
+                events::publish(events, Event::Final {
+                    league_id: m.league_id,
+                    season: m.season,
+                    week: m.week,
+                    user_id: m.user_id,
+                    opponent_id: m.opponent_id,
+                    user_points: m.user_points,
+                    opponent_points: m.opponent_points,
+                    winner_id,
+                });
+            }
+        }
+    }
 
     Ok(())
 }
 
-pub async fn fetch_matchups(db_pool: &db::DBPool, league_id: String) -> Result<(), Infallible> {
+// Only needed to rescore lineups under the big league's scoring, so
+// nothing is fetched unless bigleague.scoring is set. The current and
+// previous weeks are refetched for stat corrections, along with any
+// week that has lineups but no stats, e.g. after a backfill.
+pub async fn fetch_player_stats(db_pool: &db::DBPool, normalized: bool) -> Result<(), Error> {
 
-    info!("fetching matchups for league: {}", league_id);
+    if !normalized {
+        info!("not fetching player stats, bigleague.scoring isn't set");
+        return Ok(());
+    }
 
-    let con = db::get_db_con(db_pool).await;
+    let mut con = db::get_job_con(db_pool, "player_stats").await?;
 
-    let time = con.query("SELECT season, week FROM state ORDER BY season DESC, week DESC LIMIT 1", &[])
-        .await
-        .unwrap();
+    let (season, week) = db::get_time_period(&con).await?;
+    let mut weeks = db::get_weeks_missing_stats(&con).await?;
+    for w in [week - 1, week] {
+        if (1..=MAX_WEEKS).contains(&w) && !weeks.contains(&(season, w)) {
+            weeks.push((season, w));
+        }
+    }
 
-    let season: i32 = 2022;//time[0].get("season");
-    let week: i32 = 12;//time[0].get("week");
+    for (season, week) in weeks {
+        info!("fetching player stats for {} week {}", season, week);
+
+        let body = sleeper_get("stats", format!("https://api.sleeper.app/v1/stats/nfl/regular/{}/{}", season, week)).await?;
+        let stats: HashMap<String, Value> = serde_json::from_str::<Option<_>>(&body)?.unwrap_or_default();
+
+        let mut player_ids = vec![];
+        let mut names = vec![];
+        let mut values = vec![];
+        for (player_id, player_stats) in &stats {
+            for (stat, value) in player_stats.as_object().into_iter().flatten() {
+                match value.as_f64() {
+                    Some(v) if v != 0.0 => {
+                        player_ids.push(player_id.as_str());
+                        names.push(stat.as_str());
+                        values.push(v);
+                    },
+                    _ => (),
+                }
+            }
+        }
 
-    let body = reqwest::get(format!("https://api.sleeper.app/v1/league/{}/matchups/{}", league_id, week))
-        .await
-        .unwrap()
-        .text()
-        .await
-        .unwrap();
+        // A week's stats are replaced in one go, there are too many
+        // to insert one at a time
+        let transaction = con.transaction().await?;
+        transaction.execute("DELETE FROM player_stats WHERE season = $1 AND week = $2", &[&season, &week]).await?;
+        transaction.execute(
+            "
+            INSERT INTO player_stats
+            SELECT $1, $2, * FROM UNNEST($3::VARCHAR[], $4::VARCHAR[], $5::FLOAT8[])
+            ",
+            &[&season, &week, &player_ids, &names, &values]
+        ).await?;
+        transaction.commit().await?;
+    }
 
-    let matchups: Vec<Value> = serde_json::from_str(&body).unwrap();
+    Ok(())
+}
 
-    let roster_map_for_league: HashMap<i32, String> = 
-        con.query("
+pub async fn fetch_matchups(db_pool: &db::DBPool, league_id: String, events: &events::EventSender) -> Result<(), Error> {
+
+    info!("fetching matchups for league: {}", league_id);
+
+    let con = db::get_job_con(db_pool, "matchups").await?;
+
+    let (season, week) = db::get_time_period(&con).await?;
+
+    let roster_map_for_league = roster_owners(&con, &league_id).await?;
+
+    fetch_week_matchups(&con, league_id, season, week, &roster_map_for_league, events).await
+}
+
+// Sleeper's roster ids in a league -> their owners
+async fn roster_owners(con: &db::DBCon, league_id: &str) -> Result<HashMap<i32, String>, Error> {
+    Ok(con.query("
             SELECT roster_id, user_id FROM rosters WHERE league_id = $1
             ",
             &[&league_id])
-        .await
-        .unwrap()
+        .await?
         .iter()
         .map(|row| (row.get("roster_id"), row.get("user_id")))
+        .collect())
+}
+
+// A league's first fetch of the season picks up every week so far,
+// after that only the current and previous weeks are refetched since
+// waivers and trades can land around the week rolling over
+pub async fn fetch_transactions(db_pool: &db::DBPool, league_id: String) -> Result<(), Error> {
+
+    info!("fetching transactions for league: {}", league_id);
+
+    let con = db::get_job_con(db_pool, "transactions").await?;
+
+    let (season, week) = db::get_time_period(&con).await?;
+    let week = week.clamp(1, MAX_WEEKS);
+    let first_week = match db::has_transactions(&con, &league_id, season).await? {
+        true => (week - 1).max(1),
+        false => 1,
+    };
+
+    let roster_map_for_league = roster_owners(&con, &league_id).await?;
+
+    for w in first_week..=week {
+        let body = sleeper_get("transactions", format!("https://api.sleeper.app/v1/league/{}/transactions/{}", league_id, w)).await?;
+        let transactions: Vec<Value> = serde_json::from_str(&body)?;
+        for t in transactions.iter() {
+            store_transaction(&con, &league_id, season, w, t, &roster_map_for_league).await?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn store_transaction(
+    con: &db::DBCon,
+    league_id: &str,
+    season: i32,
+    week: i32,
+    transaction: &Value,
+    roster_map_for_league: &HashMap<i32, String>,
+) -> Result<(), Error> {
+
+    let transaction_id = str_field(transaction, "transaction_id")?;
+    let created_at = transaction["created"]
+        .as_i64()
+        .and_then(|ms| Utc.timestamp_millis_opt(ms).single())
+        .ok_or_else(|| Error::Ingest(format!("transaction {} has no created time", transaction_id)))?;
+    let waiver_bid = transaction["settings"]["waiver_bid"].as_i64().and_then(|b| i32::try_from(b).ok());
+
+    con.execute(
+        "
+        INSERT INTO transactions VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+        ON CONFLICT(id) DO UPDATE SET
+            week = EXCLUDED.week,
+            status = EXCLUDED.status,
+            waiver_bid = EXCLUDED.waiver_bid
+        ",
+        &[
+            &transaction_id,
+            &league_id,
+            &season,
+            &match int_or_zero(&transaction["leg"]) {
+                0 => week,
+                leg => leg,
+            },
+            &str_field(transaction, "type")?,
+            &str_field(transaction, "status")?,
+            &transaction["creator"].as_str(),
+            &waiver_bid,
+            &created_at,
+        ]
+    ).await?;
+
+    // Rosters that have lost their owner can't be tied to a user
+    let owner = |roster_id: &Value| -> Option<&String> {
+        let owner = roster_map_for_league.get(&int_or_zero(roster_id));
+        if owner.is_none() {
+            warn!("transaction {} involves unknown roster {} in league {}", transaction_id, roster_id, league_id);
+        }
+        owner
+    };
+
+    for action in ["adds", "drops"] {
+        let moves = match transaction[action].as_object() {
+            Some(m) => m,
+            None => continue,
+        };
+        for (player_id, roster_id) in moves {
+            let user_id = match owner(roster_id) {
+                Some(u) => u,
+                None => continue,
+            };
+            con.execute(
+                "
+                INSERT INTO transaction_players VALUES ($1, $2, $3, $4)
+                ON CONFLICT(transaction_id, player_id, action) DO UPDATE SET
+                    user_id = EXCLUDED.user_id
+                ",
+                &[&transaction_id, &player_id, &action.trim_end_matches('s'), &user_id]
+            ).await?;
+        }
+    }
+
+    for pick in transaction["draft_picks"].as_array().into_iter().flatten() {
+        let (original, previous, owner) = match (owner(&pick["roster_id"]), owner(&pick["previous_owner_id"]), owner(&pick["owner_id"])) {
+            (Some(o), Some(p), Some(u)) => (o, p, u),
+            _ => continue,
+        };
+        let pick_season = pick["season"]
+            .as_str()
+            .and_then(|s| s.parse::<i32>().ok())
+            .ok_or_else(|| Error::Ingest(format!("draft pick in transaction {} has no season", transaction_id)))?;
+        con.execute(
+            "
+            INSERT INTO transaction_picks VALUES ($1, $2, $3, $4, $5, $6)
+            ON CONFLICT(transaction_id, season, round, original_user_id) DO UPDATE SET
+                previous_user_id = EXCLUDED.previous_user_id,
+                user_id = EXCLUDED.user_id
+            ",
+            &[&transaction_id, &pick_season, &int_or_zero(&pick["round"]), original, previous, owner]
+        ).await?;
+    }
+
+    Ok(())
+}
+
+// Finished drafts are only fetched until their picks are stored,
+// ones that haven't started yet have no picks to fetch
+pub async fn fetch_drafts(db_pool: &db::DBPool, league_id: String) -> Result<(), Error> {
+
+    info!("fetching drafts for league: {}", league_id);
+
+    let con = db::get_job_con(db_pool, "drafts").await?;
+
+    let body = sleeper_get("drafts", format!("https://api.sleeper.app/v1/league/{}/drafts", league_id)).await?;
+    let drafts: Vec<Value> = serde_json::from_str(&body)?;
+
+    let roster_map_for_league = roster_owners(&con, &league_id).await?;
+
+    for draft in drafts.iter() {
+        let draft_id = str_field(draft, "draft_id")?;
+        let status = str_field(draft, "status")?;
+        let season = str_field(draft, "season")?
+            .parse::<i32>()
+            .map_err(|_| Error::Ingest(format!("draft {} has an invalid season", draft_id)))?;
+        let start_time = draft["start_time"]
+            .as_i64()
+            .and_then(|ms| Utc.timestamp_millis_opt(ms).single());
+
+        con.execute(
+            "
+            INSERT INTO drafts VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            ON CONFLICT(id) DO UPDATE SET
+                status = EXCLUDED.status,
+                rounds = EXCLUDED.rounds,
+                teams = EXCLUDED.teams,
+                start_time = EXCLUDED.start_time
+            ",
+            &[
+                &draft_id,
+                &league_id,
+                &season,
+                &str_field(draft, "type")?,
+                &status,
+                &int_or_zero(&draft["settings"]["rounds"]),
+                &int_or_zero(&draft["settings"]["teams"]),
+                &start_time,
+            ]
+        ).await?;
+
+        if status == "pre_draft" || (status == "complete" && db::has_draft_picks(&con, draft_id).await?) {
+            continue;
+        }
+
+        let body = sleeper_get("draft_picks", format!("https://api.sleeper.app/v1/draft/{}/picks", draft_id)).await?;
+        let picks: Vec<Value> = serde_json::from_str(&body)?;
+        for pick in picks.iter() {
+            store_draft_pick(&con, draft_id, pick, &roster_map_for_league).await?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn store_draft_pick(
+    con: &db::DBCon,
+    draft_id: &str,
+    pick: &Value,
+    roster_map_for_league: &HashMap<i32, String>,
+) -> Result<(), Error> {
+
+    // Sleeper sends roster ids as numbers or strings depending on the
+    // draft, and leaves picked_by empty for picks a commissioner made
+    let roster_id = match &pick["roster_id"] {
+        Value::String(r) => r.parse::<i32>().unwrap_or(0),
+        r => int_or_zero(r),
+    };
+    let user_id = roster_map_for_league
+        .get(&roster_id)
+        .map(String::as_str)
+        .or_else(|| pick["picked_by"].as_str().filter(|u| !u.is_empty()));
+    let amount = pick["metadata"]["amount"]
+        .as_str()
+        .and_then(|a| a.parse::<i32>().ok());
+
+    con.execute(
+        "
+        INSERT INTO draft_picks VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        ON CONFLICT(draft_id, pick_no) DO UPDATE SET
+            player_id = EXCLUDED.player_id,
+            user_id = EXCLUDED.user_id,
+            amount = EXCLUDED.amount,
+            is_keeper = EXCLUDED.is_keeper
+        ",
+        &[
+            &draft_id,
+            &int_or_zero(&pick["pick_no"]),
+            &int_or_zero(&pick["round"]),
+            &int_or_zero(&pick["draft_slot"]),
+            &str_field(pick, "player_id")?,
+            &user_id,
+            &amount,
+            &pick["is_keeper"].as_bool().unwrap_or_default(),
+        ]
+    ).await?;
+
+    Ok(())
+}
+
+// Regular season and playoffs never run past this
+const MAX_WEEKS: i32 = 18;
+
+// How far back to follow a league's history
+const MAX_SEASONS: usize = 20;
+
+// Sleeper gives each season of a league its own id, so walk back
+// through the previous seasons until we find the one asked for
+async fn find_league_season(league_id: String, season: i32) -> Result<Value, Error> {
+    let mut current = league_id.clone();
+    for _ in 0..MAX_SEASONS {
+        let body = sleeper_get("league", format!("https://api.sleeper.app/v1/league/{}", current)).await?;
+        let league: Value = serde_json::from_str(&body)?;
+
+        if str_field(&league, "season")?.parse::<i32>().ok() == Some(season) {
+            return Ok(league);
+        }
+
+        current = match league["previous_league_id"].as_str() {
+            Some(previous) if previous != "0" => previous.to_string(),
+            _ => break,
+        };
+    }
+    Err(Error::Ingest(format!("league {} has no {} season", league_id, season)))
+}
+
+// Loads every scored week of a season. Past seasons don't keep their
+// rosters around, so owners are looked up from Sleeper directly.
+pub async fn backfill_season(db_pool: &db::DBPool, league_id: String, season: i32, events: &events::EventSender) -> Result<(), Error> {
+
+    info!("backfilling {} matchups for league: {}", season, league_id);
+
+    let league = find_league_season(league_id, season).await?;
+    let season_league_id = str_field(&league, "league_id")?.to_string();
+
+    let body = sleeper_get("rosters", format!("https://api.sleeper.app/v1/league/{}/rosters", season_league_id)).await?;
+    let rosters: Vec<Value> = serde_json::from_str(&body)?;
+    let roster_map: HashMap<i32, String> = rosters
+        .iter()
+        .filter_map(|r| Some((int_or_zero(&r["roster_id"]), r["owner_id"].as_str()?.to_string())))
         .collect();
 
+    let last_week = match int_or_zero(&league["settings"]["last_scored_leg"]) {
+        0 => MAX_WEEKS,
+        w => w.min(MAX_WEEKS),
+    };
+
+    let con = db::get_job_con(db_pool, "backfill").await?;
+    for week in 1..=last_week {
+        fetch_week_matchups(&con, season_league_id.clone(), season, week, &roster_map, events).await?;
+    }
+
+    Ok(())
+}
+
+// roster_map_for_league maps Sleeper's roster ids in the league to their owners
+pub async fn fetch_week_matchups(
+    con: &db::DBCon,
+    league_id: String,
+    season: i32,
+    week: i32,
+    roster_map_for_league: &HashMap<i32, String>,
+    events: &events::EventSender,
+) -> Result<(), Error> {
+
+    // Points before this fetch, used to find the scores that changed
+    let previous_points: HashMap<String, f32> =
+        con.query("
+            SELECT user_id, points FROM matchups WHERE season = $1 AND week = $2 AND league_id = $3
+            ",
+            &[&season, &week, &league_id])
+        .await?
+        .iter()
+        .map(|row| (row.get("user_id"), row.get("points")))
+        .collect();
+
+    let body = sleeper_get("matchups", format!("https://api.sleeper.app/v1/league/{}/matchups/{}", league_id, week)).await?;
+
+    let matchups: Vec<Value> = serde_json::from_str(&body)?;
+
+    let roster_owner = |matchup: &Value| -> Result<&String, Error> {
+        roster_map_for_league
+            .get(&int_or_zero(&matchup["roster_id"]))
+            .ok_or_else(|| Error::Ingest(format!("unknown roster {} in league {}", matchup["roster_id"], league_id)))
+    };
+
     let mut opponent_map: HashMap<String, String> = HashMap::new();
-    let mut opponent_resolutions: HashMap<u64, u64> = HashMap::new();
-    for matchup in matchups.clone() {
-        let m_id = matchup["matchup_id"].as_u64().unwrap();
-        let r_id = matchup["roster_id"].as_u64().unwrap();
-
-        if opponent_resolutions.contains_key(&m_id) {
-            let opponents = (
-                roster_map_for_league.get(&i32::try_from(r_id).unwrap()).unwrap(),
-                roster_map_for_league.get(
-                    &i32::try_from(
-                        *opponent_resolutions.get(&m_id).unwrap()
-                    ).unwrap()
-                ).unwrap(),
-            );
-            opponent_map.insert(opponents.0.to_string(), opponents.1.to_string());
-            opponent_map.insert(opponents.1.to_string(), opponents.0.to_string());
-        } else {
-            opponent_resolutions.insert(m_id, r_id);
+    let mut opponent_resolutions: HashMap<u64, &Value> = HashMap::new();
+    for matchup in matchups.iter() {
+        // Teams without a game this week have no matchup id
+        let m_id = match matchup["matchup_id"].as_u64() {
+            Some(m) => m,
+            None => continue,
+        };
+
+        match opponent_resolutions.entry(m_id) {
+            Entry::Occupied(resolution) => {
+                let opponents = (
+                    roster_owner(matchup)?,
+                    roster_owner(resolution.get())?,
+                );
+                opponent_map.insert(opponents.0.to_string(), opponents.1.to_string());
+                opponent_map.insert(opponents.1.to_string(), opponents.0.to_string());
+            }
+            Entry::Vacant(resolution) => {
+                resolution.insert(matchup);
+            }
         }
     }
 
-    for matchup in matchups {
-        let user = roster_map_for_league.get(
-            &i32::try_from(matchup["roster_id"].as_u64().unwrap()).unwrap()
-        ).unwrap();
+    let mut current_points: HashMap<String, f32> = HashMap::new();
+    for matchup in matchups.iter() {
+        let user = roster_owner(matchup)?;
+        let opponent = match opponent_map.get(user) {
+            Some(o) => o,
+            None => continue,
+        };
+        let points = matchup["points"].as_f64().unwrap_or(0.0) as f32;
 
         con.execute(
             "
@@ -453,14 +1255,31 @@ pub async fn fetch_matchups(db_pool: &db::DBPool, league_id: String) -> Result<(
                 &week,
                 &league_id,
                 user,
-                &opponent_map.get(
-                    user
-                ).unwrap(),
-                &(matchup["points"].as_f64().unwrap() as f32),
+                opponent,
+                &points,
             ]
-        ).await.unwrap();
+        ).await?;
+
+        current_points.insert(user.to_string(), points);
+
+        if previous_points.get(user) != Some(&points) {
+            events::publish(events, Event::ScoreChange {
+                league_id: league_id.clone(),
+                season,
+                week,
+                user_id: user.to_string(),
+                points,
+            });
+        }
 
-        for (player, points) in matchup["players_points"].as_object().unwrap() {
+        store_lineup(con, &league_id, season, week, user, matchup).await?;
+
+        let players_points = match matchup["players_points"].as_object() {
+            Some(p) => p,
+            None => continue,
+        };
+
+        for (player, points) in players_points {
             con.execute(
             "
             INSERT INTO scores VALUES ($1, $2, $3, $4, $5)
@@ -476,11 +1295,137 @@ pub async fn fetch_matchups(db_pool: &db::DBPool, league_id: String) -> Result<(
                 &league_id,
                 &season,
                 &week,
-                &(points.as_f64().unwrap() as f32),
+                &(points.as_f64().unwrap_or(0.0) as f32),
             ]
-        ).await.unwrap();
+        ).await?;
     
         }
     } 
+
+    // Each pairing is in the map twice, only look at it once
+    for (user, opponent) in opponent_map.iter().filter(|(u, o)| u < o) {
+        let previous_leader = leader(user, opponent, &previous_points);
+        let current_leader = leader(user, opponent, &current_points);
+        if let Some(current) = current_leader {
+            if previous_leader != current_leader {
+                let trailing = if current == user { opponent } else { user };
+                events::publish(events, Event::LeadChange {
+                    league_id: league_id.clone(),
+                    season,
+                    week,
+                    user_id: current.to_string(),
+                    opponent_id: trailing.to_string(),
+                    user_points: current_points[current],
+                    opponent_points: current_points[trailing],
+                });
+            }
+        }
+    }
+
     Ok(())
 }
+
+// Who was on a user's roster for the week and who started, players
+// who have since left the roster are cleared out
+async fn store_lineup(con: &db::DBCon, league_id: &str, season: i32, week: i32, user: &str, matchup: &Value) -> Result<(), Error> {
+    let ids = |field: &str| -> Vec<&str> {
+        matchup[field]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str)
+            .collect()
+    };
+    let players = ids("players");
+    let starters = ids("starters");
+
+    con.execute(
+        "
+        DELETE FROM lineups
+        WHERE season = $1 AND week = $2 AND league_id = $3 AND user_id = $4 AND NOT (player_id = ANY($5))
+        ",
+        &[&season, &week, &league_id, &user, &players]
+    ).await?;
+
+    for player in players.iter() {
+        con.execute(
+            "
+            INSERT INTO lineups VALUES ($1, $2, $3, $4, $5, $6)
+            ON CONFLICT(season, week, league_id, player_id) DO UPDATE SET
+                user_id = EXCLUDED.user_id,
+                starter = EXCLUDED.starter
+            ",
+            &[&season, &week, &league_id, &user, player, &starters.contains(player)]
+        ).await?;
+    }
+
+    Ok(())
+}
+
+// Whoever has more points in a matchup, or None if it's tied
+// or either side has no points yet
+fn leader<'a>(user: &'a String, opponent: &'a String, points: &HashMap<String, f32>) -> Option<&'a String> {
+    let user_points = points.get(user)?;
+    let opponent_points = points.get(opponent)?;
+    if user_points > opponent_points {
+        Some(user)
+    } else if opponent_points > user_points {
+        Some(opponent)
+    } else {
+        None
+    }
+}
+
+// Looks for teams that moved on in the playoffs since the last
+// time the bracket was checked. The first check only records the
+// bracket so a restart doesn't announce every result again.
+pub async fn check_bracket(db_pool: &db::DBPool, config: &config::Config, events: &events::EventSender, previous: &mut Option<db::Bracket>) {
+
+    if config.bigleague.playoffs_at_large.is_none() {
+        return;
+    }
+
+    let con = match db::get_db_con(db_pool).await {
+        Ok(con) => con,
+        Err(e) => {
+            warn!("couldn't check bracket: {}", e);
+            return;
+        }
+    };
+
+    let leagues = match db::get_ranks(&con).await {
+        Ok(l) => l,
+        Err(e) => {
+            warn!("couldn't check bracket: {}", e);
+            return;
+        }
+    };
+
+    let bracket = match db::get_bracket(con, config.clone()).await {
+        Ok(b) => b,
+        Err(e) => {
+            warn!("couldn't check bracket: {}", e);
+            return;
+        }
+    };
+
+    if let Some(previous) = previous {
+        for (round, stage) in bracket.stages.iter().enumerate().skip(1) {
+            for team in stage {
+                let advanced_before = previous.stages
+                    .get(round)
+                    .is_some_and(|s| s.iter().any(|t| t.user.id == team.user.id));
+                if !advanced_before {
+                    events::publish(events, Event::BracketAdvance {
+                        league_id: leagues.get(&team.user.id).map(|(l, _)| l.clone()).unwrap_or_default(),
+                        week: team.week,
+                        user_id: team.user.id.clone(),
+                        rank: team.rank,
+                    });
+                }
+            }
+        }
+    }
+
+    *previous = Some(bracket);
+}
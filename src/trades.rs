@@ -0,0 +1,205 @@
+use serde::{Serialize, Deserialize};
+use utoipa::ToSchema;
+use std::collections::HashMap;
+
+use crate::db;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct TradedPlayer {
+    pub player: db::TransactionPlayer,
+    /// Scored while starting for the side that got them
+    pub points: f64,
+    pub starts: i64,
+}
+
+// Everything one user got out of a trade
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct TradeSide {
+    pub user_id: String,
+    pub user_name: Option<String>,
+    pub points: f64,
+    pub players: Vec<TradedPlayer>,
+    pub picks: Vec<db::TransactionPick>,
+    /// won, lost or even
+    pub result: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct Trade {
+    pub id: String,
+    pub league_id: String,
+    pub league_name: Option<String>,
+    pub season: i32,
+    pub week: i32,
+    #[schema(value_type = String, format = DateTime)]
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub sides: Vec<TradeSide>,
+}
+
+// Splits each trade into what every side got and how many points the
+// players they got have scored for them since. The side with the most
+// points won, and it's even when nobody is ahead. Draft picks don't
+// score so they aren't counted.
+pub fn analyze(transactions: Vec<db::Transaction>, points: &HashMap<(String, String), (f64, i64)>) -> Vec<Trade> {
+    transactions
+        .into_iter()
+        .filter(|t| t.kind == "trade")
+        .map(|t| {
+            let mut sides: Vec<TradeSide> = vec![];
+            let mut side = |user_id: &String, user_name: &Option<String>| -> usize {
+                match sides.iter().position(|s| &s.user_id == user_id) {
+                    Some(i) => i,
+                    None => {
+                        sides.push(TradeSide {
+                            user_id: user_id.clone(),
+                            user_name: user_name.clone(),
+                            points: 0.0,
+                            players: vec![],
+                            picks: vec![],
+                            result: String::new(),
+                        });
+                        sides.len() - 1
+                    },
+                }
+            };
+
+            // Anyone who only gave things away still has a side
+            let mut acquired = vec![];
+            for player in t.players {
+                let i = side(&player.user_id, &player.user_name);
+                if player.action == "add" {
+                    acquired.push((i, player));
+                }
+            }
+            let mut picks = vec![];
+            for pick in t.picks {
+                let i = side(&pick.user_id, &pick.user_name);
+                side(&pick.previous_user_id, &pick.previous_user_name);
+                picks.push((i, pick));
+            }
+
+            for (i, player) in acquired {
+                let (points, starts) = points
+                    .get(&(t.id.clone(), player.player_id.clone()))
+                    .copied()
+                    .unwrap_or((0.0, 0));
+                sides[i].points += points;
+                sides[i].players.push(TradedPlayer { player, points, starts });
+            }
+            for (i, pick) in picks {
+                sides[i].picks.push(pick);
+            }
+
+            let best = sides.iter().map(|s| s.points).fold(f64::NEG_INFINITY, f64::max);
+            let leaders = sides.iter().filter(|s| s.points == best).count();
+            for s in sides.iter_mut() {
+                s.result = String::from(match (s.points == best, leaders) {
+                    (true, 1) => "won",
+                    (true, _) => "even",
+                    (false, _) => "lost",
+                });
+            }
+
+            Trade {
+                id: t.id,
+                league_id: t.league_id,
+                league_name: t.league_name,
+                season: t.season,
+                week: t.week,
+                created_at: t.created_at,
+                sides,
+            }
+        })
+        .collect()
+}
+
+// A season's trades, newest first, narrowed down to a league or a user
+pub async fn get_trades(con: &db::DBCon, season: i32, league_id: Option<String>, user_id: Option<String>) -> Result<Vec<Trade>, tokio_postgres::Error> {
+    let filter = db::TransactionFilter {
+        league_id,
+        user_id,
+        season: Some(season),
+        kind: Some(String::from("trade")),
+        ..Default::default()
+    };
+    let transactions = db::get_transactions(con, &filter, None).await?;
+    let points = db::get_trade_points(con, season).await?;
+    Ok(analyze(transactions, &points))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{Transaction, TransactionPick, TransactionPlayer};
+    use crate::trades;
+    use chrono::Utc;
+    use std::collections::HashMap;
+
+    fn player(player_id: &str, action: &str, user_id: &str) -> TransactionPlayer {
+        TransactionPlayer {
+            player_id: player_id.to_string(),
+            first_name: None,
+            last_name: None,
+            position: None,
+            team: None,
+            action: action.to_string(),
+            user_id: user_id.to_string(),
+            user_name: None,
+        }
+    }
+
+    #[test]
+    fn test_analyze() {
+        let trade = Transaction {
+            id: String::from("t1"),
+            league_id: String::from("1"),
+            league_name: None,
+            season: 2023,
+            week: 4,
+            kind: String::from("trade"),
+            creator_id: None,
+            waiver_bid: None,
+            created_at: Utc::now(),
+            players: vec![
+                player("p1", "add", "a"),
+                player("p1", "drop", "b"),
+                player("p2", "add", "b"),
+                player("p3", "add", "b"),
+                player("p2", "drop", "a"),
+                player("p3", "drop", "a"),
+            ],
+            picks: vec![TransactionPick {
+                season: 2024,
+                round: 1,
+                original_user_id: String::from("c"),
+                original_user_name: None,
+                previous_user_id: String::from("b"),
+                previous_user_name: None,
+                user_id: String::from("c"),
+                user_name: None,
+            }],
+        };
+        let points = HashMap::from([
+            ((String::from("t1"), String::from("p1")), (40.0, 3)),
+            ((String::from("t1"), String::from("p2")), (25.0, 2)),
+            ((String::from("t1"), String::from("p3")), (15.0, 1)),
+        ]);
+
+        let analyzed = trades::analyze(vec![trade.clone()], &points);
+        let sides: Vec<(&str, f64, &str)> = analyzed[0].sides
+            .iter()
+            .map(|s| (s.user_id.as_str(), s.points, s.result.as_str()))
+            .collect();
+        assert_eq!(sides, vec![("a", 40.0, "even"), ("b", 40.0, "even"), ("c", 0.0, "lost")]);
+        assert_eq!(analyzed[0].sides[1].players.len(), 2);
+        assert_eq!(analyzed[0].sides[2].picks.len(), 1);
+
+        let mut gift = trade.clone();
+        gift.players.clear();
+        let analyzed = trades::analyze(vec![gift], &points);
+        let sides: Vec<(&str, &str)> = analyzed[0].sides.iter().map(|s| (s.user_id.as_str(), s.result.as_str())).collect();
+        assert_eq!(sides, vec![("c", "even"), ("b", "even")]);
+
+        let analyzed = trades::analyze(vec![trade], &HashMap::new());
+        assert!(analyzed[0].sides.iter().all(|s| s.result == "even"));
+    }
+}
@@ -0,0 +1,26 @@
+// Updates scores in place as they change during games.
+// Any element with the live-score class and matching
+// data-user and data-week attributes is updated.
+// Season totals with the live-total class add the week's
+// points to their data-points, since Sleeper only counts
+// a week in a roster's points once it's over.
+(function () {
+    if (!window.EventSource) {
+        return;
+    }
+
+    var source = new EventSource("/live");
+    source.addEventListener("score_change", function (e) {
+        var change = JSON.parse(e.data);
+        var selector = '.live-score[data-user="' + change.user_id + '"][data-week="' + change.week + '"]';
+        document.querySelectorAll(selector).forEach(function (el) {
+            el.textContent = change.points.toFixed(2);
+            el.classList.add("live-updated");
+        });
+        var totals = '.live-total[data-user="' + change.user_id + '"]';
+        document.querySelectorAll(totals).forEach(function (el) {
+            el.textContent = (parseFloat(el.dataset.points) + change.points).toFixed(2);
+            el.classList.add("live-updated");
+        });
+    });
+})();